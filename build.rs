@@ -0,0 +1,107 @@
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+
+// The platforms with their own `get_num_cpus()` in src/lib.rs. Building for
+// one of them with the catch-all returning `1` fails, so that a `target_os`
+// forgotten in one of the cfg lists there doesn't silently count 1 CPU.
+// Keep it in sync with src/lib.rs when adding a platform.
+const FIRST_CLASS: &'static [&'static str] = &[
+    "aix",
+    "android",
+    "cygwin",
+    "dragonfly",
+    "freebsd",
+    "fuchsia",
+    "haiku",
+    "hermit",
+    "horizon",
+    "illumos",
+    "ios",
+    "l4re",
+    "linux",
+    "macos",
+    "netbsd",
+    "nto",
+    "openbsd",
+    "solaris",
+    "teeos",
+    "trusty",
+    "tvos",
+    "uefi",
+    "wasi",
+    "watchos",
+    "windows",
+];
+
+// FreeBSD, and the targets derived from it which count their CPUs the same way,
+// such as console SDK targets with their own `target_os`. Such a target joins
+// here, and names the sysctl of the CPUs left to applications in its own
+// `app_cpus_sysctl()` of src/lib.rs.
+const FREEBSD_LIKE: &'static [&'static str] = &["freebsd"];
+
+// `NUM_CPUS_COMPILE_TIME=4 cargo build` bakes the count in, for targets whose
+// number of cores is known when building, so `get()` doesn't detect anything.
+fn main() {
+    println!("cargo:rerun-if-env-changed=NUM_CPUS_COMPILE_TIME");
+
+    // With the `compact` feature, some of them use the catch-all on purpose.
+    println!("cargo:rustc-check-cfg=cfg(num_cpus_first_class)");
+    let target_os = env::var("CARGO_CFG_TARGET_OS").unwrap_or(String::new());
+    if FIRST_CLASS.contains(&&target_os[..]) && env::var_os("CARGO_FEATURE_COMPACT").is_none() {
+        println!("cargo:rustc-cfg=num_cpus_first_class");
+    }
+
+    println!("cargo:rustc-check-cfg=cfg(num_cpus_freebsd_like)");
+    if FREEBSD_LIKE.contains(&&target_os[..]) {
+        println!("cargo:rustc-cfg=num_cpus_freebsd_like");
+    }
+
+    // `NonZeroUsize` is newer than the oldest Rust this crate supports, from
+    // 1.28, and src/nonzero.rs needs the tool lints of 1.31.
+    println!("cargo:rustc-check-cfg=cfg(num_cpus_nonzero)");
+    if rustc_minor().map_or(false, |minor| minor >= 31) {
+        println!("cargo:rustc-cfg=num_cpus_nonzero");
+    }
+
+    let cpus = match env::var("NUM_CPUS_COMPILE_TIME") {
+        Ok(value) => match value.trim().parse::<usize>() {
+            Ok(cpus) if cpus > 0 => Some(cpus),
+            _ => {
+                println!("cargo:warning=ignoring invalid NUM_CPUS_COMPILE_TIME={:?}", value);
+                None
+            }
+        },
+        Err(_) => None,
+    };
+
+    let out_dir = env::var("OUT_DIR").expect("OUT_DIR");
+    let mut file = File::create(Path::new(&out_dir).join("compile_time.rs"))
+        .expect("create compile_time.rs");
+    writeln!(file, "const COMPILE_TIME_CPUS: Option<usize> = {:?};", cpus)
+        .expect("write compile_time.rs");
+}
+
+// The `N` of `rustc 1.N.0`.
+fn rustc_minor() -> Option<u32> {
+    let rustc = env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+    let output = match Command::new(rustc).arg("--version").output() {
+        Ok(output) => output,
+        Err(_) => return None,
+    };
+    let version = match String::from_utf8(output.stdout) {
+        Ok(version) => version,
+        Err(_) => return None,
+    };
+
+    let mut numbers = match version.split(' ').nth(1) {
+        Some(numbers) => numbers.split('.'),
+        None => return None,
+    };
+    if numbers.next() != Some("1") {
+        return None;
+    }
+    numbers.next().and_then(|minor| minor.parse().ok())
+}
@@ -3,4 +3,12 @@ extern crate num_cpus;
 fn main() {
     println!("Logical CPUs: {}", num_cpus::get());
     println!("Physical CPUs: {}", num_cpus::get_physical());
+    match num_cpus::restriction_reason() {
+        Some(reason) => println!("Restricted by: {:?}", reason),
+        None => println!("Restricted by: nothing"),
+    }
+    match num_cpus::cgroup_path() {
+        Some(path) => println!("Cgroup: {}", path.display()),
+        None => println!("Cgroup: none"),
+    }
 }
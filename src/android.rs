@@ -0,0 +1,113 @@
+// Only the Android build uses these, but they're also built for the Linux
+// tests so that the fixtures are checked on CI.
+#![cfg_attr(not(target_os = "android"), allow(dead_code))]
+
+use std::fs::File;
+use std::io::Read;
+use std::mem;
+use std::path::Path;
+
+use libc;
+
+use cpu_list::parse_cpu_list;
+
+pub fn get_num_cpus() -> usize {
+    // Android moves apps between the cpusets mounted at /dev/cpuset as they go
+    // from the foreground to the background, which usually leaves background
+    // apps with only a couple of the little cores. That's why `get()` doesn't
+    // cache this count on Android, and reads the cpuset again on every call.
+    match cpuset_cpus("/proc/self/cpuset", "/dev/cpuset") {
+        Some(cpuset) => ::std::cmp::max(1, count_in_affinity(&cpuset, affinity_cpus())),
+        None => sysconf_cpus(),
+    }
+}
+
+/// Reads the CPUs of the cpuset group named in `cpuset_proc`, such as
+/// `/foreground`, from under the `dev_cpuset` mount.
+fn cpuset_cpus<P1, P2>(cpuset_proc: P1, dev_cpuset: P2) -> Option<Vec<usize>>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let cpus = read_file(cpuset_proc).and_then(|group| {
+        // The group is an absolute path, which would replace `dev_cpuset` when joined.
+        let group = group.trim();
+        let group = if group.starts_with('/') { &group[1..] } else { group };
+
+        read_file(dev_cpuset.as_ref().join(group).join("cpus"))
+    });
+
+    match cpus.map(|cpus| parse_cpu_list(&cpus)) {
+        Some(ref cpus) if cpus.is_empty() => None,
+        cpus => cpus,
+    }
+}
+
+fn count_in_affinity(cpuset: &[usize], affinity: Option<Vec<usize>>) -> usize {
+    match affinity {
+        Some(affinity) => cpuset.iter().filter(|cpu| affinity.contains(cpu)).count(),
+        None => cpuset.len(),
+    }
+}
+
+fn affinity_cpus() -> Option<Vec<usize>> {
+    let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+    let size = mem::size_of::<libc::cpu_set_t>();
+    if unsafe { libc::sched_getaffinity(0, size, &mut set) } != 0 {
+        return None;
+    }
+
+    // CPU_SETSIZE is a c_int in bionic's libc bindings on some targets and a
+    // usize on others, the size of the set in bits is the same everywhere.
+    Some((0..size * 8).filter(|&cpu| unsafe { libc::CPU_ISSET(cpu, &set) }).collect())
+}
+
+fn sysconf_cpus() -> usize {
+    // On ARM targets, processors could be turned off to save power.
+    // Use `_SC_NPROCESSORS_CONF` to get the real number.
+    let name = if cfg!(any(target_arch = "arm", target_arch = "aarch64")) {
+        libc::_SC_NPROCESSORS_CONF
+    } else {
+        libc::_SC_NPROCESSORS_ONLN
+    };
+
+    let cpus = unsafe { libc::sysconf(name) };
+    if cpus < 1 {
+        1
+    } else {
+        cpus as usize
+    }
+}
+
+fn read_file<P: AsRef<Path>>(path: P) -> Option<String> {
+    let mut buf = String::new();
+    match File::open(path).and_then(|mut file| file.read_to_string(&mut buf)) {
+        Ok(_) => Some(buf),
+        Err(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{count_in_affinity, cpuset_cpus};
+
+    static DEV_CPUSET: &'static str = "fixtures/android/dev/cpuset";
+
+    #[test]
+    fn test_cpuset_cpus() {
+        let cpus = |group: &str| cpuset_cpus(format!("fixtures/android/proc/cpuset_{}", group), DEV_CPUSET);
+
+        assert_eq!(cpus("foreground"), Some(vec![0, 1, 2, 3, 4, 5]));
+        assert_eq!(cpus("background"), Some(vec![0, 1]));
+        assert_eq!(cpus("root"), Some((0..8).collect()));
+        // no such group
+        assert_eq!(cpus("unknown"), None);
+        assert_eq!(cpus("missing"), None);
+    }
+
+    #[test]
+    fn test_count_in_affinity() {
+        assert_eq!(count_in_affinity(&[0, 1, 2, 3, 4, 5], None), 6);
+        assert_eq!(count_in_affinity(&[0, 1, 2, 3, 4, 5], Some(vec![4, 5, 6, 7])), 2);
+    }
+}
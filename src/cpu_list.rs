@@ -0,0 +1,69 @@
+/// The most CPUs we expect any machine to have.
+///
+/// This is far more than Linux supports (`CONFIG_NR_CPUS` is at most 8192),
+/// so anything larger is treated as bogus.
+pub const MAX_CPUS: usize = 1 << 20;
+
+/// Parses a kernel CPU list, such as `0-3,6,8-11`, into the CPUs it contains.
+///
+/// This is the format of `/sys/devices/system/cpu/online`, the cgroups
+/// `cpuset.cpus`, the NUMA node `cpulist`s and so on. Anything malformed
+/// results in an empty list.
+pub fn parse_cpu_list(list: &str) -> Vec<usize> {
+    let mut cpus = Vec::new();
+    let list = list.trim();
+    if list.is_empty() {
+        return cpus;
+    }
+
+    for part in list.split(',') {
+        let mut range = part.splitn(2, '-');
+        let start: usize = match range.next().and_then(|n| n.parse().ok()) {
+            Some(start) => start,
+            None => return Vec::new(),
+        };
+        let end: usize = match range.next() {
+            Some(end) => match end.parse().ok() {
+                Some(end) => end,
+                None => return Vec::new(),
+            },
+            None => start,
+        };
+        if end < start || end >= MAX_CPUS {
+            return Vec::new();
+        }
+        cpus.extend(start..end + 1);
+    }
+    cpus
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_cpu_list;
+
+    #[test]
+    fn test_parse_cpu_list() {
+        let empty: Vec<usize> = Vec::new();
+
+        assert_eq!(parse_cpu_list("0"), vec![0]);
+        assert_eq!(parse_cpu_list("5\n"), vec![5]);
+        assert_eq!(parse_cpu_list("0-3\n"), vec![0, 1, 2, 3]);
+        assert_eq!(parse_cpu_list("0-3,6,8-11"), vec![0, 1, 2, 3, 6, 8, 9, 10, 11]);
+        assert_eq!(parse_cpu_list("1,3,5"), vec![1, 3, 5]);
+        assert_eq!(parse_cpu_list("2-2"), vec![2]);
+        assert_eq!(parse_cpu_list(""), empty);
+        assert_eq!(parse_cpu_list("\n"), empty);
+
+        // malformed
+        assert_eq!(parse_cpu_list("3-0"), empty);
+        assert_eq!(parse_cpu_list("a-b"), empty);
+        assert_eq!(parse_cpu_list("0-"), empty);
+        assert_eq!(parse_cpu_list("-3"), empty);
+        assert_eq!(parse_cpu_list("0,,1"), empty);
+        assert_eq!(parse_cpu_list("0,1,"), empty);
+        assert_eq!(parse_cpu_list("0-3-5"), empty);
+        assert_eq!(parse_cpu_list("0 1"), empty);
+        assert_eq!(parse_cpu_list("0-18446744073709551615"), empty);
+        assert_eq!(parse_cpu_list("0-99999999999999999999"), empty);
+    }
+}
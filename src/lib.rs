@@ -24,8 +24,83 @@
 //! used when setting up parallel jobs (Where the threadpool example uses a fixed
 //! number 8, it could use the number of CPUs).
 //!
+//! ## Optional features
+//!
+//! - `tracing`: emits [`tracing`] debug events describing which files were read
+//!   and which source the count came from, which helps when a container reports
+//!   an unexpected number of CPUs. Disabled by default.
+//! - `no_affinity`: compiles out the [sched affinity] check on Linux, and the
+//!   cpuset check on FreeBSD, so the count is based on the online CPUs instead,
+//!   for programs managing their own pinning.
+//!   cgroups limits still apply. Disabled by default.
+//! - `env_override`: lets operators cap or bound [`get()`] without code changes, with
+//!   the `NUM_CPUS`, `NUM_CPUS_MIN` and `NUM_CPUS_MAX` environment variables, and
+//!   point it at the cgroup files with `CGROUP_ROOT`, such as in some rootless
+//!   containers. Disabled by default.
+//! - `compact`: compiles out the code of the platforms other than Linux (with
+//!   Android), macOS (with iOS) and Windows, for builds that only target those.
+//!   Other Unix platforms then use `sysconf(_SC_NPROCESSORS_ONLN)`, without
+//!   physical core detection, and the rest always report `1`. Disabled by default.
+//!
+//! ## Platform support
+//!
+//! How [`get()`] counts the CPUs, beyond the sched affinity and cgroups of Linux:
+//!
+//! - Linux: every cgroups limit applies, so the count is the lowest of them: a CPU
+//!   quota of 4 in a cpuset of 2 CPUs counts 2. That includes the quotas of the parent
+//!   cgroups, up to where the hierarchy is mounted.
+//! - Android: only the CPUs of the cpuset the app is in, such as `/foreground`, are
+//!   counted. Apps are moved to another cpuset when they go to the background, so this
+//!   count isn't cached, and is read again on every call.
+//! - OpenHarmony: counted like Linux, except that when the sched affinity can't be
+//!   read, ARM devices count their configured CPUs rather than the online ones, like
+//!   Android: idle cores are turned off to save power.
+//! - FreeBSD: only the CPUs of the process' cpuset, such as the ones a jail is pinned
+//!   to with `cpuset(1)`.
+//! - Windows: the active processors of every processor group, not only the at most 64
+//!   of the group the current thread runs in.
+//! - macOS, iOS, tvOS and watchOS: `sysconf`. On ARM, which is every iOS, tvOS and
+//!   watchOS device and Apple silicon Macs, it's the configured CPUs
+//!   (`_SC_NPROCESSORS_CONF`), as idle cores may be powered down, and elsewhere the
+//!   online ones. [`get_physical()`] is the `hw.physicalcpu` sysctl on all of them.
+//! - Cygwin (`x86_64-pc-cygwin`): `sysconf(_SC_NPROCESSORS_ONLN)` of its POSIX layer
+//!   rather than the Windows APIs. MinGW targets such as `x86_64-pc-windows-gnu` are
+//!   Windows, not Cygwin, and count like the other Windows targets.
+//! - WASI: the number of threads the host allows the module to run when built for the
+//!   threads proposal (such as `wasm32-wasip1-threads`), rather than the number of
+//!   hardware cores, and `1` otherwise.
+//! - L4Re: always `1`, see the comment of its implementation.
+//!
+//! ## Overrides
+//!
+//! The detected count of [`get()`] can be replaced, from the highest precedence:
+//!
+//! - If the `NUM_CPUS_COMPILE_TIME` environment variable was set to a positive integer
+//!   when building this crate, that constant is returned, without detecting anything
+//!   at runtime.
+//! - With the `env_override` feature, the count is clamped to the `NUM_CPUS_MIN` and
+//!   `NUM_CPUS_MAX` environment variables, when they are set to a positive integer. If
+//!   `NUM_CPUS_MIN` is larger than `NUM_CPUS_MAX`, the maximum wins.
+//! - With the `env_override` feature, a `NUM_CPUS` environment variable set to a
+//!   positive integer caps the count, such as for a CI job or an orchestrator which
+//!   wants less parallelism without code changes. It's clamped to the detected count:
+//!   the precedence is `NUM_CPUS`, then the cgroups limits, then the sched affinity,
+//!   each of which can only lower the count. A malformed value is ignored, with a
+//!   `debug!` event of the `tracing` feature.
+//! - With the `env_override` feature on Linux, a `CGROUP_ROOT` variable replaces where
+//!   the cgroup filesystem is mounted, for sandboxes where `/proc/self/mountinfo`
+//!   doesn't lead to it: the cgroup of `/proc/self/cgroup` is looked up under it, or
+//!   `CGROUP_ROOT` itself is used.
+//! - A function set with [`set_detector()`] replaces the detection itself, the
+//!   `env_override` bounds still apply to what it returns.
+//!
 //! [processor tricks]: https://en.wikipedia.org/wiki/Simultaneous_multithreading
 //! [`rayon::ThreadPool`]: https://docs.rs/rayon/1.*/rayon/struct.ThreadPool.html
+//! [`tracing`]: https://docs.rs/tracing
+//! [`get()`]: fn.get.html
+//! [`get_physical()`]: fn.get_physical.html
+//! [`set_detector()`]: fn.set_detector.html
+//! [sched affinity]: http://www.gnu.org/software/libc/manual/html_node/CPU-Affinity.html
 #![cfg_attr(test, deny(warnings))]
 #![deny(missing_docs)]
 #![allow(non_snake_case)]
@@ -33,13 +108,77 @@
 #[cfg(not(windows))]
 extern crate libc;
 
-#[cfg(target_os = "hermit")]
+#[cfg(all(target_os = "hermit", not(feature = "compact")))]
 extern crate hermit_abi;
 
+#[cfg(feature = "tracing")]
+extern crate tracing;
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Once;
+use std::time::{Duration, Instant};
+
+// Defines `COMPILE_TIME_CPUS`, see build.rs.
+include!(concat!(env!("OUT_DIR"), "/compile_time.rs"));
+
+// Defined before the modules, so that they can use it too.
+#[cfg(any(target_os = "linux", feature = "env_override"))]
+macro_rules! debug {
+    ($($args:expr),*) => ({
+        #[cfg(feature = "tracing")]
+        ::tracing::debug!($($args),*);
+
+        if false {
+        //if true {
+            println!($($args),*);
+        }
+    });
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod cpu_list;
+// Included rather than `mod nonzero;`, so that an older rustc doesn't parse it.
+#[cfg(num_cpus_nonzero)]
+mod nonzero {
+    include!("nonzero.rs");
+}
+#[cfg(num_cpus_nonzero)]
+pub use nonzero::{get_nonzero, get_physical_nonzero};
 #[cfg(target_os = "linux")]
 mod linux;
 #[cfg(target_os = "linux")]
-use linux::{get_num_cpus, get_num_physical_cpus};
+use linux::{
+    get_affinity_cpus, get_affinity_mask, get_allowed_cpus_per_node, get_cgroup_cpu_quota,
+    get_cgroup_cpus_at, get_cgroup_path, get_cgroup_version, get_cores_per_socket, get_cpu_info,
+    get_cpu_states, get_effective_cpus_estimate, get_full_topology, get_hybrid_counts,
+    get_logical_cpus_per_node, get_max_threads, get_nohz_full_cpus, get_num_cpus,
+    get_num_cpus_excluding, get_num_cpus_quota_as_hint, get_num_cpus_with_source,
+    get_num_hardware_cpus, get_num_physical_cpus, get_num_physical_cpus_with_source,
+    get_num_schedulable_cpus, get_online_live_cpus, get_physical_disagreement,
+    get_restriction_reason, get_threads_per_core_detailed, get_topology, refresh_num_cpus,
+    try_num_cpus, try_num_physical_cpus,
+};
+#[cfg(any(target_os = "android", all(target_os = "linux", test)))]
+mod android;
+#[cfg(target_os = "android")]
+use android::get_num_cpus;
+#[cfg(windows)]
+mod windows;
+#[cfg(windows)]
+use windows::{
+    get_hybrid_counts, get_num_cpus, get_num_hardware_cpus, get_num_physical_cpus,
+    get_num_physical_cpus_with_source, get_topology, try_num_physical_cpus,
+};
+#[cfg(all(target_os = "nto", not(feature = "compact")))]
+mod qnx;
+#[cfg(all(target_os = "nto", not(feature = "compact")))]
+use qnx::get_num_physical_cpus_probe;
+#[cfg(all(any(target_os = "solaris", target_os = "illumos"), not(feature = "compact")))]
+mod solaris;
+#[cfg(all(any(target_os = "solaris", target_os = "illumos"), not(feature = "compact")))]
+use solaris::get_num_physical_cpus_probe;
 
 /// Returns the number of available CPUs of the current system.
 ///
@@ -65,331 +204,2396 @@ use linux::{get_num_cpus, get_num_physical_cpus};
 /// thread does not have access to all the computer's CPUs.
 ///
 /// This will also check [cgroups], frequently used in containers to constrain CPU usage.
+/// The cgroups limits are only read on the first call, see [`refresh()`] to read them again.
+///
+/// The count is detected on the first call and then cached, so later calls only read an
+/// atomic. It may be stale: if the affinity of a thread changes, or if the first call was
+/// made from a thread pinned to fewer CPUs, `get()` keeps returning the first count. Call
+/// [`get_uncached()`] to detect it again, from the current thread.
+///
+/// How the other platforms count is described in the [platform support] section of the
+/// crate documentation, and how the count can be replaced in its [overrides] section.
 ///
 /// [smt]: https://en.wikipedia.org/wiki/Simultaneous_multithreading
 /// [sched affinity]: http://www.gnu.org/software/libc/manual/html_node/CPU-Affinity.html
 /// [cgroups]: https://www.kernel.org/doc/Documentation/cgroup-v1/cgroups.txt
+/// [`refresh()`]: fn.refresh.html
+/// [`get_uncached()`]: fn.get_uncached.html
+/// [platform support]: index.html#platform-support
+/// [overrides]: index.html#overrides
 #[inline]
 pub fn get() -> usize {
-    get_num_cpus()
+    match try_get() {
+        Ok(cpus) => cpus,
+        Err(_) => env_bounds(1),
+    }
 }
 
-/// Returns the number of physical cores of the current system.
-///
-/// This will always return at least `1`.
-///
-/// # Note
+/// Returns the number of available CPUs, or why they couldn't be counted.
 ///
-/// Physical count is supported only on Linux, mac OS and Windows platforms.
-/// On other platforms, or if the physical count fails on supported platforms,
-/// this function returns the same as [`get()`], which is the number of logical
-/// CPUS.
+/// This is [`get()`], except that where it would fall back to `1` because the
+/// detection failed, this returns the [`Error`], so that a server can at least
+/// log why it's running a single worker. On Linux, that's when neither the sched
+/// affinity, `sysconf` nor `/proc` could be read. On platforms this crate
+/// doesn't know it's [`Error::Unsupported`], and elsewhere this is the same as
+/// [`get()`].
 ///
 /// # Examples
 ///
 /// ```
-/// let logical_cpus = num_cpus::get();
-/// let physical_cpus = num_cpus::get_physical();
-/// if logical_cpus > physical_cpus {
-///     println!("We have simultaneous multithreading with about {:.2} \
-///               logical cores to 1 physical core.", 
-///               (logical_cpus as f64) / (physical_cpus as f64));
-/// } else if logical_cpus == physical_cpus {
-///     println!("Either we don't have simultaneous multithreading, or our \
-///               system doesn't support getting the number of physical CPUs.");
-/// } else {
-///     println!("We have less logical CPUs than physical CPUs, maybe we only have access to \
-///               some of the CPUs on our system.");
-/// }
+/// let cpus = match num_cpus::try_get() {
+///     Ok(cpus) => cpus,
+///     Err(err) => {
+///         println!("can't count the CPUs, using 1: {}", err);
+///         1
+///     }
+/// };
+/// assert!(cpus >= 1);
 /// ```
 ///
 /// [`get()`]: fn.get.html
-#[inline]
-pub fn get_physical() -> usize {
-    get_num_physical_cpus()
-}
-
-
-#[cfg(not(any(
-    target_os = "linux",
-    target_os = "windows",
-    target_os = "macos",
-    target_os = "openbsd",
-    target_os = "aix")))]
-#[inline]
-fn get_num_physical_cpus() -> usize {
-    // Not implemented, fall back
-    get_num_cpus()
-}
-
-#[cfg(target_os = "windows")]
-fn get_num_physical_cpus() -> usize {
-    match get_num_physical_cpus_windows() {
-        Some(num) => num,
-        None => get_num_cpus()
+/// [`Error`]: enum.Error.html
+/// [`Error::Unsupported`]: enum.Error.html#variant.Unsupported
+pub fn try_get() -> Result<usize, Error> {
+    match COMPILE_TIME_CPUS {
+        Some(cpus) => Ok(cpus),
+        None => cached_cpus(&CACHED_CPUS, &DETECTOR),
     }
 }
 
-#[cfg(target_os = "windows")]
-fn get_num_physical_cpus_windows() -> Option<usize> {
-    // Inspired by https://msdn.microsoft.com/en-us/library/ms683194
-
-    use std::ptr;
-    use std::mem;
-
-    #[allow(non_upper_case_globals)]
-    const RelationProcessorCore: u32 = 0;
+/// Returns the number of available CPUs, detecting them again.
+///
+/// This is [`get()`] without its cache: the sched affinity of the current thread,
+/// and the other sources of the platform, are read on every call. It's for callers
+/// which changed the affinity, such as a thread pool pinning its workers, and want
+/// the count as it is now. It doesn't update what [`get()`] returns, see
+/// [`refresh()`] for that.
+///
+/// # Examples
+///
+/// ```
+/// let cpus = num_cpus::get_uncached();
+/// assert!(cpus >= 1);
+/// ```
+///
+/// [`get()`]: fn.get.html
+/// [`refresh()`]: fn.refresh.html
+pub fn get_uncached() -> usize {
+    let cpus = match COMPILE_TIME_CPUS {
+        Some(cpus) => Ok(cpus),
+        None => uncached_cpus(&DETECTOR),
+    };
+    cpus.unwrap_or_else(|_| env_bounds(1))
+}
 
-    #[repr(C)]
-    #[allow(non_camel_case_types)]
-    struct SYSTEM_LOGICAL_PROCESSOR_INFORMATION {
-        mask: usize,
-        relationship: u32,
-        _unused: [u64; 2]
-    }
+// The result of `get()`, 0 until it's first detected.
+// Allow deprecation warnings, ATOMIC_USIZE_INIT is needed by older rustc.
+#[allow(warnings)]
+static CACHED_CPUS: AtomicUsize = ::std::sync::atomic::ATOMIC_USIZE_INIT;
 
-    extern "system" {
-        fn GetLogicalProcessorInformation(
-            info: *mut SYSTEM_LOGICAL_PROCESSOR_INFORMATION,
-            length: &mut u32
-        ) -> u32;
+fn cached_cpus(cache: &AtomicUsize, detector: &AtomicUsize) -> Result<usize, Error> {
+    // The function of `set_detector()` is documented to be called every time,
+    // and Android moves apps to another cpuset whenever they go to the background.
+    if cfg!(target_os = "android") || load_detector(detector).is_some() || hooks::overridden() {
+        return uncached_cpus(detector);
     }
-
-    // First we need to determine how much space to reserve.
-
-    // The required size of the buffer, in bytes.
-    let mut needed_size = 0;
-
-    unsafe {
-        GetLogicalProcessorInformation(ptr::null_mut(), &mut needed_size);
+    match cache.load(Ordering::Acquire) {
+        0 => {
+            let cpus = uncached_cpus(detector);
+            // Failures aren't cached, so that a later call can still succeed.
+            if let Ok(cpus) = cpus {
+                cache.store(cpus, Ordering::Release);
+            }
+            cpus
+        }
+        cpus => Ok(cpus),
     }
+}
 
-    let struct_size = mem::size_of::<SYSTEM_LOGICAL_PROCESSOR_INFORMATION>() as u32;
+fn uncached_cpus(detector: &AtomicUsize) -> Result<usize, Error> {
+    detected_cpus(detector).map(|cpus| env_bounds(clamp_min_one(cpus)))
+}
 
-    // Could be 0, or some other bogus size.
-    if needed_size == 0 || needed_size < struct_size || needed_size % struct_size != 0 {
-        return None;
+/// Returns the number of physical cores, or why they couldn't be counted.
+///
+/// This is [`try_physical()`], with the reason instead of `None`: where
+/// [`get_physical()`] falls back to the logical CPUs, this returns the [`Error`].
+/// It's [`Error::Unsupported`] on platforms without physical core detection.
+///
+/// # Examples
+///
+/// ```
+/// match num_cpus::try_get_physical() {
+///     Ok(cores) => println!("{} physical cores", cores),
+///     Err(err) => println!("physical cores unknown: {}", err),
+/// }
+/// ```
+///
+/// [`try_physical()`]: fn.try_physical.html
+/// [`get_physical()`]: fn.get_physical.html
+/// [`Error`]: enum.Error.html
+/// [`Error::Unsupported`]: enum.Error.html#variant.Unsupported
+pub fn try_get_physical() -> Result<usize, Error> {
+    match try_physical() {
+        Some(cores) => Ok(cores),
+        None => Err(PHYSICAL_ERROR),
     }
+}
 
-    let count = needed_size / struct_size;
-
-    // Allocate some memory where we will store the processor info.
-    let mut buf = Vec::with_capacity(count as usize);
-
-    let result;
-
-    unsafe {
-        result = GetLogicalProcessorInformation(buf.as_mut_ptr(), &mut needed_size);
-    }
+/// Why [`try_get()`](fn.try_get.html) or
+/// [`try_get_physical()`](fn.try_get_physical.html) couldn't count the CPUs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Error {
+    /// A system call failed, with this `errno`, such as `sysconf` in a sandbox
+    /// which also hides `/proc`.
+    Os(i32),
+    /// The platform didn't describe the CPUs in a way that could be parsed, such
+    /// as a `/proc/cpuinfo` and `/sys/devices/system/cpu` without a topology.
+    Parse,
+    /// This platform has no way to count them.
+    Unsupported,
+}
 
-    // Failed for any reason.
-    if result == 0 {
-        return None;
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Os(errno) => write!(f, "system call failed with errno {}", errno),
+            Error::Parse => f.write_str("CPU information couldn't be parsed"),
+            Error::Unsupported => f.write_str("unsupported platform"),
+        }
     }
+}
 
-    let count = needed_size / struct_size;
-
-    unsafe {
-        buf.set_len(count as usize);
+impl ::std::error::Error for Error {
+    #[allow(deprecated)]
+    fn description(&self) -> &str {
+        match *self {
+            Error::Os(_) => "system call failed",
+            Error::Parse => "CPU information couldn't be parsed",
+            Error::Unsupported => "unsupported platform",
+        }
     }
+}
 
-    let phys_proc_count = buf.iter()
-        // Only interested in processor packages (physical processors.)
-        .filter(|proc_info| proc_info.relationship == RelationProcessorCore)
-        .count();
+#[cfg(feature = "env_override")]
+fn env_bounds(cpus: usize) -> usize {
+    let cpus = cap_cpus(cpus, std::env::var("NUM_CPUS").ok());
+    clamp_to_bounds(cpus, env_bound("NUM_CPUS_MIN"), env_bound("NUM_CPUS_MAX"))
+}
 
-    if phys_proc_count == 0 {
-        None
-    } else {
-        Some(phys_proc_count)
+// `NUM_CPUS` can only lower the detected count, unlike `NUM_CPUS_MIN`.
+#[cfg(feature = "env_override")]
+fn cap_cpus(cpus: usize, value: Option<String>) -> usize {
+    let value = match value {
+        Some(value) => value,
+        None => return cpus,
+    };
+    match value.trim().parse() {
+        Ok(0) | Err(_) => {
+            debug!("ignoring invalid NUM_CPUS={:?}", value);
+            cpus
+        }
+        Ok(cap) => std::cmp::min(cap, cpus),
     }
 }
 
-#[cfg(windows)]
-fn get_num_cpus() -> usize {
-    #[repr(C)]
-    struct SYSTEM_INFO {
-        wProcessorArchitecture: u16,
-        wReserved: u16,
-        dwPageSize: u32,
-        lpMinimumApplicationAddress: *mut u8,
-        lpMaximumApplicationAddress: *mut u8,
-        dwActiveProcessorMask: *mut u8,
-        dwNumberOfProcessors: u32,
-        dwProcessorType: u32,
-        dwAllocationGranularity: u32,
-        wProcessorLevel: u16,
-        wProcessorRevision: u16,
-    }
+#[cfg(not(feature = "env_override"))]
+#[inline]
+fn env_bounds(cpus: usize) -> usize {
+    cpus
+}
 
-    extern "system" {
-        fn GetSystemInfo(lpSystemInfo: *mut SYSTEM_INFO);
-    }
+// Unset, empty, zero or otherwise invalid values are ignored.
+#[cfg(feature = "env_override")]
+fn env_bound(name: &str) -> Option<usize> {
+    parse_bound(std::env::var(name).ok())
+}
 
-    unsafe {
-        let mut sysinfo: SYSTEM_INFO = std::mem::zeroed();
-        GetSystemInfo(&mut sysinfo);
-        sysinfo.dwNumberOfProcessors as usize
+#[cfg(feature = "env_override")]
+fn parse_bound(value: Option<String>) -> Option<usize> {
+    match value.and_then(|value| value.trim().parse().ok()) {
+        Some(0) | None => None,
+        bound => bound,
     }
 }
 
-#[cfg(any(target_os = "freebsd",
-          target_os = "dragonfly",
-          target_os = "netbsd"))]
-fn get_num_cpus() -> usize {
-    use std::ptr;
-
-    let mut cpus: libc::c_uint = 0;
-    let mut cpus_size = std::mem::size_of_val(&cpus);
-
-    unsafe {
-        cpus = libc::sysconf(libc::_SC_NPROCESSORS_ONLN) as libc::c_uint;
-    }
-    if cpus < 1 {
-        let mut mib = [libc::CTL_HW, libc::HW_NCPU, 0, 0];
-        unsafe {
-            libc::sysctl(mib.as_mut_ptr(),
-                         2,
-                         &mut cpus as *mut _ as *mut _,
-                         &mut cpus_size as *mut _ as *mut _,
-                         ptr::null_mut(),
-                         0);
-        }
-        if cpus < 1 {
-            cpus = 1;
-        }
+#[cfg(feature = "env_override")]
+fn clamp_to_bounds(cpus: usize, min: Option<usize>, max: Option<usize>) -> usize {
+    let cpus = match min {
+        Some(min) => std::cmp::max(cpus, min),
+        None => cpus,
+    };
+    match max {
+        Some(max) => std::cmp::min(cpus, max),
+        None => cpus,
     }
-    cpus as usize
 }
 
-#[cfg(target_os = "openbsd")]
-fn get_num_cpus() -> usize {
-    use std::ptr;
-
-    let mut cpus: libc::c_uint = 0;
-    let mut cpus_size = std::mem::size_of_val(&cpus);
-    let mut mib = [libc::CTL_HW, libc::HW_NCPUONLINE, 0, 0];
-    let rc: libc::c_int;
+/// Where the count returned by [`get_with_source()`](fn.get_with_source.html)
+/// came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LogicalSource {
+    /// The CPU quota of the cgroup, on Linux.
+    CgroupQuota,
+    /// The [sched affinity] of the current thread, on Linux.
+    ///
+    /// [sched affinity]: http://www.gnu.org/software/libc/manual/html_node/CPU-Affinity.html
+    Affinity,
+    /// The online CPUs, from `sysconf(_SC_NPROCESSORS_ONLN)` or `/proc/stat`, on
+    /// Linux, because the sched affinity couldn't be read.
+    Sysconf {
+        /// `sched_getaffinity` failed with `EPERM` or `ENOSYS`, as when a seccomp
+        /// filter blocks it, such as in gVisor. The count may then be higher than
+        /// the CPUs the process can actually run on.
+        affinity_blocked: bool,
+    },
+    /// The platform's own API, on platforms other than Linux.
+    Platform,
+    /// `NUM_CPUS_COMPILE_TIME`, the function set with
+    /// [`set_detector()`](fn.set_detector.html), or the `env_override` variables,
+    /// which changed the detected count.
+    Override,
+}
 
-    unsafe {
-        rc = libc::sysctl(mib.as_mut_ptr(),
-                          2,
-                          &mut cpus as *mut _ as *mut _,
-                          &mut cpus_size as *mut _ as *mut _,
-                          ptr::null_mut(),
-                          0);
+/// Returns the same number as [`get()`], along with where it came from.
+///
+/// This is meant for diagnostics, such as explaining a surprising count in a
+/// sandbox: on Linux, a [`LogicalSource::Sysconf`] with `affinity_blocked` means
+/// the sched affinity was refused rather than unrestricted. Unlike [`get()`],
+/// the count isn't cached, it's detected again on every call.
+///
+/// # Examples
+///
+/// ```
+/// let (cpus, source) = num_cpus::get_with_source();
+/// println!("{} CPUs, from {:?}", cpus, source);
+/// ```
+///
+/// [`get()`]: fn.get.html
+/// [`LogicalSource::Sysconf`]: enum.LogicalSource.html#variant.Sysconf
+pub fn get_with_source() -> (usize, LogicalSource) {
+    if let Some(cpus) = COMPILE_TIME_CPUS {
+        return (cpus, LogicalSource::Override);
     }
-    if rc < 0 {
-        cpus = 1;
+    if let Some(detector) = load_detector(&DETECTOR) {
+        return (env_bounds(clamp_min_one(detector())), LogicalSource::Override);
     }
-    cpus as usize
-}
-
-#[cfg(target_os = "openbsd")]
-fn get_num_physical_cpus() -> usize {
-    use std::ptr;
-
-    let mut cpus: libc::c_uint = 0;
-    let mut cpus_size = std::mem::size_of_val(&cpus);
-    let mut mib = [libc::CTL_HW, libc::HW_NCPU, 0, 0];
-    let rc: libc::c_int;
 
-    unsafe {
-        rc = libc::sysctl(mib.as_mut_ptr(),
-                          2,
-                          &mut cpus as *mut _ as *mut _,
-                          &mut cpus_size as *mut _ as *mut _,
-                          ptr::null_mut(),
-                          0);
-    }
-    if rc < 0 {
-        cpus = 1;
+    let (cpus, source) = get_num_cpus_with_source();
+    let cpus = clamp_min_one(cpus);
+    let bounded = env_bounds(cpus);
+    if bounded != cpus {
+        (bounded, LogicalSource::Override)
+    } else {
+        (cpus, source)
     }
-    cpus as usize
 }
 
+#[cfg(not(target_os = "linux"))]
+fn get_num_cpus_with_source() -> (usize, LogicalSource) {
+    (get_num_cpus(), LogicalSource::Platform)
+}
 
-#[cfg(target_os = "macos")]
-fn get_num_physical_cpus() -> usize {
-    use std::ffi::CStr;
-    use std::ptr;
-
-    let mut cpus: i32 = 0;
-    let mut cpus_size = std::mem::size_of_val(&cpus);
-
-    let sysctl_name = CStr::from_bytes_with_nul(b"hw.physicalcpu\0")
-        .expect("byte literal is missing NUL");
+// The address of the `fn() -> usize` set with `set_detector()`, 0 until then.
+// Allow deprecation warnings, ATOMIC_USIZE_INIT is needed by older rustc.
+#[allow(warnings)]
+static DETECTOR: AtomicUsize = ::std::sync::atomic::ATOMIC_USIZE_INIT;
 
-    unsafe {
-        if 0 != libc::sysctlbyname(sysctl_name.as_ptr(),
-                                   &mut cpus as *mut _ as *mut _,
-                                   &mut cpus_size as *mut _ as *mut _,
-                                   ptr::null_mut(),
-                                   0) {
-            return get_num_cpus();
-        }
-    }
-    cpus as usize
+/// Makes [`get()`] count the CPUs with `detector`, instead of detecting them.
+///
+/// This is an advanced hook for custom and embedded operating systems this crate
+/// doesn't know, where [`get()`] would otherwise return `1`: the application, which
+/// knows how to ask its scheduler, can provide the count. It's a plain function
+/// pointer kept in an atomic, so nothing is allocated or locked.
+///
+/// It's called on every call to [`get()`], bypassing its cache, and should be cheap. It applies to the
+/// whole process, from any thread, and the last call wins. `NUM_CPUS_COMPILE_TIME`
+/// still takes precedence, and the `env_override` bounds still apply. The other
+/// functions of this crate, such as [`get_physical()`], keep their own detection.
+///
+/// # Examples
+///
+/// ```
+/// fn scheduler_cpus() -> usize {
+///     // ask the RTOS
+///     4
+/// }
+///
+/// num_cpus::set_detector(scheduler_cpus);
+/// # if option_env!("NUM_CPUS_COMPILE_TIME").is_none() {
+/// assert_eq!(num_cpus::get(), 4);
+/// # }
+/// ```
+///
+/// [`get()`]: fn.get.html
+/// [`get_physical()`]: fn.get_physical.html
+#[inline]
+pub fn set_detector(detector: fn() -> usize) {
+    store_detector(&DETECTOR, detector)
 }
 
-#[cfg(target_os = "aix")]
-fn get_num_physical_cpus() -> usize {
-    match get_smt_threads_aix() {
-        Some(num) => get_num_cpus() / num,
-        None => get_num_cpus(),
+fn detected_cpus(detector: &AtomicUsize) -> Result<usize, Error> {
+    match load_detector(detector) {
+        Some(detector) => Ok(detector()),
+        None => hooks::try_num_cpus(),
     }
 }
 
-#[cfg(target_os = "aix")]
-fn get_smt_threads_aix() -> Option<usize> {
-    let smt = unsafe {
-        libc::getsystemcfg(libc::SC_SMT_TC)
-    };
-    if smt == u64::MAX {
-        return None;
+fn load_detector(detector: &AtomicUsize) -> Option<fn() -> usize> {
+    match detector.load(Ordering::Acquire) {
+        0 => None,
+        // A function pointer is never null, and is what was stored.
+        addr => Some(unsafe { ::std::mem::transmute::<usize, fn() -> usize>(addr) }),
     }
-    Some(smt as usize)
 }
 
-#[cfg(any(
-    target_os = "macos",
-    target_os = "ios",
-    target_os = "android",
-    target_os = "aix",
-    target_os = "solaris",
-    target_os = "illumos",
-    target_os = "fuchsia")
-)]
-fn get_num_cpus() -> usize {
-    // On ARM targets, processors could be turned off to save power.
-    // Use `_SC_NPROCESSORS_CONF` to get the real number.
-    #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
-    const CONF_NAME: libc::c_int = libc::_SC_NPROCESSORS_CONF;
-    #[cfg(not(any(target_arch = "arm", target_arch = "aarch64")))]
-    const CONF_NAME: libc::c_int = libc::_SC_NPROCESSORS_ONLN;
-
-    let cpus = unsafe { libc::sysconf(CONF_NAME) };
-    if cpus < 1 {
-        1
-    } else {
-        cpus as usize
-    }
+fn store_detector(detector: &AtomicUsize, f: fn() -> usize) {
+    detector.store(f as usize, Ordering::Release);
 }
 
-#[cfg(target_os = "haiku")]
-fn get_num_cpus() -> usize {
-    use std::mem;
-
-    #[allow(non_camel_case_types)]
-    type bigtime_t = i64;
-    #[allow(non_camel_case_types)]
-    type status_t = i32;
-
-    #[repr(C)]
-    pub struct system_info {
-        pub boot_time: bigtime_t,
-        pub cpu_count: u32,
-        pub max_pages: u64,
+/// Returns the number of physical cores of the current system.
+///
+/// This will always return at least `1`.
+///
+/// # Note
+///
+/// Physical count is supported only on Linux, macOS, iOS, FreeBSD, OpenBSD, AIX,
+/// QNX (when its system page tells SMT threads apart), Solaris, illumos and Windows
+/// platforms. On Solaris and illumos, the cores are the distinct `core_id`s of each
+/// chip in the `cpu_info` kstats of the online CPUs; without kstat, such as in a
+/// zone that doesn't expose it, this is the same as [`get()`].
+/// On other platforms, or if the physical count fails on supported platforms,
+/// this function returns the same as [`get()`], which is the number of logical
+/// CPUS.
+///
+/// # Examples
+///
+/// ```
+/// let logical_cpus = num_cpus::get();
+/// let physical_cpus = num_cpus::get_physical();
+/// if logical_cpus > physical_cpus {
+///     println!("We have simultaneous multithreading with about {:.2} \
+///               logical cores to 1 physical core.", 
+///               (logical_cpus as f64) / (physical_cpus as f64));
+/// } else if logical_cpus == physical_cpus {
+///     println!("Either we don't have simultaneous multithreading, or our \
+///               system doesn't support getting the number of physical CPUs.");
+/// } else {
+///     println!("We have less logical CPUs than physical CPUs, maybe we only have access to \
+///               some of the CPUs on our system.");
+/// }
+/// ```
+///
+/// Use [`try_physical()`] to tell these fallbacks apart from a system without
+/// simultaneous multithreading.
+///
+/// On Linux, the cores are read from `/proc/cpuinfo` and `/sys/devices/system/cpu`.
+/// When they disagree, the sysfs topology is used, as it's what the kernel schedules
+/// on, see [`physical_disagreement()`]. In a chroot where neither is mounted, this
+/// falls back to the logical count, while [`get()`] keeps working: the sched affinity
+/// is a syscall. Under gVisor, neither has a topology, so this is the logical count too.
+/// Virtual machines usually don't report SMT, see [`smt_enabled()`], so each vCPU counts
+/// as a core, even when it's one thread of a host core.
+/// Firecracker and other microVMs run a guest kernel reporting the topology of their
+/// vCPUs, unless it's built without SMP support, which again leaves the logical count.
+/// Only online CPUs count, so with SMT turned off at runtime, a 6 core and 12 thread
+/// chip has 6 physical and 6 logical CPUs, and [`smt_enabled()`] is `false`.
+///
+/// [`get()`]: fn.get.html
+/// [`try_physical()`]: fn.try_physical.html
+/// [`physical_disagreement()`]: fn.physical_disagreement.html
+/// [`smt_enabled()`]: fn.smt_enabled.html
+#[inline]
+pub fn get_physical() -> usize {
+    clamp_min_one(hooks::num_physical_cpus())
+}
+
+/// Returns the number of physical cores of the current system, if it could be
+/// determined.
+///
+/// Unlike [`get_physical()`], this doesn't fall back to the number of logical
+/// CPUs: it returns `None` on platforms without physical core detection, or if
+/// the detection failed. A `Some` equal to [`get()`] means there really is no
+/// simultaneous multithreading.
+///
+/// # Examples
+///
+/// ```
+/// match num_cpus::try_physical() {
+///     Some(physical) => println!("{} physical cores", physical),
+///     None => println!("physical cores unknown"),
+/// }
+/// ```
+///
+/// [`get()`]: fn.get.html
+/// [`get_physical()`]: fn.get_physical.html
+#[inline]
+pub fn try_physical() -> Option<usize> {
+    try_num_physical_cpus()
+}
+
+/// Where the number of physical cores returned by
+/// [`get_physical_with_source()`](fn.get_physical_with_source.html) came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Source {
+    /// The `physical id` and `cpu cores` of `/proc/cpuinfo`, on Linux.
+    Cpuinfo,
+    /// The core siblings in `/sys/devices/system/cpu`, on Linux.
+    Sysfs,
+    /// A sysctl, such as `hw.physicalcpu` on macOS or `kern.smp.cores` on FreeBSD.
+    Sysctl,
+    /// `GetLogicalProcessorInformation(Ex)`, on Windows.
+    ProcessorInformation,
+    /// Another API of the platform, such as the system page on QNX.
+    Platform,
+    /// The physical cores are unknown, this is the number of logical CPUs.
+    Logical,
+}
+
+/// Returns the same number as [`get_physical()`], along with where it came from.
+///
+/// This is meant for diagnostics, such as telling a system without simultaneous
+/// multithreading apart from one where [`Source::Logical`] was the only option.
+/// There's no CPUID based detection, so `/proc` or `/sys` need to be mounted on
+/// Linux for anything else than [`Source::Logical`].
+///
+/// # Examples
+///
+/// ```
+/// let (physical, source) = num_cpus::get_physical_with_source();
+/// println!("{} physical cores, from {:?}", physical, source);
+/// ```
+///
+/// [`get_physical()`]: fn.get_physical.html
+/// [`Source::Logical`]: enum.Source.html#variant.Logical
+#[inline]
+pub fn get_physical_with_source() -> (usize, Source) {
+    let (num, source) = get_num_physical_cpus_with_source();
+    (clamp_min_one(num), source)
+}
+
+/// Returns the physical cores counted from `/proc/cpuinfo` and from
+/// `/sys/devices/system/cpu`, as `(cpuinfo, sysfs)`, if they disagree.
+///
+/// Firmware bugs and some hypervisors report a `cpu cores` in `/proc/cpuinfo`
+/// that doesn't match the thread siblings in sysfs. [`get_physical()`] uses the
+/// sysfs count then, which is considered the more reliable one. This is meant
+/// for diagnostics, and is always `None` on platforms other than Linux, or if
+/// either count is unknown.
+///
+/// # Examples
+///
+/// ```
+/// if let Some((cpuinfo, sysfs)) = num_cpus::physical_disagreement() {
+///     println!("/proc/cpuinfo says {} cores, sysfs {}", cpuinfo, sysfs);
+/// }
+/// ```
+///
+/// [`get_physical()`]: fn.get_physical.html
+#[inline]
+pub fn physical_disagreement() -> Option<(usize, usize)> {
+    get_physical_disagreement()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn get_physical_disagreement() -> Option<(usize, usize)> {
+    None
+}
+
+/// Returns the number of physical cores of each socket of the current system.
+///
+/// On Linux, the cores are grouped by the `physical_package_id` of
+/// `/sys/devices/system/cpu/cpu*/topology`, ordered by that id. Sockets don't
+/// always have the same number of cores, so its length is the number of sockets,
+/// and its sum is usually [`get_physical()`].
+///
+/// On other platforms, or if the topology can't be read, this returns a single
+/// socket with [`get_physical()`] cores.
+///
+/// # Examples
+///
+/// ```
+/// let sockets = num_cpus::cores_per_socket();
+/// for (socket, cores) in sockets.iter().enumerate() {
+///     println!("socket {} has {} cores", socket, cores);
+/// }
+/// ```
+///
+/// [`get_physical()`]: fn.get_physical.html
+#[inline]
+pub fn cores_per_socket() -> Vec<usize> {
+    get_cores_per_socket()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn get_cores_per_socket() -> Vec<usize> {
+    vec![get_num_physical_cpus()]
+}
+
+/// Returns the number of logical CPUs, or hardware threads, of each physical core.
+///
+/// Hybrid parts often mix cores with and without simultaneous multithreading,
+/// such as Intel's P-cores with 2 threads and E-cores with 1, which the ratio of
+/// [`get()`] to [`get_physical()`] averages out.
+///
+/// On Linux, these are the sizes of the groups of
+/// `/sys/devices/system/cpu/cpu*/topology/thread_siblings_list`, ordered by the
+/// first CPU of each core, so its length is the number of cores. On other
+/// platforms, or if the topology can't be read, every core gets the same share
+/// of [`get()`].
+///
+/// # Examples
+///
+/// ```
+/// let threads = num_cpus::threads_per_core_detailed();
+/// if threads.iter().any(|&n| n != threads[0]) {
+///     println!("not every core has the same number of threads");
+/// }
+/// ```
+///
+/// [`get()`]: fn.get.html
+/// [`get_physical()`]: fn.get_physical.html
+#[inline]
+pub fn threads_per_core_detailed() -> Vec<usize> {
+    get_threads_per_core_detailed()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn get_threads_per_core_detailed() -> Vec<usize> {
+    uniform_threads_per_core()
+}
+
+/// Returns whether any core runs more than one thread, with simultaneous
+/// multithreading (Intel's Hyper-Threading).
+///
+/// This is from [`threads_per_core_detailed()`]. Virtual machines often present
+/// each vCPU as a core of its own, or even a socket of its own, in which case this
+/// is `false` even if the host has SMT, and [`get_physical()`] is the same as
+/// [`get()`]. On Linux, it's `false` as well when SMT is turned off at runtime, with
+/// `nosmt` or `/sys/devices/system/cpu/smt/control`, since the offline threads of
+/// a core don't count.
+///
+/// # Examples
+///
+/// ```
+/// if num_cpus::smt_enabled() {
+///     println!("{} cores, {} threads", num_cpus::get_physical(), num_cpus::get());
+/// }
+/// ```
+///
+/// [`get()`]: fn.get.html
+/// [`get_physical()`]: fn.get_physical.html
+/// [`threads_per_core_detailed()`]: fn.threads_per_core_detailed.html
+#[inline]
+pub fn smt_enabled() -> bool {
+    has_smt(&threads_per_core_detailed())
+}
+
+fn has_smt(threads_per_core: &[usize]) -> bool {
+    threads_per_core.iter().any(|&threads| threads > 1)
+}
+
+fn uniform_threads_per_core() -> Vec<usize> {
+    let physical = clamp_min_one(get_num_physical_cpus());
+    vec![clamp_min_one(get_num_cpus() / physical); physical]
+}
+
+/// Returns the number of logical CPUs of each NUMA node of the current system.
+///
+/// On Linux, these are the CPUs listed in `/sys/devices/system/node/node*/cpulist`,
+/// ordered by node id. Like [`cpu_states()`], this doesn't take sched affinity or
+/// cgroups into account, and it includes the CPUs which are offline: its sum is
+/// the number of possible CPUs. A node with memory but no CPUs counts `0`.
+///
+/// On other platforms, or without NUMA support in the kernel, this returns a
+/// single node with [`get()`] CPUs.
+///
+/// # Examples
+///
+/// ```
+/// for (node, cpus) in num_cpus::logical_cpus_per_node().iter().enumerate() {
+///     println!("node {} has {} CPUs", node, cpus);
+/// }
+/// ```
+///
+/// [`cpu_states()`]: fn.cpu_states.html
+/// [`get()`]: fn.get.html
+#[inline]
+pub fn logical_cpus_per_node() -> Vec<usize> {
+    get_logical_cpus_per_node()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn get_logical_cpus_per_node() -> Vec<usize> {
+    vec![get()]
+}
+
+/// Returns the number of logical CPUs of the smallest NUMA node, capped by [`get()`].
+///
+/// This is a conservative bound on the parallelism of a pool pinned to a single
+/// node, whichever node it ends up on, for latency critical work that shouldn't
+/// cross nodes. Nodes with memory but no CPUs are skipped. It will always return
+/// at least `1`.
+///
+/// On systems with a single node, and on platforms other than Linux, this is the
+/// same as [`get()`].
+///
+/// # Examples
+///
+/// ```
+/// let per_node = num_cpus::min_node_cpus();
+/// assert!(per_node >= 1 && per_node <= num_cpus::get());
+/// ```
+///
+/// [`get()`]: fn.get.html
+#[inline]
+pub fn min_node_cpus() -> usize {
+    smallest_node(&logical_cpus_per_node(), get())
+}
+
+fn smallest_node(per_node: &[usize], cpus: usize) -> usize {
+    let smallest = per_node.iter().cloned().filter(|&n| n > 0).min().unwrap_or(cpus);
+    clamp_min_one(std::cmp::min(smallest, cpus))
+}
+
+/// Recomputes and returns the number of available CPUs of the current system.
+///
+/// [`get()`] caches its count, and the cgroups limits are only read once. If the
+/// process may be moved to another cgroup, or its limits or [sched affinity] changed
+/// at runtime, this re-reads them, updates what later calls to [`get()`] return, and
+/// returns the new count.
+///
+/// On platforms without any other cached state, this is the same as
+/// [`get_uncached()`], except that it also updates the cache of [`get()`]. A
+/// detector set with [`set_detector()`] is still called instead.
+///
+/// # Examples
+///
+/// ```
+/// let cpus = num_cpus::refresh();
+/// assert_eq!(cpus, num_cpus::get());
+/// ```
+///
+/// [`get()`]: fn.get.html
+/// [`get_uncached()`]: fn.get_uncached.html
+/// [`set_detector()`]: fn.set_detector.html
+/// [sched affinity]: http://www.gnu.org/software/libc/manual/html_node/CPU-Affinity.html
+#[inline]
+pub fn refresh() -> usize {
+    // There's nothing to re-read when the count was fixed at compile time.
+    if let Some(cpus) = COMPILE_TIME_CPUS {
+        return cpus;
+    }
+    refreshed_cpus(&CACHED_CPUS, &DETECTOR)
+}
+
+fn refreshed_cpus(cache: &AtomicUsize, detector: &AtomicUsize) -> usize {
+    // Only called for the limits it re-reads: the count is then detected as
+    // `get()` does, so that a detector set with `set_detector()` still wins.
+    refresh_num_cpus();
+    cache.store(0, Ordering::Release);
+    cached_cpus(cache, detector).unwrap_or_else(|_| env_bounds(1))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn refresh_num_cpus() -> usize {
+    get_num_cpus()
+}
+
+/// Returns [`refresh()`], reusing its last result until it's older than `ttl`.
+///
+/// This is for long-lived servers which want to notice a new cgroup quota or
+/// cpuset eventually, without paying for [`refresh()`] on every call. Within the
+/// `ttl`, a call only reads the clock and two atomics. When the cached count is
+/// stale, the call that notices recomputes it, and threads racing with it may
+/// recompute it too.
+///
+/// The cache is shared by the whole process, whatever `ttl` each caller passes.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+///
+/// let cpus = num_cpus::get_cached_with_ttl(Duration::from_secs(5));
+/// assert!(cpus >= 1);
+/// ```
+///
+/// [`refresh()`]: fn.refresh.html
+pub fn get_cached_with_ttl(ttl: Duration) -> usize {
+    TTL_CACHE.get(millis(since_epoch()), millis(ttl), refresh)
+}
+
+// Allow deprecation warnings, ATOMIC_USIZE_INIT is needed by older rustc.
+#[allow(warnings)]
+static TTL_CACHE: TtlCache = TtlCache {
+    cpus: ::std::sync::atomic::ATOMIC_USIZE_INIT,
+    at: ::std::sync::atomic::ATOMIC_USIZE_INIT,
+};
+
+struct TtlCache {
+    // 0 until the first count.
+    cpus: AtomicUsize,
+    // The milliseconds since `since_epoch()` started when `cpus` was counted.
+    at: AtomicUsize,
+}
+
+impl TtlCache {
+    fn get(&self, now: usize, ttl: usize, count: fn() -> usize) -> usize {
+        let at = self.at.load(Ordering::Acquire);
+        let cpus = self.cpus.load(Ordering::Acquire);
+        // Wrapping, so a 32-bit `usize` of milliseconds still works past 49 days.
+        if cpus != 0 && now.wrapping_sub(at) < ttl {
+            return cpus;
+        }
+
+        let cpus = count();
+        self.cpus.store(cpus, Ordering::Release);
+        self.at.store(now, Ordering::Release);
+        cpus
+    }
+}
+
+fn since_epoch() -> Duration {
+    // Allow deprecation warnings, ONCE_INIT is needed by older rustc.
+    #[allow(warnings)]
+    static ONCE: Once = ::std::sync::ONCE_INIT;
+    static mut EPOCH: Option<Instant> = None;
+
+    unsafe {
+        ONCE.call_once(|| EPOCH = Some(Instant::now()));
+        EPOCH.expect("initialized by call_once").elapsed()
+    }
+}
+
+fn millis(duration: Duration) -> usize {
+    let millis = duration
+        .as_secs()
+        .saturating_mul(1000)
+        .saturating_add(u64::from(duration.subsec_nanos()) / 1_000_000);
+    if millis > ::std::usize::MAX as u64 {
+        ::std::usize::MAX
+    } else {
+        millis as usize
+    }
+}
+
+/// Returns the number of CPUs the current thread can actually be scheduled on.
+///
+/// On Linux, this is the intersection of the thread's [sched affinity], the CPUs of
+/// the cgroup's cpuset (`cpuset.cpus.effective`), leaving out any CPU in
+/// `/sys/devices/system/cpu/isolated`, such as the ones set aside for device interrupts
+/// or accelerators. Unlike [`get()`], it doesn't take cgroup CPU quotas into account.
+///
+/// This will always return at least `1`. On other platforms, this is the same as [`get()`].
+///
+/// # Examples
+///
+/// ```
+/// let cpus = num_cpus::get_schedulable();
+/// assert!(cpus >= 1);
+/// ```
+///
+/// [`get()`]: fn.get.html
+/// [sched affinity]: http://www.gnu.org/software/libc/manual/html_node/CPU-Affinity.html
+#[inline]
+pub fn get_schedulable() -> usize {
+    get_num_schedulable_cpus()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn get_num_schedulable_cpus() -> usize {
+    get_num_cpus()
+}
+
+/// Returns the CPUs in `/sys/devices/system/cpu/nohz_full`.
+///
+/// These run without the scheduler tick when they have a single task, and are
+/// usually set aside with the `nohz_full=` kernel parameter for latency sensitive
+/// work, such as realtime or trading, rather than general worker threads. See
+/// [`Builder::exclude_nohz_full()`] to leave them out of the count.
+///
+/// This is empty when the kernel wasn't booted with `nohz_full=`, and always on
+/// platforms other than Linux.
+///
+/// # Examples
+///
+/// ```
+/// let tickless = num_cpus::nohz_full_cpus();
+/// println!("{} tickless CPUs", tickless.len());
+/// ```
+///
+/// [`Builder::exclude_nohz_full()`]: struct.Builder.html#method.exclude_nohz_full
+#[inline]
+pub fn nohz_full_cpus() -> Vec<usize> {
+    get_nohz_full_cpus()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn get_nohz_full_cpus() -> Vec<usize> {
+    Vec::new()
+}
+
+/// Returns the ids of the logical CPUs the current thread may run on, from its
+/// sched affinity.
+///
+/// These are the kernel's CPU numbers, the ones `sched_setaffinity` and cpusets
+/// use, in increasing order. They aren't a dense `0..n` range: a cpuset or a
+/// `taskset` can leave any CPU out, and offline CPUs leave gaps. On Linux, the
+/// `CPU_SETSIZE` CPUs of a `cpu_set_t` are checked, and if the affinity can't be
+/// read, or with the `no_affinity` feature, this is `0..n` of the logical CPUs.
+///
+/// On other platforms, this is `0..get()`.
+///
+/// # Examples
+///
+/// ```
+/// let cpus = num_cpus::get_affinity();
+/// println!("may run on CPUs {:?}", cpus);
+/// assert!(!cpus.is_empty());
+/// ```
+#[inline]
+pub fn get_affinity() -> Vec<usize> {
+    get_affinity_cpus()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn get_affinity_cpus() -> Vec<usize> {
+    (0..get()).collect()
+}
+
+/// Returns the current thread's sched affinity mask, as 64-bit words.
+///
+/// CPU `i` is bit `i % 64` of word `i / 64`, the same layout as the `cpu_set_t`
+/// passed to `sched_setaffinity`. On Linux there's a word for each 64 of the
+/// `CPU_SETSIZE` CPUs, and if the affinity can't be read, the first of the
+/// logical CPUs are set.
+///
+/// On other platforms, this has the first [`get()`] CPUs set, usually in a
+/// single word.
+///
+/// # Examples
+///
+/// ```
+/// let mask = num_cpus::affinity_mask();
+/// let cpu0 = mask[0] & 1 != 0;
+/// println!("may run on CPU 0: {}", cpu0);
+/// ```
+///
+/// [`get()`]: fn.get.html
+#[inline]
+pub fn affinity_mask() -> Vec<u64> {
+    get_affinity_mask()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn get_affinity_mask() -> Vec<u64> {
+    first_cpus_mask(get())
+}
+
+fn first_cpus_mask(cpus: usize) -> Vec<u64> {
+    let mut words = vec![!0u64; cpus / 64];
+    if cpus % 64 != 0 || words.is_empty() {
+        words.push((1 << (cpus % 64)) - 1);
+    }
+    words
+}
+
+/// Returns the most threads the current process may have, from the `Max
+/// processes` soft limit in `/proc/self/limits`.
+///
+/// This isn't a count of CPUs, but a thread pool sized from [`get()`] won't
+/// start if `RLIMIT_NPROC` is lower, since spawning a thread past it fails. The
+/// limit is per user, so threads of other processes count against it too.
+///
+/// Returns `None` when the limit is `unlimited`, and on platforms other than
+/// Linux.
+///
+/// # Examples
+///
+/// ```
+/// let mut threads = num_cpus::get();
+/// if let Some(max) = num_cpus::max_threads() {
+///     threads = std::cmp::min(threads, max);
+/// }
+/// ```
+///
+/// [`get()`]: fn.get.html
+#[inline]
+pub fn max_threads() -> Option<usize> {
+    get_max_threads()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn get_max_threads() -> Option<usize> {
+    None
+}
+
+/// Counts the available CPUs with options [`get()`] doesn't have.
+///
+/// # Examples
+///
+/// ```
+/// use num_cpus::Rounding;
+///
+/// let cpus = num_cpus::Builder::new()
+///     .exclude_nohz_full(true)
+///     .rounding(Rounding::Nearest(0.5))
+///     .get();
+/// assert!(cpus >= 1 && cpus <= num_cpus::get());
+/// ```
+///
+/// [`get()`]: fn.get.html
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Builder {
+    exclude_nohz_full: bool,
+    rounding: Rounding,
+    cgroup_as_hint: bool,
+}
+
+impl Builder {
+    /// Creates a `Builder` counting the same CPUs as [`get()`](fn.get.html).
+    pub fn new() -> Builder {
+        Builder::default()
+    }
+
+    /// Leaves the [`nohz_full_cpus()`](fn.nohz_full_cpus.html) out of the count.
+    ///
+    /// Only the tickless CPUs the current thread can run on make a difference,
+    /// and a CPU quota lower than the remaining ones still applies.
+    pub fn exclude_nohz_full(mut self, exclude: bool) -> Builder {
+        self.exclude_nohz_full = exclude;
+        self
+    }
+
+    /// Sets how a fractional [`cgroup_cpu_quota()`](fn.cgroup_cpu_quota.html)
+    /// is counted, [`Rounding::Ceil`](enum.Rounding.html) by default.
+    pub fn rounding(mut self, rounding: Rounding) -> Builder {
+        self.rounding = rounding;
+        self
+    }
+
+    /// Doesn't cap the count to the cgroup CPU quota, only to the affinity and
+    /// cpuset, when set to `true`.
+    ///
+    /// The quota is a budget of CPU time per period, which a bursty workload
+    /// can spend on more CPUs than it allows for a short while. Such a
+    /// workload can get the quota from [`cgroup_cpu_quota()`] instead.
+    ///
+    /// [`cgroup_cpu_quota()`]: fn.cgroup_cpu_quota.html
+    pub fn cgroup_as_hint(mut self, as_hint: bool) -> Builder {
+        self.cgroup_as_hint = as_hint;
+        self
+    }
+
+    /// Returns the number of available CPUs. This will always return at least `1`.
+    pub fn get(&self) -> usize {
+        let mut cpus = if !self.cgroup_as_hint {
+            get()
+        } else {
+            match COMPILE_TIME_CPUS {
+                Some(cpus) => cpus,
+                None => env_bounds(clamp_min_one(get_num_cpus_quota_as_hint())),
+            }
+        };
+        // Without a cap, there's no quota to round either.
+        if let (false, Rounding::Nearest(_)) = (self.cgroup_as_hint, self.rounding) {
+            if let Some(quota) = get_cgroup_cpu_quota() {
+                cpus = std::cmp::min(cpus, round_quota(quota, self.rounding));
+            }
+        }
+        if self.exclude_nohz_full {
+            cpus = get_num_cpus_excluding(cpus, &get_nohz_full_cpus());
+        }
+        clamp_min_one(cpus)
+    }
+}
+
+/// How [`Builder`] counts a CPU quota which isn't a whole number of CPUs.
+///
+/// A Kubernetes pod with a limit of `2050m` gets 2.05 CPUs. Rounding up to 3
+/// threads gets it throttled, while a limit of `2950m` rounded down to 2 leaves
+/// most of a CPU unused.
+///
+/// [`Builder`]: struct.Builder.html
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rounding {
+    /// Rounds any fractional part up, as [`get()`](fn.get.html) does.
+    Ceil,
+    /// Rounds up when the fractional part is at least the threshold, and down
+    /// otherwise. `Nearest(0.5)` counts 2.05 CPUs as 2 and 2.5 as 3, while
+    /// `Nearest(0.0)` is the same as `Ceil`, and above `1.0` it always rounds down.
+    Nearest(f64),
+}
+
+impl Default for Rounding {
+    fn default() -> Rounding {
+        Rounding::Ceil
+    }
+}
+
+fn round_quota(quota: f64, rounding: Rounding) -> usize {
+    let threshold = match rounding {
+        Rounding::Ceil => 0.0,
+        Rounding::Nearest(threshold) => threshold,
+    };
+
+    let whole = quota.floor();
+    let fraction = quota - whole;
+    if fraction > 0.0 && fraction >= threshold {
+        whole as usize + 1
+    } else {
+        whole as usize
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn get_num_cpus_quota_as_hint() -> usize {
+    get_num_cpus()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn get_num_cpus_excluding(cpus: usize, _excluded: &[usize]) -> usize {
+    cpus
+}
+
+/// Everything needed to set up a thread pool, from [`worker_pool_hint()`].
+///
+/// [`worker_pool_hint()`]: fn.worker_pool_hint.html
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct WorkerPoolHint {
+    /// The threads for CPU bound work, as [`get()`](fn.get.html) returns.
+    pub worker_threads: usize,
+    /// Four times `worker_threads`, a starting point for threads spending most
+    /// of their time blocked on files or the network.
+    pub blocking_threads: usize,
+    /// The CPUs of `pin_candidates` grouped by NUMA node, in node order. Nodes
+    /// without any of them are left out, and without NUMA support, there is a
+    /// single group.
+    pub numa_groups: Vec<Vec<usize>>,
+    /// The ids of the CPUs the current thread can be pinned to, in order. On
+    /// Linux, these are the CPUs [`get_schedulable()`](fn.get_schedulable.html)
+    /// counts, elsewhere they are `0` up to the number of CPUs.
+    pub pin_candidates: Vec<usize>,
+}
+
+/// Returns a [`WorkerPoolHint`] describing how to size and lay out a thread pool.
+///
+/// This is meant to configure an executor such as tokio or rayon in one call.
+/// There may be fewer `worker_threads` than `pin_candidates`, when a cgroup
+/// quota allows less time than the CPUs the process may run on.
+///
+/// # Examples
+///
+/// ```
+/// let hint = num_cpus::worker_pool_hint();
+/// for (node, cpus) in hint.numa_groups.iter().enumerate() {
+///     println!("group {}: CPUs {:?}", node, cpus);
+/// }
+/// println!("{} workers, {} blocking threads", hint.worker_threads, hint.blocking_threads);
+/// ```
+///
+/// [`WorkerPoolHint`]: struct.WorkerPoolHint.html
+pub fn worker_pool_hint() -> WorkerPoolHint {
+    let worker_threads = get();
+    let numa_groups = get_allowed_cpus_per_node();
+
+    let mut pin_candidates: Vec<usize> = numa_groups
+        .iter()
+        .flat_map(|cpus| cpus.iter().cloned())
+        .collect();
+    pin_candidates.sort();
+
+    WorkerPoolHint {
+        worker_threads: worker_threads,
+        blocking_threads: worker_threads.saturating_mul(4),
+        numa_groups: numa_groups,
+        pin_candidates: pin_candidates,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn get_allowed_cpus_per_node() -> Vec<Vec<usize>> {
+    vec![(0..get_num_cpus()).collect()]
+}
+
+// 0 until set. Allow deprecation warnings, ATOMIC_USIZE_INIT is needed by older rustc.
+#[allow(warnings)]
+static GLOBAL_HINT: AtomicUsize = ::std::sync::atomic::ATOMIC_USIZE_INIT;
+
+/// Returns the process-wide number of worker threads, as set by [`set_global_hint()`].
+///
+/// This gives an application a single place to decide how many workers to run,
+/// which libraries setting up their own pools, such as a tokio runtime and a rayon
+/// pool, can all read instead of each calling [`get()`]. It's only advisory,
+/// nothing in this crate is limited by it.
+///
+/// Until it's set, this is [`get()`].
+///
+/// # Examples
+///
+/// ```
+/// let workers = num_cpus::global_hint();
+/// assert!(workers >= 1);
+/// ```
+///
+/// [`get()`]: fn.get.html
+/// [`set_global_hint()`]: fn.set_global_hint.html
+#[inline]
+pub fn global_hint() -> usize {
+    load_hint(&GLOBAL_HINT)
+}
+
+/// Sets the number of worker threads [`global_hint()`] returns, for the whole process.
+///
+/// This is meant to be called once, early at startup, such as from a command line
+/// option. Only the first call with a `workers` above `0` takes effect, and returns
+/// `true`: later ones are ignored and return `false`, so that a library can't change
+/// the count the application decided on.
+///
+/// # Examples
+///
+/// ```
+/// if num_cpus::set_global_hint(4) {
+///     assert_eq!(num_cpus::global_hint(), 4);
+/// }
+/// ```
+///
+/// [`global_hint()`]: fn.global_hint.html
+#[inline]
+pub fn set_global_hint(workers: usize) -> bool {
+    store_hint(&GLOBAL_HINT, workers)
+}
+
+fn load_hint(hint: &AtomicUsize) -> usize {
+    match hint.load(Ordering::Acquire) {
+        0 => get(),
+        workers => workers,
+    }
+}
+
+fn store_hint(hint: &AtomicUsize, workers: usize) -> bool {
+    if workers == 0 {
+        return false;
+    }
+    hint.compare_exchange(0, workers, Ordering::AcqRel, Ordering::Acquire).is_ok()
+}
+
+/// A mechanism limiting the CPUs available to the current process.
+///
+/// See [`restriction_reason()`](fn.restriction_reason.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Restriction {
+    /// The [sched affinity] of the current thread, as set by `taskset`.
+    ///
+    /// [sched affinity]: http://www.gnu.org/software/libc/manual/html_node/CPU-Affinity.html
+    Affinity,
+    /// The CPU quota of the cgroup, as set by `docker run --cpus`.
+    CgroupQuota,
+    /// The CPUs of the cgroup's cpuset, as set by `docker run --cpuset-cpus`.
+    Cpuset,
+}
+
+/// Returns what limits [`get()`] below the number of online CPUs, if anything.
+///
+/// This is meant for diagnostics, such as explaining why a container sees fewer
+/// CPUs than the host. When several limits apply, the one giving the lowest count
+/// is returned. The cpuset also narrows the affinity, so a thread that wasn't
+/// restricted further within its cpuset reports [`Restriction::Cpuset`].
+///
+/// This is always `None` on platforms other than Linux.
+///
+/// # Examples
+///
+/// ```
+/// if let Some(reason) = num_cpus::restriction_reason() {
+///     println!("limited to {} CPUs by {:?}", num_cpus::get(), reason);
+/// }
+/// ```
+///
+/// [`get()`]: fn.get.html
+/// [`Restriction::Cpuset`]: enum.Restriction.html#variant.Cpuset
+#[inline]
+pub fn restriction_reason() -> Option<Restriction> {
+    get_restriction_reason()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn get_restriction_reason() -> Option<Restriction> {
+    None
+}
+
+/// Returns the number of available CPUs, minus `reserve` CPUs kept free for other work.
+///
+/// This is useful when sizing a thread pool that shares the machine with a main
+/// thread or an async runtime. Like [`get()`], this will always return at least `1`,
+/// even if `reserve` is as large as the number of CPUs.
+///
+/// # Examples
+///
+/// ```
+/// // Leave a CPU for the main thread.
+/// let workers = num_cpus::get_reserved(1);
+/// assert!(workers >= 1);
+/// ```
+///
+/// [`get()`]: fn.get.html
+#[inline]
+pub fn get_reserved(reserve: usize) -> usize {
+    reserve_cpus(get(), reserve)
+}
+
+fn reserve_cpus(cpus: usize, reserve: usize) -> usize {
+    clamp_min_one(cpus.saturating_sub(reserve))
+}
+
+// A bogus sysctl or a weird VM can report 0 CPUs, so this is applied to whatever
+// the platform functions return, instead of checking in each of them.
+#[inline]
+fn clamp_min_one(cpus: usize) -> usize {
+    std::cmp::max(1, cpus)
+}
+
+/// Returns the number of online CPUs, read again on every call.
+///
+/// CPUs can be brought offline and back online while a process runs, such as
+/// when a cloud VM is resized. On Linux, this reads `/sys/devices/system/cpu/online`
+/// each time, so a scheduler polling it notices the change. Like
+/// [`cpu_states()`], it doesn't take sched affinity or cgroups into account.
+///
+/// Opening and parsing a file on every call is far slower than [`get()`], so only
+/// use this where CPU hotplug is expected, and not on a hot path.
+///
+/// On other platforms, this is the same as [`get()`].
+///
+/// # Examples
+///
+/// ```
+/// let online = num_cpus::get_online_live();
+/// assert!(online >= 1);
+/// ```
+///
+/// [`cpu_states()`]: fn.cpu_states.html
+/// [`get()`]: fn.get.html
+#[inline]
+pub fn get_online_live() -> usize {
+    get_online_live_cpus()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn get_online_live_cpus() -> usize {
+    get_num_cpus()
+}
+
+/// Returns the number of logical CPUs installed in the machine, ignoring every limit.
+///
+/// This is the "what's installed" count for inventory and monitoring, in sharp
+/// contrast with [`get()`]: sched affinity, cgroups quotas and cpusets, the
+/// `no_affinity` and `env_override` features and `NUM_CPUS_COMPILE_TIME` are all
+/// ignored, and CPUs which are installed but offline are counted too. Don't use it
+/// to size a thread pool, it can be many times what the process is allowed to use.
+///
+/// On Linux, this is the count of `/sys/devices/system/cpu/present`, falling back to
+/// `sysconf(_SC_NPROCESSORS_CONF)`. On Windows, it's the active processors of all
+/// processor groups, like [`get()`]. On other Unix systems, it's `sysconf(_SC_NPROCESSORS_CONF)`, and
+/// elsewhere the same as [`get()`].
+///
+/// # Examples
+///
+/// ```
+/// let installed = num_cpus::get_hardware();
+/// let available = num_cpus::get();
+/// println!("using {} of the {} installed CPUs", available, installed);
+/// ```
+///
+/// [`get()`]: fn.get.html
+#[inline]
+pub fn get_hardware() -> usize {
+    clamp_min_one(get_num_hardware_cpus())
+}
+
+/// Returns both [`get()`] and [`get_hardware()`], as `(available, installed)`.
+///
+/// On a machine without limits the two are the same, unless some CPUs are
+/// offline. In a container, or with a restricted affinity, the first is lower.
+///
+/// # Examples
+///
+/// ```
+/// let (available, installed) = num_cpus::available_and_installed();
+/// println!("using {} of {} CPUs", available, installed);
+/// ```
+///
+/// [`get()`]: fn.get.html
+/// [`get_hardware()`]: fn.get_hardware.html
+#[inline]
+pub fn available_and_installed() -> (usize, usize) {
+    (get(), get_hardware())
+}
+
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "tvos",
+    target_os = "watchos",
+    target_os = "android",
+    all(not(feature = "compact"), any(
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "solaris",
+        target_os = "illumos",
+        target_os = "cygwin"))))]
+fn get_num_hardware_cpus() -> usize {
+    let cpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_CONF) };
+    if cpus < 1 {
+        get_num_cpus()
+    } else {
+        cpus as usize
+    }
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    windows,
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "tvos",
+    target_os = "watchos",
+    target_os = "android",
+    all(not(feature = "compact"), any(
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "solaris",
+        target_os = "illumos",
+        target_os = "cygwin")))))]
+fn get_num_hardware_cpus() -> usize {
+    get_num_cpus()
+}
+
+/// Returns `sysconf(_SC_NPROCESSORS_CONF)`, the number of logical CPUs configured.
+///
+/// [`get()`] counts the online CPUs, so the two are the same unless some CPUs are
+/// offline. On ARM devices, idle cores are often powered down and come back when
+/// there's work, so this is the capacity of the machine rather than what's
+/// available right now. Like [`get_hardware()`], it ignores sched affinity and
+/// cgroups.
+///
+/// On platforms without `_SC_NPROCESSORS_CONF`, or if it fails, this is the same
+/// as [`get()`].
+///
+/// # Examples
+///
+/// ```
+/// let configured = num_cpus::get_conf();
+/// let online = num_cpus::get();
+/// println!("{} of the {} configured CPUs available", online, configured);
+/// ```
+///
+/// [`get()`]: fn.get.html
+/// [`get_hardware()`]: fn.get_hardware.html
+#[inline]
+pub fn get_conf() -> usize {
+    get_num_conf_cpus()
+}
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "tvos",
+    target_os = "watchos",
+    all(not(feature = "compact"), any(
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "solaris",
+        target_os = "illumos",
+        target_os = "aix",
+        target_os = "nto",
+        target_os = "haiku",
+        target_os = "cygwin"))))]
+fn get_num_conf_cpus() -> usize {
+    let cpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_CONF) };
+    if cpus < 1 {
+        get()
+    } else {
+        cpus as usize
+    }
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "tvos",
+    target_os = "watchos",
+    all(not(feature = "compact"), any(
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "solaris",
+        target_os = "illumos",
+        target_os = "aix",
+        target_os = "nto",
+        target_os = "haiku",
+        target_os = "cygwin")))))]
+fn get_num_conf_cpus() -> usize {
+    get()
+}
+
+/// Returns the number of logical CPUs macOS currently has active.
+///
+/// This reads the `hw.activecpu` sysctl on every call. macOS may disable cores at
+/// runtime, such as under thermal pressure, and while `hw.logicalcpu` keeps
+/// counting every logical CPU, `hw.activecpu` only counts the ones that can run
+/// threads right now, which is what a pool sized for the current moment wants.
+///
+/// Falls back to [`get()`] if the sysctl can't be read.
+///
+/// # Examples
+///
+/// ```
+/// let active = num_cpus::get_active_macos();
+/// assert!(active >= 1);
+/// ```
+///
+/// [`get()`]: fn.get.html
+#[cfg(target_os = "macos")]
+pub fn get_active_macos() -> usize {
+    match get_sysctl_int("hw.activecpu") {
+        Some(active) if active > 0 => active,
+        _ => get(),
+    }
+}
+
+/// A summary of the CPUs of the current system.
+///
+/// See [`detect()`](fn.detect.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CpuInfo {
+    /// The online logical CPUs, regardless of sched affinity or cgroups.
+    pub logical: usize,
+    /// The physical cores, as [`get_physical()`](fn.get_physical.html) returns.
+    pub physical: usize,
+    /// The CPUs this process may use, as [`get()`](fn.get.html) returns.
+    pub available: usize,
+    /// The NUMA nodes, `1` where they aren't known.
+    pub numa_nodes: usize,
+    /// The CPU sockets, `1` where they aren't known.
+    pub sockets: usize,
+    /// The `(performance, efficiency)` logical CPUs, as
+    /// [`hybrid_counts()`](fn.hybrid_counts.html) returns.
+    pub hybrid: Option<(usize, usize)>,
+}
+
+/// Returns everything this crate knows about the CPUs of the current system at once.
+///
+/// This is cheaper than calling each function separately, since the Linux
+/// implementation avoids reading the same files from `/proc` and `/sys` more
+/// than once. Every count is at least `1`.
+///
+/// The CPUs are only detected on the first call, later ones return the same
+/// `CpuInfo` without any syscall or file I/O. That first call may block on
+/// the filesystem, see [`detect_blocking()`].
+///
+/// # Examples
+///
+/// ```
+/// let info = num_cpus::detect();
+/// println!(
+///     "{} of {} CPUs available, on {} cores in {} sockets",
+///     info.available, info.logical, info.physical, info.sockets
+/// );
+/// ```
+///
+/// [`detect_blocking()`]: fn.detect_blocking.html
+pub fn detect() -> CpuInfo {
+    // Allow deprecation warnings, ONCE_INIT is needed by older rustc.
+    #[allow(warnings)]
+    static ONCE: Once = ::std::sync::ONCE_INIT;
+    static mut CPU_INFO: Option<CpuInfo> = None;
+
+    unsafe {
+        ONCE.call_once(|| CPU_INFO = Some(get_cpu_info()));
+        CPU_INFO.expect("initialized by call_once")
+    }
+}
+
+/// The same as [`detect()`], named for the filesystem I/O it performs.
+///
+/// On Linux, the first call reads `/proc` and `/sys`, which is blocking and can
+/// be slow, such as on a loaded host or with a FUSE backed `/proc` in some
+/// sandboxes. In an async service, call it from a blocking thread, such as
+/// with tokio's `spawn_blocking`, instead of on an executor worker. Once
+/// detected, calls return immediately.
+///
+/// # Examples
+///
+/// ```
+/// let info = num_cpus::detect_blocking();
+/// assert!(info.available >= 1);
+/// ```
+///
+/// [`detect()`]: fn.detect.html
+#[inline]
+pub fn detect_blocking() -> CpuInfo {
+    detect()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn get_cpu_info() -> CpuInfo {
+    CpuInfo {
+        // What the platform counts, not narrowed by the sched affinity of `get()`.
+        logical: clamp_min_one(get_num_cpus()),
+        physical: get_physical(),
+        available: get(),
+        numa_nodes: 1,
+        sockets: cores_per_socket().len(),
+        hybrid: hybrid_counts(),
+    }
+}
+
+/// The number of CPUs in each of the states tracked by the kernel.
+///
+/// See [`cpu_states()`](fn.cpu_states.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CpuStates {
+    /// CPUs that could ever be brought online, including empty hotplug slots.
+    pub possible: usize,
+    /// CPUs that are currently installed.
+    pub present: usize,
+    /// CPUs that are currently online and can be scheduled on.
+    pub online: usize,
+}
+
+/// Returns the number of possible, present and online CPUs of the current system.
+///
+/// On Linux, these are read from `/sys/devices/system/cpu/{possible,present,online}`,
+/// and always satisfy `possible >= present >= online`. Unlike [`get()`], none
+/// of them take sched affinity or cgroups into account.
+///
+/// On other platforms, all three are the number of CPUs the platform reports,
+/// the `logical` count of [`detect()`].
+///
+/// # Examples
+///
+/// ```
+/// let states = num_cpus::cpu_states();
+/// println!("{} of {} CPUs are online", states.online, states.present);
+/// ```
+///
+/// [`get()`]: fn.get.html
+/// [`detect()`]: fn.detect.html
+#[inline]
+pub fn cpu_states() -> CpuStates {
+    get_cpu_states()
+}
+
+/// Returns the number of `(performance, efficiency)` logical CPUs of a hybrid system.
+///
+/// The core types are read from:
+///
+/// - Linux: the `cpu_core` and `cpu_atom` CPU lists of Intel hybrid parts, or
+///   else the `cpu_capacity` of each CPU in `/sys/devices/system/cpu` on Arm.
+/// - macOS: the `hw.perflevelN.logicalcpu` sysctls of Apple silicon.
+/// - Windows: the `EfficiencyClass` of `GetLogicalProcessorInformationEx`.
+///
+/// Some systems have more than two tiers, such as the prime, performance and
+/// efficiency cores of recent Arm SoCs. Only the slowest tier counts as
+/// efficiency CPUs, the others count as performance ones.
+///
+/// Returns `None` if all CPUs are of the same type, and always on other platforms.
+///
+/// # Examples
+///
+/// ```
+/// match num_cpus::hybrid_counts() {
+///     Some((performance, efficiency)) => {
+///         println!("{} performance and {} efficiency CPUs", performance, efficiency)
+///     }
+///     None => println!("all CPUs are alike"),
+/// }
+/// ```
+#[inline]
+pub fn hybrid_counts() -> Option<(usize, usize)> {
+    get_hybrid_counts()
+}
+
+/// Returns whether the CPUs are of more than one type, such as the performance
+/// and efficiency cores of Apple silicon, Intel hybrid parts or Arm big.LITTLE.
+///
+/// This is [`hybrid_counts()`](fn.hybrid_counts.html) returning `Some`, for a
+/// scheduler which only needs to know whether pinning work to a core type can
+/// make a difference. It's `false` where the core types aren't detected.
+///
+/// # Examples
+///
+/// ```
+/// if num_cpus::is_heterogeneous() {
+///     println!("pinning latency sensitive work to the performance cores");
+/// }
+/// ```
+#[inline]
+pub fn is_heterogeneous() -> bool {
+    hybrid_counts().is_some()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn get_hybrid_counts() -> Option<(usize, usize)> {
+    None
+}
+
+/// The type of the core a logical CPU belongs to, on a hybrid system.
+///
+/// See [`full_topology()`](fn.full_topology.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CoreType {
+    /// A performance core, or any but the slowest tier of a system with more
+    /// than two.
+    Performance,
+    /// An efficiency core, of the slowest tier.
+    Efficiency,
+    /// All the cores are of the same type, or it isn't known.
+    Unknown,
+}
+
+/// The NUMA nodes of the current system, with their cores and logical CPUs.
+///
+/// See [`full_topology()`](fn.full_topology.html).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FullTopology {
+    /// The NUMA nodes with CPUs, ordered by id.
+    pub nodes: Vec<NumaNode>,
+}
+
+/// A NUMA node of a [`FullTopology`](struct.FullTopology.html).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NumaNode {
+    /// The id of the node, `N` of `/sys/devices/system/node/nodeN` on Linux.
+    pub id: usize,
+    /// The physical cores of the node, ordered by their first logical CPU.
+    pub cores: Vec<PhysicalCore>,
+}
+
+/// A physical core of a [`NumaNode`](struct.NumaNode.html).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PhysicalCore {
+    /// The package, or socket, of the core.
+    pub package: usize,
+    /// The logical CPUs of the core, more than one with SMT, ordered by id.
+    pub cpus: Vec<LogicalCpu>,
+}
+
+/// A logical CPU of a [`PhysicalCore`](struct.PhysicalCore.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LogicalCpu {
+    /// The id of the CPU, as used in sched affinity masks and cpusets.
+    pub id: usize,
+    /// The type of its core.
+    pub core_type: CoreType,
+    /// The maximum frequency of the CPU in kHz, if it's known.
+    pub max_freq_khz: Option<u64>,
+}
+
+/// Returns the NUMA nodes of the current system, their physical cores, and the
+/// logical CPUs of each core, with their core type and maximum frequency.
+///
+/// This is the whole machine at once, for schedulers which would otherwise call
+/// [`logical_cpus_per_node()`], [`threads_per_core_detailed()`] and
+/// [`hybrid_counts()`] and still not know which CPU is which. On Linux it's read
+/// in a single walk of `/sys/devices/system/cpu`, with the nodes of
+/// `/sys/devices/system/node`, the core types as for [`hybrid_counts()`], and the
+/// frequencies from `cpufreq/cpuinfo_max_freq`. Only the online CPUs are listed,
+/// regardless of sched affinity or cgroups, and without NUMA support there's a
+/// single node `0`.
+///
+/// On other platforms, or without sysfs, this is a best effort: a single node of
+/// [`threads_per_core_detailed()`] cores, with consecutive CPU ids, of an unknown
+/// type and frequency.
+///
+/// # Examples
+///
+/// ```
+/// let topology = num_cpus::full_topology();
+/// for node in &topology.nodes {
+///     for core in &node.cores {
+///         let ids: Vec<usize> = core.cpus.iter().map(|cpu| cpu.id).collect();
+///         println!("node {}: core of CPUs {:?}", node.id, ids);
+///     }
+/// }
+/// ```
+///
+/// [`logical_cpus_per_node()`]: fn.logical_cpus_per_node.html
+/// [`threads_per_core_detailed()`]: fn.threads_per_core_detailed.html
+/// [`hybrid_counts()`]: fn.hybrid_counts.html
+#[inline]
+pub fn full_topology() -> FullTopology {
+    get_full_topology()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn get_full_topology() -> FullTopology {
+    uniform_topology()
+}
+
+fn uniform_topology() -> FullTopology {
+    let mut next = 0;
+    let cores = threads_per_core_detailed()
+        .into_iter()
+        .map(|threads| {
+            let cpus = (next..next + threads)
+                .map(|id| LogicalCpu {
+                    id: id,
+                    core_type: CoreType::Unknown,
+                    max_freq_khz: None,
+                })
+                .collect();
+            next += threads;
+            PhysicalCore {
+                package: 0,
+                cpus: cpus,
+            }
+        })
+        .collect();
+
+    FullTopology {
+        nodes: vec![NumaNode {
+            id: 0,
+            cores: cores,
+        }],
+    }
+}
+
+/// The number of sockets, physical cores and logical CPUs of the current system.
+///
+/// See [`topology()`](fn.topology.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CpuTopology {
+    /// The CPU sockets, or packages, `1` where they aren't known.
+    pub sockets: usize,
+    /// The physical cores of all sockets.
+    pub physical_cores: usize,
+    /// The logical CPUs, or hardware threads, of all cores.
+    pub logical_cores: usize,
+}
+
+/// Returns the number of sockets, physical cores and logical CPUs at once.
+///
+/// This is for schedulers which want the shape of the machine rather than a flat
+/// count, and is detected in one pass over what each platform reports:
+///
+/// * On Linux, `/proc/cpuinfo`, with its distinct `physical id`s as the sockets, and
+///   the `physical_package_id`s of sysfs when it has none, or when its cores disagree
+///   with the thread siblings of sysfs. The physical cores are counted as for
+///   [`get_physical()`], and the logical CPUs are the online ones.
+/// * On Windows, the `RelationProcessorPackage` and `RelationProcessorCore` records
+///   of `GetLogicalProcessorInformationEx`, with the active processors of every group.
+/// * On macOS, iOS, tvOS and watchOS, the `hw.packages`, `hw.physicalcpu` and
+///   `hw.logicalcpu` sysctls.
+///
+/// Like [`detect()`], the logical CPUs aren't limited by the sched affinity or
+/// cgroups. On other platforms, this is a single socket with the counts of
+/// [`get_physical()`] and [`get()`]. Every count is at least `1`.
+///
+/// # Examples
+///
+/// ```
+/// let topology = num_cpus::topology();
+/// println!(
+///     "{} sockets, {} cores, {} threads",
+///     topology.sockets, topology.physical_cores, topology.logical_cores
+/// );
+/// ```
+///
+/// [`get()`]: fn.get.html
+/// [`get_physical()`]: fn.get_physical.html
+/// [`detect()`]: fn.detect.html
+#[inline]
+pub fn topology() -> CpuTopology {
+    let topology = get_topology();
+    CpuTopology {
+        sockets: clamp_min_one(topology.sockets),
+        physical_cores: clamp_min_one(topology.physical_cores),
+        logical_cores: clamp_min_one(topology.logical_cores),
+    }
+}
+
+#[cfg(any(target_os = "macos",
+          target_os = "ios",
+          target_os = "tvos",
+          target_os = "watchos"))]
+fn get_topology() -> CpuTopology {
+    let sysctl = |name, fallback| match get_sysctl_int(name) {
+        Some(value) if value > 0 => value,
+        _ => fallback,
+    };
+
+    CpuTopology {
+        sockets: sysctl("hw.packages", 1),
+        physical_cores: sysctl("hw.physicalcpu", get_physical()),
+        logical_cores: sysctl("hw.logicalcpu", get()),
+    }
+}
+
+#[cfg(not(any(target_os = "linux",
+              target_os = "windows",
+              target_os = "macos",
+              target_os = "ios",
+              target_os = "tvos",
+              target_os = "watchos")))]
+fn get_topology() -> CpuTopology {
+    CpuTopology {
+        sockets: 1,
+        physical_cores: get_physical(),
+        logical_cores: get(),
+    }
+}
+
+/// The version of cgroups mounted on the current system.
+///
+/// See [`cgroup_version()`](fn.cgroup_version.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CgroupVersion {
+    /// Only cgroups v1 hierarchies are mounted.
+    V1,
+    /// Only the cgroups v2 unified hierarchy is mounted.
+    V2,
+    /// Both are mounted, usually with the v2 hierarchy at `/sys/fs/cgroup/unified`.
+    Hybrid,
+}
+
+/// Returns the version of cgroups in use on the current system.
+///
+/// This inspects the `cgroup` and `cgroup2` filesystems listed in
+/// `/proc/self/mountinfo`, and returns `None` if neither is mounted.
+///
+/// This is always `None` on platforms other than Linux.
+///
+/// # Examples
+///
+/// ```
+/// match num_cpus::cgroup_version() {
+///     Some(version) => println!("cgroups: {:?}", version),
+///     None => println!("cgroups are not mounted"),
+/// }
+/// ```
+#[inline]
+pub fn cgroup_version() -> Option<CgroupVersion> {
+    get_cgroup_version()
+}
+
+/// Returns the cgroup directory of the current process, as seen from this mount namespace.
+///
+/// This is the directory of the `cpu` controller that [`get()`] reads the CPU quota
+/// from, found by translating the path in `/proc/self/cgroup` through the mount
+/// points of `/proc/self/mountinfo`. It is useful when the count looks wrong in a
+/// container, to check which limits were read.
+///
+/// Returns `None` if the process isn't in a cgroup, or the cgroup isn't mounted,
+/// and always on platforms other than Linux.
+///
+/// # Examples
+///
+/// ```
+/// if let Some(path) = num_cpus::cgroup_path() {
+///     println!("cgroup: {}", path.display());
+/// }
+/// ```
+///
+/// [`get()`]: fn.get.html
+#[inline]
+pub fn cgroup_path() -> Option<PathBuf> {
+    get_cgroup_path()
+}
+
+/// Returns the number of CPUs the cgroup at `path` would allow.
+///
+/// Unlike [`get()`](fn.get.html), this looks at an arbitrary cgroup directory,
+/// such as `/sys/fs/cgroup/system.slice/docker-<id>.scope`, instead of the one
+/// of the current process. The count is the lowest of the online CPUs, the
+/// CPU quota (`cpu.max`, or `cpu.cfs_quota_us` and `cpu.cfs_period_us` on
+/// cgroups v1) and the CPUs in its `cpuset`. The affinity of the current
+/// thread is not taken into account.
+///
+/// Returns `None` if `path` is not a cgroup directory, and always on
+/// platforms other than Linux.
+///
+/// # Examples
+///
+/// ```
+/// use std::path::Path;
+///
+/// if let Some(cpus) = num_cpus::cgroup_cpus_at(Path::new("/sys/fs/cgroup/system.slice")) {
+///     println!("system.slice may use {} CPUs", cpus);
+/// }
+/// ```
+#[inline]
+pub fn cgroup_cpus_at(path: &Path) -> Option<usize> {
+    get_cgroup_cpus_at(path)
+}
+
+/// Returns the CPU quota of the cgroup of the current process, as a fraction.
+///
+/// A `cpu.max` of `250000 100000` is `2.5`, which [`get()`](fn.get.html)
+/// rounds up to 3 CPUs. This gives the exact value, for callers which weigh
+/// their work by it, or report it. The quota is read on every call.
+///
+/// Returns `None` without a quota, and always on platforms other than Linux.
+///
+/// # Examples
+///
+/// ```
+/// if let Some(quota) = num_cpus::cgroup_cpu_quota() {
+///     println!("limited to {:.2} CPUs", quota);
+/// }
+/// ```
+#[inline]
+pub fn cgroup_cpu_quota() -> Option<f64> {
+    get_cgroup_cpu_quota()
+}
+
+/// Estimates the number of CPUs the current process gets when the machine is busy.
+///
+/// With a cgroup CPU quota, this is [`get()`]. Without one, such as for a
+/// Kubernetes pod with a CPU request but no limit, the cgroup can use every CPU
+/// while the others are idle, but only gets a share of them proportional to its
+/// `cpu.weight` (`cpu.shares` on cgroups v1) when they aren't. This returns that
+/// share of [`get()`], relative to the weights of the cgroup and its siblings.
+///
+/// This is a heuristic, meant for decisions such as autoscaling, and is separate
+/// from [`get()`] on purpose. It will always return at least `1`, and never more
+/// than [`get()`], which it is the same as outside of a cgroup and on platforms
+/// other than Linux.
+///
+/// # Examples
+///
+/// ```
+/// let estimate = num_cpus::effective_cpus_estimate();
+/// assert!(estimate >= 1 && estimate <= num_cpus::get());
+/// ```
+///
+/// [`get()`]: fn.get.html
+#[inline]
+pub fn effective_cpus_estimate() -> usize {
+    clamp_min_one(get_effective_cpus_estimate())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn get_effective_cpus_estimate() -> usize {
+    get_num_cpus()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn get_cpu_states() -> CpuStates {
+    let cpus = clamp_min_one(get_num_cpus());
+    CpuStates {
+        possible: cpus,
+        present: cpus,
+        online: cpus,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn get_cgroup_version() -> Option<CgroupVersion> {
+    None
+}
+
+#[cfg(not(target_os = "linux"))]
+fn get_cgroup_path() -> Option<PathBuf> {
+    None
+}
+
+#[cfg(not(target_os = "linux"))]
+fn get_cgroup_cpus_at(_path: &Path) -> Option<usize> {
+    None
+}
+
+#[cfg(not(target_os = "linux"))]
+fn get_cgroup_cpu_quota() -> Option<f64> {
+    None
+}
+
+// Linux and Windows have their own modules. Elsewhere, each platform knowing
+// its physical cores only needs a `get_num_physical_cpus_probe()` below.
+//
+// `sysconf(_SC_NPROCESSORS_CONF)` isn't used as a probe: it counts the
+// configured logical CPUs, which only differs from the online ones when some
+// were turned off.
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn get_num_physical_cpus() -> usize {
+    match try_num_physical_cpus() {
+        Some(num) => num,
+        // Not implemented, fall back
+        None => get_num_cpus(),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn get_num_physical_cpus_with_source() -> (usize, Source) {
+    match try_num_physical_cpus() {
+        Some(num) if cfg!(any(
+            target_os = "aix",
+            target_os = "nto",
+            target_os = "solaris",
+            target_os = "illumos"
+        )) => (num, Source::Platform),
+        Some(num) => (num, Source::Sysctl),
+        None => (get_num_cpus(), Source::Logical),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn try_num_physical_cpus() -> Option<usize> {
+    match get_num_physical_cpus_probe() {
+        Some(num) if num > 0 => Some(num),
+        _ => None,
+    }
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "windows",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "tvos",
+    target_os = "watchos",
+    all(not(feature = "compact"), any(
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "aix",
+        target_os = "nto",
+        target_os = "solaris",
+        target_os = "illumos")))))]
+#[inline]
+fn get_num_physical_cpus_probe() -> Option<usize> {
+    None
+}
+
+// What `try_get_physical()` returns when `try_physical()` is `None`.
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "windows",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "tvos",
+    target_os = "watchos",
+    all(not(feature = "compact"), any(
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "aix",
+        target_os = "nto",
+        target_os = "solaris",
+        target_os = "illumos")))))]
+const PHYSICAL_ERROR: Error = Error::Unsupported;
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "windows",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "tvos",
+    target_os = "watchos",
+    all(not(feature = "compact"), any(
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "aix",
+        target_os = "nto",
+        target_os = "solaris",
+        target_os = "illumos"))))]
+const PHYSICAL_ERROR: Error = Error::Parse;
+
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "tvos", target_os = "watchos"))]
+fn get_num_physical_cpus_probe() -> Option<usize> {
+    get_sysctl_int("hw.physicalcpu")
+}
+
+#[cfg(all(target_os = "freebsd", not(feature = "compact")))]
+fn get_num_physical_cpus_probe() -> Option<usize> {
+    get_sysctl_int("kern.smp.cores")
+}
+
+#[cfg(all(num_cpus_freebsd_like, not(feature = "compact")))]
+fn get_num_cpus() -> usize {
+    let cpus = if cfg!(feature = "no_affinity") {
+        get_num_online_cpus()
+    } else {
+        match get_affinity_freebsd() {
+            Some(cpus) if cpus > 0 => cpus,
+            _ => get_num_online_cpus(),
+        }
+    };
+
+    match get_app_cpus() {
+        Some(app) if app > 0 && app < cpus => app,
+        _ => cpus,
+    }
+}
+
+// The CPUs an application may use, when the system reserves some of them for
+// itself. Console SDK targets derived from FreeBSD report these with their own
+// sysctl, lower than `hw.ncpu`.
+#[cfg(all(num_cpus_freebsd_like, not(feature = "compact")))]
+fn get_app_cpus() -> Option<usize> {
+    app_cpus_sysctl().and_then(get_sysctl_int)
+}
+
+// The sysctl of `get_app_cpus()`. A target derived from FreeBSD joins the
+// `FREEBSD_LIKE` list of build.rs, and names its sysctl in its own version of
+// this function, behind its `target_os`, so the mainline BSD code stays as is.
+#[cfg(all(target_os = "freebsd", not(feature = "compact")))]
+#[inline]
+fn app_cpus_sysctl() -> Option<&'static str> {
+    None
+}
+
+// Fails to build a target of `FREEBSD_LIKE` without its own `app_cpus_sysctl()`,
+// or with one of another type.
+#[cfg(all(num_cpus_freebsd_like, not(feature = "compact")))]
+#[allow(dead_code)]
+const APP_CPUS_SYSCTL: fn() -> Option<&'static str> = app_cpus_sysctl;
+
+// The CPUs of the process' cpuset, as set with `cpuset(1)`, which narrows
+// those of the jail it runs in.
+#[cfg(all(num_cpus_freebsd_like, not(feature = "compact")))]
+fn get_affinity_freebsd() -> Option<usize> {
+    const CPU_LEVEL_WHICH: libc::c_int = 3;
+    const CPU_WHICH_PID: libc::c_int = 2;
+
+    extern "C" {
+        fn cpuset_getaffinity(
+            level: libc::c_int,
+            which: libc::c_int,
+            id: libc::id_t,
+            setsize: libc::size_t,
+            mask: *mut u64,
+        ) -> libc::c_int;
+    }
+
+    // 1024 bits, the largest set the kernel accepts (`CPU_MAXSIZE`), so it
+    // doesn't depend on the `CPU_SETSIZE` the system was built with.
+    let mut mask = [0u64; 16];
+    let rc = unsafe {
+        cpuset_getaffinity(CPU_LEVEL_WHICH,
+                           CPU_WHICH_PID,
+                           -1,
+                           std::mem::size_of_val(&mask),
+                           mask[..].as_mut_ptr())
+    };
+
+    if rc != 0 {
+        return None;
+    }
+
+    Some(mask.iter().map(|bits| bits.count_ones() as usize).sum())
+}
+
+#[cfg(all(any(target_os = "dragonfly",
+              target_os = "netbsd"),
+          not(feature = "compact")))]
+fn get_num_cpus() -> usize {
+    get_num_online_cpus()
+}
+
+#[cfg(all(any(num_cpus_freebsd_like,
+              target_os = "dragonfly",
+              target_os = "netbsd"),
+          not(feature = "compact")))]
+fn get_num_online_cpus() -> usize {
+    use std::ptr;
+
+    let mut cpus: libc::c_uint = 0;
+    let mut cpus_size = std::mem::size_of_val(&cpus);
+
+    unsafe {
+        cpus = libc::sysconf(libc::_SC_NPROCESSORS_ONLN) as libc::c_uint;
+    }
+    if cpus < 1 {
+        let mut mib = [libc::CTL_HW, libc::HW_NCPU, 0, 0];
+        unsafe {
+            libc::sysctl(mib.as_mut_ptr(),
+                         2,
+                         &mut cpus as *mut _ as *mut _,
+                         &mut cpus_size as *mut _ as *mut _,
+                         ptr::null_mut(),
+                         0);
+        }
+    }
+    cpus as usize
+}
+
+#[cfg(all(target_os = "openbsd", not(feature = "compact")))]
+fn get_num_cpus() -> usize {
+    use std::ptr;
+
+    let mut cpus: libc::c_uint = 0;
+    let mut cpus_size = std::mem::size_of_val(&cpus);
+    let mut mib = [libc::CTL_HW, libc::HW_NCPUONLINE, 0, 0];
+    let rc: libc::c_int;
+
+    unsafe {
+        rc = libc::sysctl(mib.as_mut_ptr(),
+                          2,
+                          &mut cpus as *mut _ as *mut _,
+                          &mut cpus_size as *mut _ as *mut _,
+                          ptr::null_mut(),
+                          0);
+    }
+    if rc < 0 {
+        cpus = 1;
+    }
+    cpus as usize
+}
+
+#[cfg(all(target_os = "openbsd", not(feature = "compact")))]
+fn get_num_physical_cpus_probe() -> Option<usize> {
+    use std::ptr;
+
+    let mut cpus: libc::c_uint = 0;
+    let mut cpus_size = std::mem::size_of_val(&cpus);
+    let mut mib = [libc::CTL_HW, libc::HW_NCPU, 0, 0];
+    let rc: libc::c_int;
+
+    unsafe {
+        rc = libc::sysctl(mib.as_mut_ptr(),
+                          2,
+                          &mut cpus as *mut _ as *mut _,
+                          &mut cpus_size as *mut _ as *mut _,
+                          ptr::null_mut(),
+                          0);
+    }
+    if rc < 0 {
+        None
+    } else {
+        Some(cpus as usize)
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn get_hybrid_counts() -> Option<(usize, usize)> {
+    // Apple silicon lists its core types as performance levels, from the
+    // fastest in `hw.perflevel0` to the most efficient one.
+    get_sysctl_int("hw.nperflevels").and_then(|levels| {
+        let logical: Option<Vec<usize>> = (0..levels)
+            .map(|level| get_sysctl_int(&format!("hw.perflevel{}.logicalcpu", level)))
+            .collect();
+
+        logical.and_then(|logical| hybrid_counts_from_perflevels(&logical))
+    })
+}
+
+#[cfg(target_os = "macos")]
+fn hybrid_counts_from_perflevels(logical: &[usize]) -> Option<(usize, usize)> {
+    match logical.split_last() {
+        Some((&efficiency, performance)) if !performance.is_empty() => {
+            Some((performance.iter().sum(), efficiency))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(any(target_os = "macos",
+          target_os = "ios",
+          target_os = "tvos",
+          target_os = "watchos",
+          all(num_cpus_freebsd_like, not(feature = "compact"))))]
+fn get_sysctl_int(name: &str) -> Option<usize> {
+    use std::ffi::CString;
+    use std::ptr;
+
+    let mut value: i32 = 0;
+    let mut value_size = std::mem::size_of_val(&value);
+
+    let name = match CString::new(name) {
+        Ok(name) => name,
+        Err(_) => return None,
+    };
+
+    unsafe {
+        if 0 != libc::sysctlbyname(name.as_ptr(),
+                                   &mut value as *mut _ as *mut _,
+                                   &mut value_size as *mut _ as *mut _,
+                                   ptr::null_mut(),
+                                   0) {
+            return None;
+        }
+    }
+
+    if value < 0 {
+        None
+    } else {
+        Some(value as usize)
+    }
+}
+
+#[cfg(all(target_os = "aix", not(feature = "compact")))]
+fn get_num_physical_cpus_probe() -> Option<usize> {
+    match get_smt_threads_aix() {
+        Some(num) if num > 0 => Some(get_num_cpus() / num),
+        _ => None,
+    }
+}
+
+#[cfg(all(target_os = "aix", not(feature = "compact")))]
+fn get_smt_threads_aix() -> Option<usize> {
+    let smt = unsafe {
+        libc::getsystemcfg(libc::SC_SMT_TC)
+    };
+    if smt == u64::MAX {
+        return None;
+    }
+    Some(smt as usize)
+}
+
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "tvos",
+    target_os = "watchos",
+    all(not(feature = "compact"), any(
+        target_os = "aix",
+        target_os = "solaris",
+        target_os = "illumos",
+        target_os = "fuchsia",
+        target_os = "nto")))
+)]
+fn get_num_cpus() -> usize {
+    // On ARM targets, processors could be turned off to save power.
+    // Use `_SC_NPROCESSORS_CONF` to get the real number.
+    #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+    const CONF_NAME: libc::c_int = libc::_SC_NPROCESSORS_CONF;
+    #[cfg(not(any(target_arch = "arm", target_arch = "aarch64")))]
+    const CONF_NAME: libc::c_int = libc::_SC_NPROCESSORS_ONLN;
+
+    let cpus = unsafe { libc::sysconf(CONF_NAME) };
+    if cpus < 1 {
+        1
+    } else {
+        cpus as usize
+    }
+}
+
+#[cfg(all(target_os = "haiku", not(feature = "compact")))]
+fn get_num_cpus() -> usize {
+    use std::mem;
+
+    #[allow(non_camel_case_types)]
+    type bigtime_t = i64;
+    #[allow(non_camel_case_types)]
+    type status_t = i32;
+
+    #[repr(C)]
+    pub struct system_info {
+        pub boot_time: bigtime_t,
+        pub cpu_count: u32,
+        pub max_pages: u64,
         pub used_pages: u64,
         pub cached_pages: u64,
         pub block_cache_pages: u64,
@@ -414,43 +2618,298 @@ fn get_num_cpus() -> usize {
         pub abi: u32,
     }
 
-    extern {
-        fn get_system_info(info: *mut system_info) -> status_t;
+    extern {
+        fn get_system_info(info: *mut system_info) -> status_t;
+    }
+
+    let mut info: system_info = unsafe { mem::zeroed() };
+    let status = unsafe { get_system_info(&mut info as *mut _) };
+    if status == 0 {
+        info.cpu_count as usize
+    } else {
+        1
+    }
+}
+
+#[cfg(all(target_os = "hermit", not(feature = "compact")))]
+fn get_num_cpus() -> usize {
+    unsafe { hermit_abi::get_processor_count() }
+}
+
+#[cfg(all(target_os = "wasi", not(feature = "compact")))]
+fn get_num_cpus() -> usize {
+    // Without the threads proposal, a module can only ever run on one thread.
+    // With it (`wasm32-wasip1-threads`), wasi-libc reports how many threads
+    // the host is willing to run, which isn't necessarily the number of cores.
+    if !cfg!(target_feature = "atomics") {
+        return 1;
+    }
+
+    let cpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+    if cpus < 1 {
+        1
+    } else {
+        cpus as usize
+    }
+}
+
+#[cfg(all(target_os = "uefi", not(feature = "compact")))]
+fn get_num_cpus() -> usize {
+    // `EFI_MP_SERVICES_PROTOCOL::GetNumberOfProcessors` knows how many processors
+    // are enabled, but locating the protocol needs the boot services table, which
+    // std only exposes through the unstable `std::os::uefi`. Rust code only runs on
+    // the bootstrap processor anyway, as std doesn't support threads on UEFI.
+    1
+}
+
+#[cfg(all(target_os = "horizon", target_arch = "aarch64", not(feature = "compact")))]
+fn get_num_cpus() -> usize {
+    // The Nintendo Switch has 4 cores, but the last one is reserved for the
+    // system, so applications can only run threads on cores 0 to 2.
+    //
+    // libnx can report the core mask through `svcGetInfo`, but it isn't
+    // something we can link against, so rely on the fixed layout instead.
+    3
+}
+
+#[cfg(all(target_os = "horizon", target_arch = "arm", not(feature = "compact")))]
+fn get_num_cpus() -> usize {
+    // devkitPro's newlib on the 3DS reports the cores an application can run
+    // threads on: 2 on the original 3DS, up to 4 on the New 3DS once the
+    // extra cores are enabled with `APT_SetAppCpuTimeLimit`.
+    //
+    // The PSP (`mipsel-sony-psp`) is single core and has no std at all, so it
+    // can't build this crate.
+    let cpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+    if cpus < 1 {
+        1
+    } else {
+        cpus as usize
+    }
+}
+
+#[cfg(all(target_os = "trusty", not(feature = "compact")))]
+fn get_num_cpus() -> usize {
+    // Trusty apps are single threaded, std can't spawn threads there, and the
+    // libc doesn't have `sysconf` to ask the TEE how many cores it runs on.
+    1
+}
+
+#[cfg(all(target_os = "l4re", not(feature = "compact")))]
+fn get_num_cpus() -> usize {
+    // L4Re's scheduler reports the CPUs a task may run on with
+    // `l4_scheduler_info()`, but that's an inline function of the l4sys headers
+    // rather than a symbol we can link against. Until there's a way to call it,
+    // L4Re has its own `1` instead of relying on the catch-all below.
+    1
+}
+
+#[cfg(all(target_os = "teeos", not(feature = "compact")))]
+fn get_num_cpus() -> usize {
+    let cpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+    if cpus < 1 {
+        1
+    } else {
+        cpus as usize
+    }
+}
+
+// Cygwin is a Unix on top of Windows, and `cfg(windows)` isn't set for it, so
+// windows.rs isn't used. Its POSIX layer answers `sysconf` the same way as for
+// the rest of the process, rather than the Win32 calls behind its emulation.
+#[cfg(all(target_os = "cygwin", not(feature = "compact")))]
+fn get_num_cpus() -> usize {
+    let cpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+    if cpus < 1 {
+        1
+    } else {
+        cpus as usize
+    }
+}
+
+#[cfg(any(
+    all(feature = "compact", not(any(unix, windows))),
+    all(feature = "compact", target_os = "trusty"),
+    all(not(feature = "compact"), not(any(
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "tvos",
+        target_os = "watchos",
+        target_os = "android",
+        target_os = "aix",
+        target_os = "solaris",
+        target_os = "illumos",
+        target_os = "fuchsia",
+        target_os = "linux",
+        target_os = "openbsd",
+        target_os = "freebsd",
+        num_cpus_freebsd_like,
+        target_os = "dragonfly",
+        target_os = "netbsd",
+        target_os = "haiku",
+        target_os = "hermit",
+        all(target_os = "horizon", target_arch = "aarch64"),
+        all(target_os = "horizon", target_arch = "arm"),
+        target_os = "wasi",
+        target_os = "uefi",
+        target_os = "trusty",
+        target_os = "l4re",
+        target_os = "teeos",
+        target_os = "nto",
+        target_os = "cygwin",
+        windows,
+    ))),
+))]
+fn try_num_cpus() -> Result<usize, Error> {
+    // A platform listed in build.rs ended up here, it's missing from one of the
+    // cfg lists above.
+    #[cfg(num_cpus_first_class)]
+    let _: () = "this target_os has its own get_num_cpus(), see build.rs";
+
+    Err(Error::Unsupported)
+}
+
+#[cfg(any(
+    all(feature = "compact", not(any(unix, windows))),
+    all(feature = "compact", target_os = "trusty"),
+    all(not(feature = "compact"), not(any(
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "tvos",
+        target_os = "watchos",
+        target_os = "android",
+        target_os = "aix",
+        target_os = "solaris",
+        target_os = "illumos",
+        target_os = "fuchsia",
+        target_os = "linux",
+        target_os = "openbsd",
+        target_os = "freebsd",
+        num_cpus_freebsd_like,
+        target_os = "dragonfly",
+        target_os = "netbsd",
+        target_os = "haiku",
+        target_os = "hermit",
+        all(target_os = "horizon", target_arch = "aarch64"),
+        all(target_os = "horizon", target_arch = "arm"),
+        target_os = "wasi",
+        target_os = "uefi",
+        target_os = "trusty",
+        target_os = "l4re",
+        target_os = "teeos",
+        target_os = "nto",
+        target_os = "cygwin",
+        windows,
+    ))),
+))]
+fn get_num_cpus() -> usize {
+    1
+}
+
+// Elsewhere, the platform's get_num_cpus() has its own fallbacks.
+#[cfg(not(any(
+    target_os = "linux",
+    all(feature = "compact", not(any(unix, windows))),
+    all(feature = "compact", target_os = "trusty"),
+    all(not(feature = "compact"), not(any(
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "tvos",
+        target_os = "watchos",
+        target_os = "android",
+        target_os = "aix",
+        target_os = "solaris",
+        target_os = "illumos",
+        target_os = "fuchsia",
+        target_os = "linux",
+        target_os = "openbsd",
+        target_os = "freebsd",
+        num_cpus_freebsd_like,
+        target_os = "dragonfly",
+        target_os = "netbsd",
+        target_os = "haiku",
+        target_os = "hermit",
+        all(target_os = "horizon", target_arch = "aarch64"),
+        all(target_os = "horizon", target_arch = "arm"),
+        target_os = "wasi",
+        target_os = "uefi",
+        target_os = "trusty",
+        target_os = "l4re",
+        target_os = "teeos",
+        target_os = "nto",
+        target_os = "cygwin",
+        windows,
+    ))),
+)))]
+fn try_num_cpus() -> Result<usize, Error> {
+    Ok(get_num_cpus())
+}
+
+// With the `compact` feature, the Unix platforms other than Linux and macOS
+// share this instead of their own code. Trusty's libc has no `sysconf`.
+#[cfg(all(feature = "compact", unix, not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "tvos",
+    target_os = "watchos",
+    target_os = "trusty"))))]
+fn get_num_cpus() -> usize {
+    let cpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+    if cpus < 1 {
+        1
+    } else {
+        cpus as usize
+    }
+}
+
+// The platform functions, called through here so that the tests can replace them.
+#[cfg(not(test))]
+mod hooks {
+    #[inline]
+    pub fn try_num_cpus() -> Result<usize, ::Error> {
+        ::try_num_cpus()
+    }
+
+    #[inline]
+    pub fn overridden() -> bool {
+        false
     }
 
-    let mut info: system_info = unsafe { mem::zeroed() };
-    let status = unsafe { get_system_info(&mut info as *mut _) };
-    if status == 0 {
-        info.cpu_count as usize
-    } else {
-        1
+    #[inline]
+    pub fn num_physical_cpus() -> usize {
+        ::get_num_physical_cpus()
     }
 }
 
-#[cfg(target_os = "hermit")]
-fn get_num_cpus() -> usize {
-    unsafe { hermit_abi::get_processor_count() }
-}
+#[cfg(test)]
+mod hooks {
+    use std::cell::Cell;
 
-#[cfg(not(any(
-    target_os = "macos",
-    target_os = "ios",
-    target_os = "android",
-    target_os = "aix",
-    target_os = "solaris",
-    target_os = "illumos",
-    target_os = "fuchsia",
-    target_os = "linux",
-    target_os = "openbsd",
-    target_os = "freebsd",
-    target_os = "dragonfly",
-    target_os = "netbsd",
-    target_os = "haiku",
-    target_os = "hermit",
-    windows,
-)))]
-fn get_num_cpus() -> usize {
-    1
+    thread_local! {
+        pub static NUM_CPUS: Cell<Option<fn() -> usize>> = Cell::new(None);
+        pub static NUM_PHYSICAL_CPUS: Cell<Option<fn() -> usize>> = Cell::new(None);
+    }
+
+    pub fn try_num_cpus() -> Result<usize, ::Error> {
+        match NUM_CPUS.with(Cell::get) {
+            Some(f) => Ok(f()),
+            None => ::try_num_cpus(),
+        }
+    }
+
+    // A hooked count must not end up in the cache of `get()`, shared by every test.
+    pub fn overridden() -> bool {
+        NUM_CPUS.with(Cell::get).is_some()
+    }
+
+    pub fn num_physical_cpus() -> usize {
+        match NUM_PHYSICAL_CPUS.with(Cell::get) {
+            Some(f) => f(),
+            None => ::get_num_physical_cpus(),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -480,4 +2939,687 @@ mod tests {
             assert!(num < 236_451);
         }
     }
+
+    #[test]
+    fn test_compile_time() {
+        let expected = option_env!("NUM_CPUS_COMPILE_TIME")
+            .and_then(|cpus| cpus.trim().parse::<usize>().ok());
+
+        match expected {
+            Some(cpus) if cpus > 0 => {
+                assert_eq!(super::COMPILE_TIME_CPUS, Some(cpus));
+                assert_eq!(super::get(), cpus);
+                assert_eq!(super::refresh(), cpus);
+            }
+            _ => assert_eq!(super::COMPILE_TIME_CPUS, None),
+        }
+    }
+
+    #[test]
+    fn test_get_never_zero() {
+        fn zero() -> usize {
+            0
+        }
+
+        super::hooks::NUM_CPUS.with(|hook| hook.set(Some(zero as fn() -> usize)));
+        super::hooks::NUM_PHYSICAL_CPUS.with(|hook| hook.set(Some(zero as fn() -> usize)));
+
+        assert_eq!(super::get(), 1);
+        assert_eq!(super::get_physical(), 1);
+        assert_eq!(super::get_reserved(1), 1);
+    }
+
+    #[test]
+    #[cfg(target_os = "macos")]
+    fn test_hybrid_counts_from_perflevels() {
+        assert_eq!(super::hybrid_counts_from_perflevels(&[8, 4]), Some((8, 4)));
+        assert_eq!(super::hybrid_counts_from_perflevels(&[2, 4, 6]), Some((6, 6)));
+        assert_eq!(super::hybrid_counts_from_perflevels(&[8]), None);
+        assert_eq!(super::hybrid_counts_from_perflevels(&[]), None);
+
+        if let Some((performance, efficiency)) = super::hybrid_counts() {
+            assert!(performance > 0 && efficiency > 0);
+        }
+    }
+
+    #[test]
+    fn test_is_heterogeneous() {
+        assert_eq!(super::is_heterogeneous(), super::hybrid_counts().is_some());
+        if cfg!(not(any(target_os = "linux", target_os = "macos", windows))) {
+            assert!(!super::is_heterogeneous());
+        }
+    }
+
+    #[test]
+    #[cfg(any(
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "tvos",
+        target_os = "watchos",
+        all(not(feature = "compact"), any(
+            target_os = "freebsd",
+            target_os = "openbsd",
+            target_os = "aix",
+            target_os = "solaris",
+            target_os = "illumos"))))]
+    fn test_physical_probe() {
+        let physical = match super::get_num_physical_cpus_probe() {
+            Some(physical) => physical,
+            // a zone without `/dev/kstat` can't count them
+            None if cfg!(any(target_os = "solaris", target_os = "illumos")) => return,
+            None => panic!("physical cores"),
+        };
+        // `get()` may be narrowed by an affinity mask the cores aren't. The
+        // `HW_NCPU` of OpenBSD also counts the SMT threads `hw.smt` disabled.
+        let online = if cfg!(target_os = "openbsd") {
+            unsafe { libc::sysconf(libc::_SC_NPROCESSORS_CONF) }
+        } else {
+            unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) }
+        };
+
+        assert!(physical > 0);
+        assert!(online < 1 || physical <= online as usize);
+    }
+
+    #[test]
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "windows",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "tvos",
+        target_os = "watchos",
+        all(not(feature = "compact"), any(
+            target_os = "freebsd",
+            target_os = "openbsd",
+            target_os = "aix",
+            target_os = "solaris",
+            target_os = "illumos"))))]
+    fn test_try_physical() {
+        let physical = match super::try_physical() {
+            Some(physical) => physical,
+            None if cfg!(any(target_os = "solaris", target_os = "illumos")) => return,
+            None => panic!("physical cores"),
+        };
+
+        assert_eq!(physical, super::get_physical());
+    }
+
+    #[test]
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "windows",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "tvos",
+        target_os = "watchos",
+        all(not(feature = "compact"), any(
+            target_os = "freebsd",
+            target_os = "openbsd",
+            target_os = "aix",
+            target_os = "solaris",
+            target_os = "illumos")))))]
+    fn test_try_physical_unsupported() {
+        assert_eq!(super::try_physical(), None);
+        assert_eq!(super::get_physical(), super::get_num_cpus());
+    }
+
+    #[test]
+    #[cfg(target_os = "macos")]
+    fn test_get_active_macos() {
+        let logical = super::get_sysctl_int("hw.logicalcpu").expect("hw.logicalcpu");
+        let active = super::get_active_macos();
+
+        assert!(active >= 1);
+        assert!(active <= logical);
+    }
+
+    #[test]
+    #[cfg(all(target_os = "freebsd", not(feature = "compact")))]
+    fn test_affinity_freebsd() {
+        let allowed = super::get_affinity_freebsd().expect("cpuset_getaffinity");
+
+        assert!(allowed >= 1);
+        assert!(allowed <= super::get_num_online_cpus());
+    }
+
+    #[test]
+    #[cfg(all(target_os = "freebsd", not(feature = "compact")))]
+    fn test_physical_freebsd() {
+        let cores = super::get_sysctl_int("kern.smp.cores").expect("kern.smp.cores");
+        let ncpu = super::get_sysctl_int("hw.ncpu").expect("hw.ncpu");
+
+        assert!(cores >= 1);
+        assert!(cores <= ncpu);
+        assert_eq!(super::get_physical(), cores);
+    }
+
+    #[test]
+    #[cfg(all(num_cpus_freebsd_like, not(feature = "compact")))]
+    fn test_app_cpus_freebsd_like() {
+        let cpus = super::get_num_cpus();
+
+        assert!(cpus >= 1 && cpus <= super::get_num_online_cpus());
+        if let Some(app) = super::get_app_cpus() {
+            assert!(cpus <= app);
+        }
+    }
+
+    #[test]
+    fn test_get_with_source() {
+        let (cpus, source) = super::get_with_source();
+
+        assert_eq!(cpus, super::get());
+        if cfg!(target_os = "linux") && super::COMPILE_TIME_CPUS.is_none() {
+            assert!(source != super::LogicalSource::Platform);
+        }
+    }
+
+    #[test]
+    fn test_threads_per_core_detailed() {
+        let threads = super::threads_per_core_detailed();
+
+        assert!(!threads.is_empty());
+        assert!(threads.iter().all(|&n| n >= 1));
+    }
+
+    #[test]
+    fn test_smt_enabled() {
+        assert!(super::has_smt(&[2, 2, 1, 1]));
+        assert!(!super::has_smt(&[1, 1, 1, 1]));
+        assert!(!super::has_smt(&[]));
+
+        if super::smt_enabled() {
+            assert!(super::get_physical() < super::get_hardware());
+        }
+    }
+
+    #[test]
+    fn test_get_affinity() {
+        use LogicalSource;
+
+        let cpus = super::get_affinity();
+        assert!(cpus.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let mask = super::affinity_mask();
+        for &cpu in &cpus {
+            assert!(mask[cpu / 64] & (1 << (cpu % 64)) != 0);
+        }
+        match super::get_with_source().1 {
+            LogicalSource::CgroupQuota | LogicalSource::Override => assert!(!cpus.is_empty()),
+            _ => assert_eq!(cpus.len(), super::get()),
+        }
+    }
+
+    #[test]
+    fn test_affinity_mask() {
+        use LogicalSource;
+
+        assert_eq!(super::first_cpus_mask(0), vec![0]);
+        assert_eq!(super::first_cpus_mask(3), vec![0b111]);
+        assert_eq!(super::first_cpus_mask(64), vec![!0]);
+        assert_eq!(super::first_cpus_mask(65), vec![!0, 1]);
+
+        let bits: usize = super::affinity_mask()
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum();
+        // A quota or an override changes the count, but not the mask
+        match super::get_with_source().1 {
+            LogicalSource::CgroupQuota | LogicalSource::Override => assert!(bits >= 1),
+            _ => assert_eq!(bits, super::get()),
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_get_conf() {
+        assert!(super::get_conf() >= super::get());
+    }
+
+    #[test]
+    #[cfg(not(unix))]
+    fn test_get_conf_fallback() {
+        assert_eq!(super::get_conf(), super::get());
+    }
+
+    #[test]
+    fn test_global_hint() {
+        use std::sync::atomic::AtomicUsize;
+
+        let hint = AtomicUsize::new(0);
+        assert_eq!(super::load_hint(&hint), super::get());
+
+        assert!(!super::store_hint(&hint, 0));
+        assert_eq!(super::load_hint(&hint), super::get());
+
+        assert!(super::store_hint(&hint, 3));
+        assert_eq!(super::load_hint(&hint), 3);
+
+        // only once
+        assert!(!super::store_hint(&hint, 5));
+        assert_eq!(super::load_hint(&hint), 3);
+    }
+
+    #[test]
+    fn test_get_cached_with_ttl() {
+        use std::cell::Cell;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::Duration;
+
+        thread_local! {
+            static COUNTS: Cell<usize> = Cell::new(0);
+        }
+        // Each recount returns one more CPU than the last.
+        fn count() -> usize {
+            COUNTS.with(|counts| {
+                counts.set(counts.get() + 1);
+                counts.get()
+            })
+        }
+
+        let cache = super::TtlCache {
+            cpus: AtomicUsize::new(0),
+            at: AtomicUsize::new(0),
+        };
+        // The clock is faked by passing `now` explicitly.
+        assert_eq!(cache.get(1000, 100, count), 1);
+        assert_eq!(cache.get(1099, 100, count), 1);
+        assert_eq!(cache.get(1100, 100, count), 2);
+        assert_eq!(cache.get(1150, 100, count), 2);
+        assert_eq!(cache.get(1150, 0, count), 3);
+
+        // the clock wrapping around
+        cache.at.store(::std::usize::MAX - 9, Ordering::SeqCst);
+        assert_eq!(cache.get(40, 100, count), 3);
+        assert_eq!(cache.get(90, 100, count), 4);
+
+        assert_eq!(super::millis(Duration::new(2, 345_678_901)), 2345);
+        assert_eq!(super::millis(Duration::from_secs(::std::u64::MAX)), ::std::usize::MAX);
+
+        let cpus = super::get_cached_with_ttl(Duration::from_secs(60));
+        assert!(cpus >= 1);
+        assert_eq!(super::get_cached_with_ttl(Duration::from_secs(60)), cpus);
+    }
+
+    #[test]
+    fn test_full_topology() {
+        let topology = super::full_topology();
+        let mut ids: Vec<usize> = topology
+            .nodes
+            .iter()
+            .flat_map(|node| node.cores.iter())
+            .flat_map(|core| core.cpus.iter().map(|cpu| cpu.id))
+            .collect();
+        assert!(!ids.is_empty());
+        let cpus = ids.len();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), cpus);
+
+        let uniform = super::uniform_topology();
+        let threads: Vec<usize> = uniform.nodes[0].cores.iter().map(|core| core.cpus.len()).collect();
+        assert_eq!(threads, super::threads_per_core_detailed());
+    }
+
+    #[test]
+    fn test_topology() {
+        let topology = super::topology();
+        assert!(topology.sockets >= 1);
+        assert!(topology.sockets <= topology.physical_cores);
+        assert!(topology.logical_cores >= 1);
+    }
+
+    #[test]
+    fn test_try_get() {
+        use Error;
+
+        assert_eq!(super::try_get().unwrap_or(1), super::get());
+        match super::try_get_physical() {
+            Ok(cores) => assert_eq!(Some(cores), super::try_physical()),
+            Err(err) => assert!(err == Error::Parse || err == Error::Unsupported),
+        }
+
+        assert_eq!(Error::Os(1).to_string(), "system call failed with errno 1");
+        assert_eq!(Error::Unsupported.to_string(), "unsupported platform");
+    }
+
+    #[test]
+    fn test_cached_cpus() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        fn three() -> usize {
+            3
+        }
+
+        let cache = AtomicUsize::new(0);
+        let detector = AtomicUsize::new(0);
+        let cpus = super::cached_cpus(&cache, &detector);
+        assert_eq!(cpus, super::uncached_cpus(&detector));
+        if let Ok(cpus) = cpus {
+            assert_eq!(cache.load(Ordering::SeqCst), cpus);
+        }
+
+        // a stale count is returned until refreshed
+        cache.store(1000, Ordering::SeqCst);
+        assert_eq!(super::cached_cpus(&cache, &detector), Ok(1000));
+
+        // the detector is called every time
+        super::store_detector(&detector, three);
+        assert_eq!(super::cached_cpus(&cache, &detector), Ok(super::env_bounds(3)));
+        assert_eq!(cache.load(Ordering::SeqCst), 1000);
+
+        if option_env!("NUM_CPUS_COMPILE_TIME").is_none() {
+            assert_eq!(super::get_uncached(), super::refresh());
+            assert_eq!(super::get(), super::get_uncached());
+        }
+    }
+
+    #[test]
+    fn test_refreshed_cpus() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        fn five() -> usize {
+            5
+        }
+
+        let cache = AtomicUsize::new(1000);
+        let detector = AtomicUsize::new(0);
+        let cpus = super::refreshed_cpus(&cache, &detector);
+        assert_eq!(Ok(cpus), super::uncached_cpus(&detector));
+        assert_eq!(cache.load(Ordering::SeqCst), cpus);
+
+        // the detector wins over the platform, and isn't cached
+        super::store_detector(&detector, five);
+        cache.store(1000, Ordering::SeqCst);
+        assert_eq!(super::refreshed_cpus(&cache, &detector), super::env_bounds(5));
+        assert_eq!(cache.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_detector() {
+        use std::sync::atomic::AtomicUsize;
+
+        fn seven() -> usize {
+            7
+        }
+        fn none() -> usize {
+            0
+        }
+
+        let detector = AtomicUsize::new(0);
+        assert!(super::load_detector(&detector).is_none());
+        assert_eq!(super::detected_cpus(&detector), super::try_num_cpus());
+
+        super::store_detector(&detector, seven);
+        assert_eq!(super::detected_cpus(&detector), Ok(7));
+
+        // the last one wins, and get() still counts at least 1
+        super::store_detector(&detector, none);
+        assert_eq!(super::detected_cpus(&detector), Ok(0));
+        assert_eq!(super::detected_cpus(&detector).map(super::clamp_min_one), Ok(1));
+    }
+
+    #[test]
+    fn test_global_hint_concurrent() {
+        use std::sync::atomic::AtomicUsize;
+        use std::sync::Arc;
+        use std::thread;
+
+        let hint = Arc::new(AtomicUsize::new(0));
+        let cpus = super::get();
+
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let hint = hint.clone();
+                thread::spawn(move || {
+                    let set = i % 2 == 0 && super::store_hint(&hint, 100 + i);
+                    let read = super::load_hint(&hint);
+                    (set, read)
+                })
+            })
+            .collect();
+        let threads: Vec<_> = threads.into_iter().map(|thread| thread.join().unwrap()).collect();
+
+        // exactly one of the writers wins, and nobody reads anything else
+        let winner = hint.load(::std::sync::atomic::Ordering::SeqCst);
+        assert_eq!(threads.iter().filter(|&&(set, _)| set).count(), 1);
+        assert!(threads.iter().all(|&(_, read)| read == winner || read == cpus));
+    }
+
+    #[test]
+    fn test_builder() {
+        use super::Rounding;
+
+        assert_eq!(super::Builder::new().get(), super::get());
+        assert_eq!(super::Builder::new().exclude_nohz_full(false).get(), super::get());
+
+        let cpus = super::Builder::new().exclude_nohz_full(true).get();
+        assert!(cpus >= 1 && cpus <= super::get());
+
+        assert_eq!(super::Builder::new().rounding(Rounding::Ceil).get(), super::get());
+        let cpus = super::Builder::new().rounding(Rounding::Nearest(0.5)).get();
+        assert!(cpus >= 1 && cpus <= super::get());
+
+        let capped = super::Builder::new().cgroup_as_hint(false).get();
+        assert_eq!(capped, super::get());
+        assert!(super::Builder::new().cgroup_as_hint(true).get() >= capped);
+    }
+
+    #[test]
+    fn test_round_quota() {
+        use super::{round_quota, Rounding};
+
+        let nearest = Rounding::Nearest(0.5);
+        assert_eq!(round_quota(2.05, nearest), 2);
+        assert_eq!(round_quota(2.5, nearest), 3);
+        assert_eq!(round_quota(2.95, nearest), 3);
+        assert_eq!(round_quota(2.0, nearest), 2);
+
+        for &quota in &[2.05, 2.5, 2.95] {
+            assert_eq!(round_quota(quota, Rounding::Ceil), 3);
+            assert_eq!(round_quota(quota, Rounding::Nearest(0.0)), 3);
+            assert_eq!(round_quota(quota, Rounding::Nearest(1.5)), 2);
+        }
+        assert_eq!(round_quota(2.0, Rounding::Ceil), 2);
+        assert_eq!(round_quota(0.5, nearest), 1);
+    }
+
+    #[test]
+    fn test_get_physical_with_source() {
+        let (physical, source) = super::get_physical_with_source();
+
+        assert_eq!(physical, super::get_physical());
+        if source == super::Source::Logical {
+            assert_eq!(super::try_physical(), None);
+        } else {
+            assert_eq!(super::try_physical(), Some(physical));
+        }
+    }
+
+    #[test]
+    fn test_get_hardware() {
+        let hardware = super::get_hardware();
+
+        assert!(hardware >= super::get());
+        assert!(hardware >= super::get_online_live());
+    }
+
+    #[test]
+    fn test_available_and_installed() {
+        let (available, installed) = super::available_and_installed();
+
+        assert_eq!(available, super::get());
+        assert_eq!(installed, super::get_hardware());
+        assert!(available <= installed);
+    }
+
+    #[test]
+    fn test_worker_pool_hint() {
+        let hint = super::worker_pool_hint();
+        let grouped: usize = hint.numa_groups.iter().map(|cpus| cpus.len()).sum();
+
+        assert!(hint.worker_threads >= 1);
+        assert!(hint.worker_threads <= hint.pin_candidates.len());
+        assert!(hint.blocking_threads >= hint.worker_threads);
+        assert_eq!(grouped, hint.pin_candidates.len());
+        assert!(hint.numa_groups.iter().all(|cpus| !cpus.is_empty()));
+    }
+
+    #[test]
+    fn test_detect() {
+        let info = super::detect();
+
+        assert_eq!(info.available, super::get());
+        assert!(info.available >= 1 && info.available <= info.logical);
+        assert!(info.physical >= 1 && info.physical <= info.logical);
+        assert!(info.numa_nodes >= 1);
+        assert!(info.sockets >= 1 && info.sockets <= info.physical);
+        if let Some((performance, efficiency)) = info.hybrid {
+            assert!(performance >= 1 && efficiency >= 1);
+        }
+    }
+
+    #[test]
+    fn test_cpu_info_eq() {
+        use std::collections::HashSet;
+
+        let info = super::CpuInfo {
+            logical: 8,
+            physical: 4,
+            available: 8,
+            numa_nodes: 1,
+            sockets: 1,
+            hybrid: None,
+        };
+        let hybrid = super::CpuInfo { hybrid: Some((4, 4)), ..info };
+
+        assert_eq!(info, info.clone());
+        assert!(info != hybrid);
+
+        let mut seen = HashSet::new();
+        assert!(seen.insert(info));
+        assert!(seen.insert(hybrid));
+        assert!(!seen.insert(info));
+    }
+
+    #[test]
+    #[cfg(feature = "env_override")]
+    fn test_env_bounds() {
+        use super::{clamp_to_bounds, parse_bound};
+
+        // min only
+        assert_eq!(clamp_to_bounds(2, Some(4), None), 4);
+        assert_eq!(clamp_to_bounds(8, Some(4), None), 8);
+        // max only
+        assert_eq!(clamp_to_bounds(64, None, Some(8)), 8);
+        assert_eq!(clamp_to_bounds(4, None, Some(8)), 4);
+        // both
+        assert_eq!(clamp_to_bounds(1, Some(2), Some(8)), 2);
+        assert_eq!(clamp_to_bounds(64, Some(2), Some(8)), 8);
+        assert_eq!(clamp_to_bounds(4, Some(2), Some(8)), 4);
+        assert_eq!(clamp_to_bounds(4, Some(8), Some(2)), 2);
+        assert_eq!(clamp_to_bounds(4, None, None), 4);
+
+        assert_eq!(parse_bound(Some("8".into())), Some(8));
+        assert_eq!(parse_bound(Some(" 8\n".into())), Some(8));
+        assert_eq!(parse_bound(Some("0".into())), None);
+        assert_eq!(parse_bound(Some("-1".into())), None);
+        assert_eq!(parse_bound(Some("eight".into())), None);
+        assert_eq!(parse_bound(None), None);
+    }
+
+    #[test]
+    #[cfg(feature = "env_override")]
+    fn test_env_cap() {
+        use super::cap_cpus;
+
+        assert_eq!(cap_cpus(8, Some("4".into())), 4);
+        assert_eq!(cap_cpus(8, Some(" 4\n".into())), 4);
+        // clamped to the detected count
+        assert_eq!(cap_cpus(8, Some("64".into())), 8);
+        // ignored
+        assert_eq!(cap_cpus(8, Some("0".into())), 8);
+        assert_eq!(cap_cpus(8, Some("-1".into())), 8);
+        assert_eq!(cap_cpus(8, Some("four".into())), 8);
+        assert_eq!(cap_cpus(8, Some("".into())), 8);
+        assert_eq!(cap_cpus(8, None), 8);
+    }
+
+    #[test]
+    fn test_get_reserved() {
+        assert_eq!(super::reserve_cpus(8, 0), 8);
+        assert_eq!(super::reserve_cpus(8, 1), 7);
+        // single core
+        assert_eq!(super::reserve_cpus(1, 0), 1);
+        assert_eq!(super::reserve_cpus(1, 1), 1);
+        // reserving more than there are
+        assert_eq!(super::reserve_cpus(4, 16), 1);
+
+        assert!(super::get_reserved(1) >= 1);
+        assert!(super::get_reserved(1) <= super::get());
+    }
+
+    #[cfg(all(target_os = "wasi", not(target_feature = "atomics")))]
+    #[test]
+    fn test_get_wasi_without_threads() {
+        assert_eq!(super::get(), 1);
+    }
+
+    #[cfg(target_os = "uefi")]
+    #[test]
+    fn test_get_uefi() {
+        assert_eq!(super::get(), 1);
+        assert_eq!(super::get_physical(), 1);
+    }
+
+    #[cfg(all(target_os = "l4re", not(feature = "compact")))]
+    #[test]
+    fn test_get_l4re() {
+        assert_eq!(super::get(), 1);
+        assert_eq!(super::get_physical(), 1);
+    }
+
+    #[cfg(any(target_os = "tvos", target_os = "watchos"))]
+    #[test]
+    fn test_get_tvos_watchos() {
+        // Only the simulators aren't ARM
+        let name = if cfg!(any(target_arch = "arm", target_arch = "aarch64")) {
+            ::libc::_SC_NPROCESSORS_CONF
+        } else {
+            ::libc::_SC_NPROCESSORS_ONLN
+        };
+        let cpus = unsafe { ::libc::sysconf(name) };
+        assert_eq!(super::get(), cpus as usize);
+        assert!(super::get_physical() <= super::get());
+    }
+
+    #[cfg(target_os = "cygwin")]
+    #[test]
+    fn test_get_cygwin() {
+        let online = unsafe { ::libc::sysconf(::libc::_SC_NPROCESSORS_ONLN) };
+        assert_eq!(super::get(), online as usize);
+        assert!(super::get_hardware() >= super::get());
+    }
+
+    #[cfg(target_os = "trusty")]
+    #[test]
+    fn test_get_trusty() {
+        assert_eq!(super::get(), 1);
+        assert_eq!(super::get_physical(), 1);
+    }
+
+    #[cfg(all(target_os = "horizon", target_arch = "arm"))]
+    #[test]
+    fn test_get_3ds() {
+        let num = super::get();
+        assert!(num >= 1 && num <= 4);
+    }
+
+    #[cfg(all(target_os = "horizon", target_arch = "aarch64", not(feature = "compact")))]
+    #[test]
+    fn test_get_horizon() {
+        assert_eq!(super::get(), 3);
+        assert_eq!(super::get_physical(), 3);
+    }
 }
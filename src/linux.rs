@@ -1,6 +1,7 @@
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufRead, BufReader, Read};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+#[cfg(not(feature = "no_affinity"))]
 use std::mem;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicUsize, Ordering};
@@ -8,14 +9,8 @@ use std::sync::Once;
 
 use libc;
 
-macro_rules! debug {
-    ($($args:expr),*) => ({
-        if false {
-        //if true {
-            println!($($args),*);
-        }
-    });
-}
+use cpu_list::{parse_cpu_list, MAX_CPUS};
+use CpuStates;
 
 macro_rules! some {
     ($e:expr) => {{
@@ -29,567 +24,3450 @@ macro_rules! some {
     }};
 }
 
+// The calls the fallbacks of `logical_cpus()` depend on. The tests can swap them
+// for failing stubs with `tests::fault`, other builds call libc directly.
+#[cfg(not(test))]
+#[allow(unused_imports)]
+mod sys {
+    pub use libc::{sched_getaffinity, sysconf};
+}
+#[cfg(test)]
+use self::tests::fault as sys;
+
+/// Where sysfs is mounted. Everything reading it takes the root as a
+/// parameter instead, so the tests can point it at `fixtures/sysfs/*`.
+const SYSFS: &'static str = "/sys";
+
 pub fn get_num_cpus() -> usize {
-    match cgroups_num_cpus() {
-        Some(n) => n,
-        None => logical_cpus(),
+    try_num_cpus().unwrap_or(1)
+}
+
+pub fn try_num_cpus() -> Result<usize, ::Error> {
+    // The affinity can change at runtime, so it's checked on every call
+    // rather than cached along with the quota.
+    try_logical_cpus().map(|cpus| {
+        let quota = cached_cgroups_quota();
+        let count = capped_by_quota(cpus, quota);
+        debug!("logical CPUs: {}, cgroups quota: {:?}, counting {}", cpus, quota, count);
+        count
+    })
+}
+
+// The kernel already narrows the affinity down to the cpuset, so only the
+// quota is left out.
+pub fn get_num_cpus_quota_as_hint() -> usize {
+    logical_cpus()
+}
+
+fn capped_by_quota(cpus: usize, quota: Option<usize>) -> usize {
+    match quota {
+        Some(quota) => ::std::cmp::min(quota, cpus),
+        None => cpus,
+    }
+}
+
+pub fn get_num_cpus_with_source() -> (usize, ::LogicalSource) {
+    let (logical, source) = logical_cpus_with_source(affinity_query());
+    match cached_cgroups_quota() {
+        Some(quota) if quota < logical => (quota, ::LogicalSource::CgroupQuota),
+        _ => (logical, source),
     }
 }
 
 fn logical_cpus() -> usize {
-    let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
-    if unsafe { libc::sched_getaffinity(0, mem::size_of::<libc::cpu_set_t>(), &mut set) } == 0 {
-        let mut count: u32 = 0;
-        for i in 0..libc::CPU_SETSIZE as usize {
-            if unsafe { libc::CPU_ISSET(i, &set) } {
-                count += 1
-            }
-        }
-        count as usize
-    } else {
-        let cpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
-        if cpus < 1 {
-            1
-        } else {
-            cpus as usize
-        }
+    try_logical_cpus().unwrap_or(1)
+}
+
+fn try_logical_cpus() -> Result<usize, ::Error> {
+    if let Some(count) = affinity_count() {
+        debug!("counting the sched affinity: {}", count);
+        return Ok(count);
     }
+
+    debug!("sched affinity unavailable, counting with sysconf");
+    try_unaffine_cpus()
 }
 
-pub fn get_num_physical_cpus() -> usize {
-    let file = match File::open("/proc/cpuinfo") {
-        Ok(val) => val,
-        Err(_) => return get_num_cpus(),
-    };
-    let reader = BufReader::new(file);
-    let mut map = HashMap::new();
-    let mut physid: u32 = 0;
-    let mut cores: usize = 0;
-    let mut chgcount = 0;
-    for line in reader.lines().filter_map(|result| result.ok()) {
-        let mut it = line.split(':');
-        let (key, value) = match (it.next(), it.next()) {
-            (Some(key), Some(value)) => (key.trim(), value.trim()),
-            _ => continue,
-        };
-        if key == "physical id" {
-            match value.parse() {
-                Ok(val) => physid = val,
-                Err(_) => break,
-            };
-            chgcount += 1;
-        }
-        if key == "cpu cores" {
-            match value.parse() {
-                Ok(val) => cores = val,
-                Err(_) => break,
-            };
-            chgcount += 1;
-        }
-        if chgcount == 2 {
-            map.insert(physid, cores);
-            chgcount = 0;
+/// Like `logical_cpus()`, for the result of `affinity_query()`.
+fn logical_cpus_with_source(affinity: Result<usize, i32>) -> (usize, ::LogicalSource) {
+    match affinity {
+        Ok(count) => (count, ::LogicalSource::Affinity),
+        Err(errno) => {
+            let blocked = errno == libc::EPERM || errno == libc::ENOSYS;
+            if blocked {
+                debug!("sched_getaffinity blocked, errno {}", errno);
+            }
+            (unaffine_cpus(), ::LogicalSource::Sysconf { affinity_blocked: blocked })
         }
     }
-    let count = map.into_iter().fold(0, |acc, (_, cores)| acc + cores);
+}
 
-    if count == 0 {
-        get_num_cpus()
+/// The CPUs to count when the affinity can't be read.
+fn unaffine_cpus() -> usize {
+    try_unaffine_cpus().unwrap_or(1)
+}
+
+#[cfg(not(all(target_env = "ohos", any(target_arch = "arm", target_arch = "aarch64"))))]
+fn try_unaffine_cpus() -> Result<usize, ::Error> {
+    try_online_cpus()
+}
+
+// OpenHarmony is Linux with a musl based libc. Like on Android, idle cores of
+// ARM devices are turned off to save power, and come back when there's work,
+// so the configured ones are counted rather than the online ones.
+#[cfg(all(target_env = "ohos", any(target_arch = "arm", target_arch = "aarch64")))]
+fn try_unaffine_cpus() -> Result<usize, ::Error> {
+    let cpus = unsafe { sys::sysconf(libc::_SC_NPROCESSORS_CONF) };
+    debug!("sysconf(_SC_NPROCESSORS_CONF): {}", cpus);
+    if cpus >= 1 {
+        Ok(cpus as usize)
     } else {
-        count
+        try_online_cpus()
     }
 }
 
-/// Cached CPUs calculated from cgroups.
-///
-/// If 0, check logical cpus.
-// Allow deprecation warnings, we want to work on older rustc
-#[allow(warnings)]
-static CGROUPS_CPUS: AtomicUsize = ::std::sync::atomic::ATOMIC_USIZE_INIT;
+fn online_cpus() -> usize {
+    try_online_cpus().unwrap_or(1)
+}
+
+fn try_online_cpus() -> Result<usize, ::Error> {
+    let cpus = unsafe { sys::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+    debug!("sysconf(_SC_NPROCESSORS_ONLN): {}", cpus);
+    if cpus >= 1 {
+        return Ok(cpus as usize);
+    }
+    let errno = io::Error::last_os_error().raw_os_error().unwrap_or(0);
 
-fn cgroups_num_cpus() -> Option<usize> {
-    #[allow(warnings)]
-    static ONCE: Once = ::std::sync::ONCE_INIT;
+    // Some seccomp sandboxes block what sysconf needs, but still let /proc
+    // be read.
+    match cpus_from_cpuinfo("/proc/cpuinfo").or_else(|| cpus_from_proc_stat("/proc/stat")) {
+        Some(cpus) => Ok(cpus),
+        None => Err(::Error::Os(errno)),
+    }
+}
 
-    ONCE.call_once(init_cgroups);
+fn cpus_from_cpuinfo<P: AsRef<Path>>(cpuinfo: P) -> Option<usize> {
+    let cpuinfo = some!(read_file(cpuinfo));
 
-    let cpus = CGROUPS_CPUS.load(Ordering::Acquire);
+    // One `processor : N` line per online CPU. Older Arm kernels also print a
+    // `Processor : ARMv7 ...` line with the model, which isn't one.
+    let count = cpuinfo
+        .lines()
+        .filter(|line| match line.find(':') {
+            Some(colon) => line[..colon].trim() == "processor",
+            None => false,
+        })
+        .count();
 
-    if cpus > 0 {
-        Some(cpus)
-    } else {
+    debug!("/proc/cpuinfo cpus: {}", count);
+    if count == 0 {
         None
+    } else {
+        Some(count)
     }
 }
 
-fn init_cgroups() {
-    // Should only be called once
-    debug_assert!(CGROUPS_CPUS.load(Ordering::SeqCst) == 0);
+fn cpus_from_proc_stat<P: AsRef<Path>>(stat: P) -> Option<usize> {
+    let stat = some!(read_file(stat));
+
+    // The first line is the total, named just "cpu", followed by one "cpuN"
+    // line per online CPU.
+    let count = stat
+        .lines()
+        .filter(|line| line.starts_with("cpu"))
+        .filter(|line| match line.as_bytes().get(3) {
+            Some(&b) => b >= b'0' && b <= b'9',
+            None => false,
+        })
+        .count();
 
-    // Fails in Miri by default (cannot open files), and Miri does not have parallelism anyway.
-    if cfg!(miri) {
-        return;
+    debug!("/proc/stat cpus: {}", count);
+    if count == 0 {
+        None
+    } else {
+        Some(count)
     }
+}
 
-    if let Some(quota) = load_cgroups("/proc/self/cgroup", "/proc/self/mountinfo") {
-        if quota == 0 {
-            return;
-        }
+fn affinity_count() -> Option<usize> {
+    affinity_query().ok()
+}
 
-        let logical = logical_cpus();
-        let count = ::std::cmp::min(quota, logical);
+/// The number of CPUs in the current thread's affinity mask, or the errno
+/// `sched_getaffinity` failed with.
+#[cfg(not(feature = "no_affinity"))]
+fn affinity_query() -> Result<usize, i32> {
+    let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+    if unsafe { sys::sched_getaffinity(0, mem::size_of::<libc::cpu_set_t>(), &mut set) } != 0 {
+        return Err(io::Error::last_os_error().raw_os_error().unwrap_or(0));
+    }
 
-        CGROUPS_CPUS.store(count, Ordering::SeqCst);
+    let mut count: u32 = 0;
+    for i in 0..libc::CPU_SETSIZE as usize {
+        if unsafe { libc::CPU_ISSET(i, &set) } {
+            count += 1
+        }
     }
+    debug!("sched_getaffinity: {}", count);
+    Ok(count as usize)
 }
 
-fn load_cgroups<P1, P2>(cgroup_proc: P1, mountinfo_proc: P2) -> Option<usize>
-where
-    P1: AsRef<Path>,
-    P2: AsRef<Path>,
-{
-    let subsys = some!(Subsys::load_cpu(cgroup_proc));
-    let mntinfo = some!(MountInfo::load_cpu(mountinfo_proc, subsys.version));
-    let cgroup = some!(Cgroup::translate(mntinfo, subsys));
-    cgroup.cpu_quota()
+// Not a failure, so `0` rather than an errno.
+#[cfg(feature = "no_affinity")]
+fn affinity_query() -> Result<usize, i32> {
+    Err(0)
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum CgroupVersion {
-    V1,
-    V2,
+/// Returns the indices of the CPUs in the current thread's affinity mask.
+#[cfg(not(feature = "no_affinity"))]
+fn affinity_cpus() -> Option<Vec<usize>> {
+    let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+    if unsafe { sys::sched_getaffinity(0, mem::size_of::<libc::cpu_set_t>(), &mut set) } != 0 {
+        return None;
+    }
+
+    Some((0..libc::CPU_SETSIZE as usize)
+        .filter(|&i| unsafe { libc::CPU_ISSET(i, &set) })
+        .collect())
 }
 
-struct Cgroup {
-    version: CgroupVersion,
-    base: PathBuf,
+#[cfg(feature = "no_affinity")]
+fn affinity_cpus() -> Option<Vec<usize>> {
+    None
 }
 
-struct MountInfo {
-    version: CgroupVersion,
-    root: String,
-    mount_point: String,
+pub fn get_affinity_cpus() -> Vec<usize> {
+    match affinity_cpus() {
+        Some(cpus) => cpus,
+        None => (0..logical_cpus()).collect(),
+    }
 }
 
-struct Subsys {
-    version: CgroupVersion,
-    base: String,
+pub fn get_affinity_mask() -> Vec<u64> {
+    match affinity_cpus() {
+        Some(cpus) => mask_of(&cpus, libc::CPU_SETSIZE as usize),
+        None => ::first_cpus_mask(logical_cpus()),
+    }
 }
 
-impl Cgroup {
-    fn new(version: CgroupVersion, dir: PathBuf) -> Cgroup {
-        Cgroup { version: version, base: dir }
+/// The words of a `cpu_set_t` of `setsize` CPUs, with the bits of `cpus` set.
+fn mask_of(cpus: &[usize], setsize: usize) -> Vec<u64> {
+    let mut words = vec![0u64; (setsize + 63) / 64];
+    for &cpu in cpus {
+        if cpu < setsize {
+            words[cpu / 64] |= 1 << (cpu % 64);
+        }
     }
+    words
+}
 
-    fn translate(mntinfo: MountInfo, subsys: Subsys) -> Option<Cgroup> {
-        // Translate the subsystem directory via the host paths.
-        debug!(
-            "subsys = {:?}; root = {:?}; mount_point = {:?}",
-            subsys.base, mntinfo.root, mntinfo.mount_point
-        );
+pub fn get_num_schedulable_cpus() -> usize {
+    let count = schedulable_cpu_list().len();
+    debug!("schedulable CPUs: {}", count);
+    ::std::cmp::max(1, count)
+}
 
-        let rel_from_root = some!(Path::new(&subsys.base).strip_prefix(&mntinfo.root).ok());
+pub fn get_allowed_cpus_per_node() -> Vec<Vec<usize>> {
+    allowed_cpus_per_node(&schedulable_cpu_list(), node_cpus_from_sysfs(SYSFS))
+}
 
-        debug!("rel_from_root: {:?}", rel_from_root);
+fn allowed_cpus_per_node(allowed: &[usize], nodes: Option<Vec<Vec<usize>>>) -> Vec<Vec<usize>> {
+    let nodes = match nodes {
+        Some(nodes) => nodes,
+        None => return vec![allowed.to_vec()],
+    };
 
-        // join(mp.MountPoint, relPath)
-        let mut path = PathBuf::from(mntinfo.mount_point);
-        path.push(rel_from_root);
-        Some(Cgroup::new(mntinfo.version, path))
+    let groups: Vec<Vec<usize>> = nodes
+        .into_iter()
+        .map(|cpus| cpus.into_iter().filter(|cpu| allowed.contains(cpu)).collect::<Vec<_>>())
+        .filter(|cpus| !cpus.is_empty())
+        .collect();
+
+    // The nodes don't list the allowed CPUs, such as in a sandbox with a fake sysfs.
+    if groups.is_empty() {
+        vec![allowed.to_vec()]
+    } else {
+        groups
     }
+}
 
-    fn cpu_quota(&self) -> Option<usize> {
-        let (quota_us, period_us) = match self.version {
-            CgroupVersion::V1 => (some!(self.quota_us()), some!(self.period_us())),
-            CgroupVersion::V2 => some!(self.max()),
-        };
+fn schedulable_cpu_list() -> Vec<usize> {
+    let affinity = match affinity_cpus() {
+        Some(cpus) => cpus,
+        None => (0..logical_cpus()).collect(),
+    };
+    let cpuset = if cfg!(miri) {
+        None
+    } else {
+        load_cpuset("/proc/self/cgroup", "/proc/self/mountinfo")
+    };
+    let isolated = isolated_cpus(SYSFS);
 
-        // protect against dividing by zero
-        if period_us == 0 {
-            return None;
-        }
+    let cpuset = cpuset.as_ref().map(|cpus| &cpus[..]);
 
-        // Ceil the division, since we want to be able to saturate
-        // the available CPUs, and flooring would leave a CPU un-utilized.
+    schedulable_cpus(&affinity, cpuset, &isolated)
+}
 
-        Some((quota_us as f64 / period_us as f64).ceil() as usize)
-    }
+pub fn get_restriction_reason() -> Option<::Restriction> {
+    let cpuset = if cfg!(miri) {
+        None
+    } else {
+        load_cpuset("/proc/self/cgroup", "/proc/self/mountinfo")
+    };
 
-    fn quota_us(&self) -> Option<usize> {
-        self.param("cpu.cfs_quota_us")
+    restriction(
+        online_cpus(),
+        affinity_count(),
+        cpuset.map(|cpus| cpus.len()),
+        cached_cgroups_quota(),
+    )
+}
+
+/// Which of the limits, if any, brings the count below the `online` CPUs.
+///
+/// The kernel already narrows the affinity mask down to the cpuset, so an
+/// affinity no smaller than the cpuset is blamed on the cpuset.
+fn restriction(
+    online: usize,
+    affinity: Option<usize>,
+    cpuset: Option<usize>,
+    quota: Option<usize>,
+) -> Option<::Restriction> {
+    let affinity = affinity.unwrap_or(online);
+    let quota = quota.unwrap_or(online);
+
+    if quota < affinity && quota < online {
+        return Some(::Restriction::CgroupQuota);
+    }
+    if affinity >= online {
+        return None;
     }
 
-    fn period_us(&self) -> Option<usize> {
-        self.param("cpu.cfs_period_us")
+    match cpuset {
+        Some(cpuset) if cpuset <= affinity => Some(::Restriction::Cpuset),
+        _ => Some(::Restriction::Affinity),
     }
+}
 
-    fn max(&self) -> Option<(usize, usize)> {
-        let max = some!(self.raw_param("cpu.max"));
-        let mut max = some!(max.lines().next()).split(' ');
+pub fn get_nohz_full_cpus() -> Vec<usize> {
+    nohz_full_cpus(SYSFS)
+}
 
-        let quota = some!(max.next().and_then(|quota| quota.parse().ok()));
-        let period = some!(max.next().and_then(|period| period.parse().ok()));
+// Without `nohz_full=`, the kernel prints an empty line, or `(null)` before 4.2,
+// and both parse to no CPUs.
+fn nohz_full_cpus<P: AsRef<Path>>(sys: P) -> Vec<usize> {
+    let nohz_full = read_file(sys.as_ref().join("devices/system/cpu/nohz_full"));
+    nohz_full.map(|list| parse_cpu_list(&list)).unwrap_or(Vec::new())
+}
 
-        Some((quota, period))
-    }
+pub fn get_max_threads() -> Option<usize> {
+    max_threads_from_limits("/proc/self/limits")
+}
 
-    fn param(&self, param: &str) -> Option<usize> {
-        let buf = some!(self.raw_param(param));
+// The soft limit, which is the one `clone()` fails with `EAGAIN` against:
+//
+//     Limit                     Soft Limit           Hard Limit           Units
+//     Max processes             4096                 63429                processes
+fn max_threads_from_limits<P: AsRef<Path>>(limits: P) -> Option<usize> {
+    let limits = some!(read_file(limits));
+    let line = some!(limits.lines().find(|line| line.starts_with("Max processes")));
+    let soft = some!(line["Max processes".len()..].split_whitespace().next());
+    soft.parse().ok()
+}
 
-        buf.trim().parse().ok()
+pub fn get_num_cpus_excluding(cpus: usize, excluded: &[usize]) -> usize {
+    if excluded.is_empty() {
+        return cpus;
     }
+    match affinity_cpus() {
+        Some(allowed) => cpus_excluding(cpus, &allowed, excluded),
+        None => cpus,
+    }
+}
 
-    fn raw_param(&self, param: &str) -> Option<String> {
-        let mut file = some!(File::open(self.base.join(param)).ok());
+/// The lower of `cpus` and the `allowed` CPUs left once `excluded` are removed.
+fn cpus_excluding(cpus: usize, allowed: &[usize], excluded: &[usize]) -> usize {
+    let remaining = allowed.iter().filter(|cpu| !excluded.contains(cpu)).count();
+    ::std::cmp::min(cpus, remaining)
+}
 
-        let mut buf = String::new();
-        some!(file.read_to_string(&mut buf).ok());
+fn isolated_cpus<P: AsRef<Path>>(sys: P) -> Vec<usize> {
+    let isolated = read_file(sys.as_ref().join("devices/system/cpu/isolated"));
+    isolated.map(|list| parse_cpu_list(&list)).unwrap_or(Vec::new())
+}
 
-        Some(buf)
-    }
+/// The CPUs in the affinity mask that are also in the cgroup's cpuset, if
+/// there is one, and that haven't been isolated from the scheduler.
+fn schedulable_cpus(affinity: &[usize], cpuset: Option<&[usize]>, isolated: &[usize]) -> Vec<usize> {
+    affinity
+        .iter()
+        .cloned()
+        .filter(|cpu| cpuset.map_or(true, |cpuset| cpuset.contains(cpu)))
+        .filter(|cpu| !isolated.contains(cpu))
+        .collect()
 }
 
-impl MountInfo {
-    fn load_cpu<P: AsRef<Path>>(proc_path: P, version: CgroupVersion) -> Option<MountInfo> {
-        let file = some!(File::open(proc_path).ok());
-        let file = BufReader::new(file);
+pub fn try_num_physical_cpus() -> Option<usize> {
+    physical_cpus("/proc/cpuinfo", SYSFS)
+}
 
-        file.lines()
-            .filter_map(|result| result.ok())
-            .filter_map(MountInfo::parse_line)
-            .find(|mount_info| mount_info.version == version)
+pub fn get_num_physical_cpus() -> usize {
+    physical_cpus_or_logical("/proc/cpuinfo", SYSFS)
+}
+
+pub fn get_num_physical_cpus_with_source() -> (usize, ::Source) {
+    match physical_cpus_with_source("/proc/cpuinfo", SYSFS) {
+        Some(found) => found,
+        None => (get_num_cpus(), ::Source::Logical),
     }
+}
 
-    fn parse_line(line: String) -> Option<MountInfo> {
-        let mut fields = line.split(' ');
+// The chain is /proc/cpuinfo, then the sysfs siblings, then the logical CPUs.
+// gVisor (`runsc`) synthesizes a /proc/cpuinfo without `physical id`, `cpu cores`
+// or `apicid`, and `cpuN` directories without a topology, so it ends up with the
+// logical CPUs, which are what its vCPUs are anyway.
+//
+// Firecracker microVMs run a real guest kernel, which reports the topology of
+// the vCPUs as usual. With a kernel built without SMP support, there's none, and
+// this also ends up with the logical CPUs, from the sched affinity.
+//
+// There's no CPUID fallback for x86 when neither /proc nor /sys is mounted,
+// such as in a chroot: the intrinsics need a newer Rust than this crate supports.
+fn physical_cpus_or_logical<P1, P2>(cpuinfo: P1, sys: P2) -> usize
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    match physical_cpus(cpuinfo, sys) {
+        Some(n) => n,
+        None => {
+            debug!("physical cores unknown, using logical CPUs");
+            get_num_cpus()
+        }
+    }
+}
 
-        // 7 5 0:6 </> /sys/fs/cgroup/cpu,cpuacct rw,nosuid,nodev,noexec,relatime shared:7 - cgroup cgroup rw,cpu,cpuacct
-        let mnt_root = some!(fields.nth(3));
-        // 7 5 0:6 / </sys/fs/cgroup/cpu,cpuacct> rw,nosuid,nodev,noexec,relatime shared:7 - cgroup cgroup rw,cpu,cpuacct
-        let mnt_point = some!(fields.next());
+fn physical_cpus<P1, P2>(cpuinfo: P1, sys: P2) -> Option<usize>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    physical_cpus_with_source(cpuinfo, sys).map(|(n, _)| n)
+}
 
-        // Ignore all fields until the separator(-).
-        // Note: there could be zero or more optional fields before hyphen.
-        // See: https://man7.org/linux/man-pages/man5/proc.5.html
-        // 7 5 0:6 / /sys/fs/cgroup/cpu,cpuacct rw,nosuid,nodev,noexec,relatime shared:7 <-> cgroup cgroup rw,cpu,cpuacct
-        // Note: we cannot use `?` here because we need to support Rust 1.13.
-        match fields.find(|&s| s == "-") {
-            Some(_) => {}
-            None => return None,
-        };
+fn physical_cpus_with_source<P1, P2>(cpuinfo: P1, sys: P2) -> Option<(usize, ::Source)>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    physical_topology(cpuinfo, sysfs_topology(sys).as_ref()).map(|(_, cores, source)| (cores, source))
+}
 
-        // 7 5 0:6 / /sys/fs/cgroup/cpu,cpuacct rw,nosuid,nodev,noexec,relatime shared:7 - <cgroup> cgroup rw,cpu,cpuacct
-        let version = match fields.next() {
-            Some("cgroup") => CgroupVersion::V1,
-            Some("cgroup2") => CgroupVersion::V2,
-            _ => return None,
-        };
+pub fn get_physical_disagreement() -> Option<(usize, usize)> {
+    physical_disagreement("/proc/cpuinfo", SYSFS)
+}
 
-        // cgroups2 only has a single mount point
-        if version == CgroupVersion::V1 {
-            // 7 5 0:6 / /sys/fs/cgroup/cpu,cpuacct rw,nosuid,nodev,noexec,relatime shared:7 - cgroup cgroup <rw,cpu,cpuacct>
-            let super_opts = some!(fields.nth(1));
+fn physical_disagreement<P1, P2>(cpuinfo: P1, sys: P2) -> Option<(usize, usize)>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let sysfs = some!(sysfs_topology(sys));
+    let (_, cpuinfo) = some!(topology_from_cpuinfo(cpuinfo, sysfs.max_threads_per_core()));
+    let sysfs = some!(sysfs.cores());
+    if cpuinfo == sysfs {
+        None
+    } else {
+        Some((cpuinfo, sysfs))
+    }
+}
 
-            // We only care about the 'cpu' option
-            if !super_opts.split(',').any(|opt| opt == "cpu") {
-                return None;
-            }
+/// The `(packages, cores)` of the system, and where the cores were counted, from
+/// one parse of `/proc/cpuinfo` and the walk of sysfs in `sysfs`.
+///
+/// The threads of a core are told apart by APIC ID with the thread siblings of
+/// sysfs, and sysfs wins when the two disagree.
+fn physical_topology<P: AsRef<Path>>(
+    cpuinfo: P,
+    sysfs: Option<&SysfsTopology>,
+) -> Option<(usize, usize, ::Source)> {
+    let threads_per_core = sysfs.and_then(SysfsTopology::max_threads_per_core);
+    let from_sysfs = sysfs
+        .and_then(|sysfs| sysfs.cores_per_package.as_ref())
+        .map(|packages| (packages.len(), packages.iter().sum()));
+
+    match (topology_from_cpuinfo(cpuinfo, threads_per_core), from_sysfs) {
+        // Firmware bugs and hypervisors can make `cpu cores` wrong, while the
+        // siblings in sysfs are the topology the kernel actually schedules on.
+        (Some((_, cpuinfo)), Some((packages, sysfs))) if cpuinfo != sysfs => {
+            debug!("physical cores disagree, /proc/cpuinfo: {}, sysfs: {}", cpuinfo, sysfs);
+            Some((packages, sysfs, ::Source::Sysfs))
         }
-
-        Some(MountInfo {
-            version: version,
-            root: mnt_root.to_owned(),
-            mount_point: mnt_point.to_owned(),
-        })
+        (Some((packages, n)), _) => {
+            debug!("physical cores from /proc/cpuinfo: {}", n);
+            Some((packages, n, ::Source::Cpuinfo))
+        }
+        (None, Some((packages, n))) => {
+            debug!("physical cores from sysfs: {}", n);
+            Some((packages, n, ::Source::Sysfs))
+        }
+        (None, None) => None,
     }
 }
 
-impl Subsys {
-    fn load_cpu<P: AsRef<Path>>(proc_path: P) -> Option<Subsys> {
-        let file = some!(File::open(proc_path).ok());
-        let file = BufReader::new(file);
-
-        file.lines()
-            .filter_map(|result| result.ok())
-            .filter_map(Subsys::parse_line)
-            .fold(None, |previous, line| {
-                // already-found v1 trumps v2 since it explicitly specifies its controllers
-                if previous.is_some() && line.version == CgroupVersion::V2 {
-                    return previous;
-                }
+// The cores of `/proc/cpuinfo` alone, as if sysfs were missing.
+#[cfg(test)]
+fn physical_cpus_from_cpuinfo<P: AsRef<Path>>(cpuinfo: P) -> Option<usize> {
+    topology_from_cpuinfo(cpuinfo, None).map(|(_, cores)| cores)
+}
 
-                Some(line)
+/// The `(packages, cores)` described by `/proc/cpuinfo`.
+///
+/// The packages are the distinct `physical id`s, or LoongArch `package`s, and
+/// `1` when it has neither but the cores could still be counted.
+///
+/// Without `cpu cores`, the cores are counted by APIC ID, shifting out the bits
+/// of the `threads_per_core`. Those aren't guessed when unknown, and the cores
+/// then aren't counted: the `ht` flag is set on every multi-core x86 CPU, with
+/// or without SMT, and the logical CPUs alone would overcount SMT cores.
+fn topology_from_cpuinfo<P: AsRef<Path>>(
+    cpuinfo: P,
+    threads_per_core: Option<usize>,
+) -> Option<(usize, usize)> {
+    let file = some!(open_file(cpuinfo).ok());
+    let reader = BufReader::new(file);
+    let mut map = HashMap::new();
+    // The `physical id` and `cpu cores` of the current processor block. They are
+    // paired within a block only, so a block missing one of them can't shift the
+    // pairs of the following ones.
+    let mut physid: Option<u32> = None;
+    let mut cores: Option<usize> = None;
+    // LoongArch has neither, but the `package` and `core` of each CPU instead.
+    let mut package: Option<u32> = None;
+    let mut core: Option<u32> = None;
+    let mut loongarch_cores = HashSet::new();
+    let mut packages = HashSet::new();
+    let mut apicids = Vec::new();
+    let mut initial_apicids = Vec::new();
+    for line in reader.lines().filter_map(|result| result.ok()) {
+        // Blocks are separated by an empty line, the last one may not be.
+        if line.trim().is_empty() {
+            physid = None;
+            cores = None;
+            package = None;
+            core = None;
+            continue;
+        }
+        let mut it = line.split(':');
+        let (key, value) = match (it.next(), it.next()) {
+            (Some(key), Some(value)) => (key.trim(), value.trim()),
+            _ => continue,
+        };
+        if key == "physical id" {
+            match value.parse() {
+                Ok(val) => physid = Some(val),
+                Err(_) => {
+                    debug!("can't parse the physical id {:?}", value);
+                    break;
+                }
+            };
+        }
+        if key == "cpu cores" {
+            match value.parse() {
+                Ok(val) => cores = Some(val),
+                Err(_) => {
+                    debug!("can't parse the cpu cores {:?}", value);
+                    break;
+                }
+            };
+        }
+        if let (Some(physid), Some(cores)) = (physid, cores) {
+            map.insert(physid, cores);
+        }
+        if let Some(physid) = physid {
+            packages.insert(physid);
+        }
+        if key == "package" {
+            package = value.parse().ok();
+        }
+        if key == "core" {
+            core = value.parse().ok();
+        }
+        if let (Some(package), Some(core)) = (package, core) {
+            loongarch_cores.insert((package, core));
+        }
+        if let Some(package) = package {
+            packages.insert(package);
+        }
+        if key == "apicid" {
+            if let Ok(val) = value.parse::<u32>() {
+                apicids.push(val);
+            }
+        }
+        // As reported by CPUID, before the firmware could renumber them.
+        if key == "initial apicid" {
+            if let Ok(val) = value.parse::<u32>() {
+                initial_apicids.push(val);
+            }
+        }
+    }
+    // A hostile /proc could claim any number of cores, don't let it overflow.
+    let mut count = map.values().fold(0, |acc: usize, &cores| acc.saturating_add(cores));
+
+    // The threads of an SMT core, such as on the 3A6000, share its `core`.
+    if count == 0 {
+        count = loongarch_cores.len();
+    }
+
+    // Every processor block has both, or the initial ones can't be trusted.
+    if initial_apicids.len() == apicids.len() {
+        apicids = initial_apicids;
+    }
+
+    if count == 0 && !apicids.is_empty() {
+        match threads_per_core {
+            Some(threads_per_core) => {
+                count = cores_from_apicids(&apicids, threads_per_core as u32);
+                debug!("physical cores from apicid, {} threads per core: {}", threads_per_core, count);
+            }
+            None => debug!("apicid without the threads per core"),
+        }
+    }
+
+    if count == 0 || count > MAX_CPUS {
+        None
+    } else {
+        Some((::std::cmp::max(packages.len(), 1), count))
+    }
+}
+
+/// Counts the distinct cores of the logical CPUs with the given APIC IDs.
+///
+/// The low bits of an APIC ID identify the thread within a core, so with
+/// `threads_per_core` rounded up to a power of 2, shifting them out leaves
+/// an ID shared by all threads of the same core.
+fn cores_from_apicids(apicids: &[u32], threads_per_core: u32) -> usize {
+    let mut smt_shift = 0;
+    while (1 << smt_shift) < threads_per_core {
+        smt_shift += 1;
+    }
+
+    apicids
+        .iter()
+        .map(|apicid| apicid >> smt_shift)
+        .collect::<HashSet<_>>()
+        .len()
+}
+
+// The cores of sysfs alone, as if /proc/cpuinfo were missing.
+#[cfg(test)]
+fn physical_cpus_from_sysfs<P: AsRef<Path>>(sys: P) -> Option<usize> {
+    sysfs_topology(sys).and_then(|sysfs| sysfs.cores())
+}
+
+pub fn get_cores_per_socket() -> Vec<usize> {
+    match cores_per_package_from_sysfs(SYSFS) {
+        Some(packages) => packages,
+        None => vec![get_num_physical_cpus()],
+    }
+}
+
+pub fn get_threads_per_core_detailed() -> Vec<usize> {
+    match threads_per_core_from_sysfs(SYSFS) {
+        Some(threads) => threads,
+        None => ::uniform_threads_per_core(),
+    }
+}
+
+fn threads_per_core_from_sysfs<P: AsRef<Path>>(sys: P) -> Option<Vec<usize>> {
+    sysfs_topology(sys).and_then(|sysfs| sysfs.threads_per_core)
+}
+
+fn cores_per_package_from_sysfs<P: AsRef<Path>>(sys: P) -> Option<Vec<usize>> {
+    sysfs_topology(sys).and_then(|sysfs| sysfs.cores_per_package)
+}
+
+/// What one walk of the online CPUs of sysfs tells of their topology.
+struct SysfsTopology {
+    /// The physical cores of each package, ordered by `physical_package_id`.
+    ///
+    /// A core is told apart by its thread siblings, or by its `core_id` when the
+    /// kernel doesn't list them, as some ARM kernels don't. The `core_id`s are only
+    /// unique within a package: on big.LITTLE SoCs, each cluster may be its own
+    /// package with cores numbered from 0.
+    cores_per_package: Option<Vec<usize>>,
+    /// The size of each group of thread siblings, ordered by its first CPU.
+    ///
+    /// Only the online siblings count: with SMT turned off at runtime, such as with
+    /// `/sys/devices/system/cpu/smt/control` set to `off`, the other threads of a
+    /// core are offline, but may still be listed.
+    threads_per_core: Option<Vec<usize>>,
+    /// The `cpu_capacity` of each CPU which has one, `None` if one doesn't parse.
+    capacities: Option<Vec<usize>>,
+}
+
+impl SysfsTopology {
+    /// Counts physical cores by their distinct `thread_siblings_list`s, or their
+    /// `core_id`s within each package.
+    ///
+    /// Every logical CPU of a core reports the same list of siblings, so
+    /// this works even when `/proc/cpuinfo` doesn't describe the topology, as on
+    /// ARM, where it has no `physical id` or `cpu cores`.
+    fn cores(&self) -> Option<usize> {
+        self.cores_per_package.as_ref().map(|packages| packages.iter().sum())
+    }
+
+    /// The most threads of any core.
+    fn max_threads_per_core(&self) -> Option<usize> {
+        self.threads_per_core.as_ref().and_then(|threads| threads.iter().cloned().max())
+    }
+}
+
+fn sysfs_topology<P: AsRef<Path>>(sys: P) -> Option<SysfsTopology> {
+    let sys = sys.as_ref();
+    let online = read_file(sys.join("devices/system/cpu/online")).map(|list| parse_cpu_list(&list));
+    let cpus = some!(online_cpu_dirs(sys, online.as_ref().map(|online| &online[..])));
+
+    let mut packages = Some(BTreeMap::new());
+    let mut cores = BTreeMap::new();
+    let mut capacities = Some(Vec::new());
+    for cpu in cpus {
+        let topology = cpu.join("topology");
+        let siblings = read_file(topology.join("thread_siblings_list"));
+
+        if let Some(ref siblings) = siblings {
+            let online_siblings: Vec<usize> = parse_cpu_list(siblings)
+                .into_iter()
+                .filter(|cpu| online.as_ref().map_or(true, |online| online.contains(cpu)))
+                .collect();
+            if let Some(&first) = online_siblings.iter().min() {
+                cores.insert(first, online_siblings.len());
+            }
+        }
+
+        let core = match siblings {
+            Some(siblings) => Some(Some(CoreKey::Siblings(siblings.trim().to_owned()))),
+            None => read_file(topology.join("core_id")).map(|id| id.trim().parse().ok().map(CoreKey::CoreId)),
+        };
+        match core {
+            Some(Some(core)) => {
+                let package: i32 = read_file(topology.join("physical_package_id"))
+                    .and_then(|id| id.trim().parse().ok())
+                    .unwrap_or(0);
+                if let Some(ref mut packages) = packages {
+                    packages.entry(package).or_insert_with(HashSet::new).insert(core);
+                }
+            }
+            Some(None) => packages = None,
+            None => {}
+        }
+
+        if let Some(capacity) = read_file(cpu.join("cpu_capacity")) {
+            match capacity.trim().parse() {
+                Ok(capacity) => {
+                    if let Some(ref mut capacities) = capacities {
+                        capacities.push(capacity);
+                    }
+                }
+                Err(_) => capacities = None,
+            }
+        }
+    }
+
+    Some(SysfsTopology {
+        cores_per_package: packages
+            .into_iter()
+            .filter(|packages| !packages.is_empty())
+            .map(|packages| packages.values().map(|cores| cores.len()).collect())
+            .next(),
+        threads_per_core: if cores.is_empty() {
+            None
+        } else {
+            Some(cores.into_iter().map(|(_, threads)| threads).collect())
+        },
+        capacities: capacities,
+    })
+}
+
+#[derive(PartialEq, Eq, Hash)]
+enum CoreKey {
+    Siblings(String),
+    CoreId(i32),
+}
+
+/// Lists the `cpuN` directories of `/sys/devices/system/cpu` of the online CPUs,
+/// ordered by number.
+///
+/// The directories are listed rather than assumed to be `cpu0` to `cpuN`: the
+/// numbers may have gaps, and offline CPUs may keep a stale directory. The
+/// `online` list is passed in, read by the caller, which needs it too.
+fn online_cpu_dirs<P: AsRef<Path>>(sys: P, online: Option<&[usize]>) -> Option<Vec<PathBuf>> {
+    let dir = sys.as_ref().join("devices/system/cpu");
+    let entries = some!(fs::read_dir(&dir).ok());
+
+    let mut cpus = BTreeMap::new();
+    for entry in entries.filter_map(|result| result.ok()) {
+        let name = entry.file_name();
+        let cpu: usize = match name.to_str() {
+            Some(name) if is_cpu_dir(name) => some!(name[3..].parse().ok()),
+            _ => continue,
+        };
+        // Without an online list, such as on some old kernels, they all are.
+        if online.map_or(true, |online| online.contains(&cpu)) {
+            cpus.insert(cpu, entry.path());
+        }
+    }
+
+    Some(cpus.into_iter().map(|(_, path)| path).collect())
+}
+
+/// Whether `name` is one of the `cpuN` entries of `/sys/devices/system/cpu`.
+fn is_cpu_dir(name: &str) -> bool {
+    is_numbered(name, "cpu")
+}
+
+/// Whether `name` is `prefix` followed by a number, such as `node1`.
+fn is_numbered(name: &str, prefix: &str) -> bool {
+    name.len() > prefix.len()
+        && name.starts_with(prefix)
+        && name[prefix.len()..].bytes().all(|b| b >= b'0' && b <= b'9')
+}
+
+/// Counts the `nodeN` entries of `/sys/devices/system/node`, which is missing
+/// on kernels built without NUMA support.
+fn numa_nodes_from_sysfs<P: AsRef<Path>>(sys: P) -> usize {
+    let nodes = match fs::read_dir(sys.as_ref().join("devices/system/node")) {
+        Ok(nodes) => nodes,
+        Err(_) => return 1,
+    };
+
+    let count = nodes
+        .filter_map(|result| result.ok())
+        .filter(|entry| entry.file_name().to_str().map_or(false, |name| is_numbered(name, "node")))
+        .count();
+    ::std::cmp::max(1, count)
+}
+
+pub fn get_logical_cpus_per_node() -> Vec<usize> {
+    match node_cpus_from_sysfs(SYSFS) {
+        Some(nodes) => nodes.iter().map(|cpus| cpus.len()).collect(),
+        None => vec![get_num_cpus()],
+    }
+}
+
+/// Lists the CPUs of each `nodeN` entry of `/sys/devices/system/node`, ordered
+/// by node id.
+fn node_cpus_from_sysfs<P: AsRef<Path>>(sys: P) -> Option<Vec<Vec<usize>>> {
+    node_cpus_by_id_from_sysfs(sys).map(|nodes| nodes.into_iter().map(|(_, cpus)| cpus).collect())
+}
+
+fn node_cpus_by_id_from_sysfs<P: AsRef<Path>>(sys: P) -> Option<BTreeMap<usize, Vec<usize>>> {
+    let entries = some!(fs::read_dir(sys.as_ref().join("devices/system/node")).ok());
+    let mut nodes = BTreeMap::new();
+
+    for entry in entries.filter_map(|result| result.ok()) {
+        let name = entry.file_name();
+        let id: usize = match name.to_str() {
+            Some(name) if is_numbered(name, "node") => some!(name[4..].parse().ok()),
+            _ => continue,
+        };
+
+        // Memory-only nodes have an empty list.
+        let cpus = read_file(entry.path().join("cpulist"))
+            .map(|list| parse_cpu_list(&list))
+            .unwrap_or(Vec::new());
+        nodes.insert(id, cpus);
+    }
+
+    if nodes.is_empty() {
+        None
+    } else {
+        Some(nodes)
+    }
+}
+
+pub fn get_cpu_info() -> ::CpuInfo {
+    cpu_info_from("/proc/cpuinfo", SYSFS, online_cpus())
+}
+
+// One parse of /proc/cpuinfo and one walk of sysfs for everything at once.
+fn cpu_info_from<P1, P2>(cpuinfo: P1, sys: P2, logical: usize) -> ::CpuInfo
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let sys = sys.as_ref();
+    let sysfs = sysfs_topology(sys);
+    let (sockets, physical) = match physical_topology(cpuinfo, sysfs.as_ref()) {
+        Some((packages, cores, _)) => (packages, cores),
+        None => (1, get_num_cpus()),
+    };
+
+    ::CpuInfo {
+        logical: logical,
+        physical: physical,
+        available: ::get(),
+        numa_nodes: numa_nodes_from_sysfs(sys),
+        sockets: sockets,
+        hybrid: hybrid_counts(sys, sysfs.as_ref()),
+    }
+}
+
+pub fn get_hybrid_counts() -> Option<(usize, usize)> {
+    hybrid_counts_from_sysfs(SYSFS)
+}
+
+pub fn get_topology() -> ::CpuTopology {
+    topology_from("/proc/cpuinfo", SYSFS, online_cpus())
+}
+
+fn topology_from<P1, P2>(cpuinfo: P1, sys: P2, logical: usize) -> ::CpuTopology
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let (sockets, physical_cores) = match physical_topology(cpuinfo, sysfs_topology(sys).as_ref()) {
+        Some((packages, cores, _)) => (packages, cores),
+        None => (1, logical),
+    };
+
+    ::CpuTopology {
+        sockets: sockets,
+        physical_cores: physical_cores,
+        logical_cores: logical,
+    }
+}
+
+fn hybrid_counts_from_sysfs<P: AsRef<Path>>(sys: P) -> Option<(usize, usize)> {
+    let sys = sys.as_ref();
+    hybrid_counts(sys, sysfs_topology(sys).as_ref())
+}
+
+fn hybrid_counts(sys: &Path, sysfs: Option<&SysfsTopology>) -> Option<(usize, usize)> {
+    if let Some((core, atom)) = intel_hybrid_cpus(sys) {
+        return Some((core.len(), atom.len()));
+    }
+
+    // Arm big.LITTLE (and its three tier successors) report the relative
+    // capacity of each CPU. The slowest ones are counted as efficiency CPUs,
+    // any faster tier as performance ones.
+    let capacities = some!(sysfs.and_then(|sysfs| sysfs.capacities.as_ref()));
+    let lowest = some!(capacities.iter().min().cloned());
+    let efficiency = capacities.iter().filter(|&&capacity| capacity == lowest).count();
+    debug!("cpu capacities: {:?}", capacities);
+
+    if efficiency == capacities.len() {
+        None
+    } else {
+        Some((capacities.len() - efficiency, efficiency))
+    }
+}
+
+/// The `(performance, efficiency)` CPUs of an Intel hybrid part, which has a perf
+/// PMU for each core type.
+fn intel_hybrid_cpus(sys: &Path) -> Option<(Vec<usize>, Vec<usize>)> {
+    let core = read_file(sys.join("devices/cpu_core/cpus")).map(|list| parse_cpu_list(&list));
+    let atom = read_file(sys.join("devices/cpu_atom/cpus")).map(|list| parse_cpu_list(&list));
+    match (core, atom) {
+        (Some(core), Some(atom)) if !core.is_empty() && !atom.is_empty() => Some((core, atom)),
+        _ => None,
+    }
+}
+
+pub fn get_full_topology() -> ::FullTopology {
+    match full_topology_from_sysfs(SYSFS) {
+        Some(topology) => topology,
+        None => ::uniform_topology(),
+    }
+}
+
+fn full_topology_from_sysfs<P: AsRef<Path>>(sys: P) -> Option<::FullTopology> {
+    let sys = sys.as_ref();
+    let online = read_file(sys.join("devices/system/cpu/online")).map(|list| parse_cpu_list(&list));
+
+    // One walk of the online CPUs, for everything but their node and core type.
+    struct Cpu {
+        id: usize,
+        core: usize,
+        package: usize,
+        max_freq_khz: Option<u64>,
+        capacity: Option<usize>,
+    }
+    let mut cpus = Vec::new();
+    for dir in some!(online_cpu_dirs(sys, online.as_ref().map(|online| &online[..]))) {
+        let id: usize = match dir.file_name().and_then(|name| name.to_str()) {
+            Some(name) => some!(name[3..].parse().ok()),
+            None => continue,
+        };
+        let topology = dir.join("topology");
+        // A core is known by its first online thread, like in sysfs_topology().
+        let core = read_file(topology.join("thread_siblings_list"))
+            .and_then(|list| {
+                parse_cpu_list(&list)
+                    .into_iter()
+                    .filter(|cpu| online.as_ref().map_or(true, |online| online.contains(cpu)))
+                    .min()
+            })
+            .unwrap_or(id);
+        let package = read_file(topology.join("physical_package_id"))
+            .and_then(|id| id.trim().parse().ok())
+            .unwrap_or(0);
+        let max_freq_khz = read_file(dir.join("cpufreq/cpuinfo_max_freq"))
+            .and_then(|freq| freq.trim().parse().ok());
+        let capacity = read_file(dir.join("cpu_capacity")).and_then(|cap| cap.trim().parse().ok());
+
+        cpus.push(Cpu {
+            id: id,
+            core: core,
+            package: package,
+            max_freq_khz: max_freq_khz,
+            capacity: capacity,
+        });
+    }
+    if cpus.is_empty() {
+        return None;
+    }
+
+    // The same core types as hybrid_counts(), for each CPU.
+    let intel = intel_hybrid_cpus(sys);
+    let capacities: Vec<usize> = cpus.iter().filter_map(|cpu| cpu.capacity).collect();
+    let lowest = capacities.iter().min().cloned();
+    let uniform = capacities.iter().all(|&capacity| Some(capacity) == lowest);
+    let core_type = |cpu: &Cpu| match (&intel, cpu.capacity) {
+        (&Some((ref core, _)), _) if core.contains(&cpu.id) => ::CoreType::Performance,
+        (&Some((_, ref atom)), _) if atom.contains(&cpu.id) => ::CoreType::Efficiency,
+        (&Some(_), _) => ::CoreType::Unknown,
+        (&None, Some(capacity)) if !uniform => {
+            if Some(capacity) == lowest {
+                ::CoreType::Efficiency
+            } else {
+                ::CoreType::Performance
+            }
+        }
+        (&None, _) => ::CoreType::Unknown,
+    };
+
+    let mut node_of = HashMap::new();
+    if let Some(nodes) = node_cpus_by_id_from_sysfs(sys) {
+        for (id, node_cpus) in nodes {
+            for cpu in node_cpus {
+                node_of.insert(cpu, id);
+            }
+        }
+    }
+
+    let mut nodes = BTreeMap::new();
+    for cpu in &cpus {
+        let node = node_of.get(&cpu.id).cloned().unwrap_or(0);
+        let core = nodes
+            .entry(node)
+            .or_insert_with(BTreeMap::new)
+            .entry(cpu.core)
+            .or_insert_with(|| ::PhysicalCore {
+                package: cpu.package,
+                cpus: Vec::new(),
+            });
+        core.cpus.push(::LogicalCpu {
+            id: cpu.id,
+            core_type: core_type(cpu),
+            max_freq_khz: cpu.max_freq_khz,
+        });
+    }
+
+    Some(::FullTopology {
+        nodes: nodes
+            .into_iter()
+            .map(|(id, cores)| ::NumaNode {
+                id: id,
+                cores: cores.into_iter().map(|(_, core)| core).collect(),
             })
+            .collect(),
+    })
+}
+
+pub fn get_online_live_cpus() -> usize {
+    online_from_sysfs(SYSFS)
+}
+
+fn online_from_sysfs<P: AsRef<Path>>(sys: P) -> usize {
+    let online = read_file(sys.as_ref().join("devices/system/cpu/online"))
+        .map(|list| parse_cpu_list(&list).len());
+
+    match online {
+        Some(n) if n > 0 => n,
+        _ => online_cpus(),
+    }
+}
+
+pub fn get_num_hardware_cpus() -> usize {
+    hardware_from_sysfs(SYSFS)
+}
+
+fn hardware_from_sysfs<P: AsRef<Path>>(sys: P) -> usize {
+    let present = read_file(sys.as_ref().join("devices/system/cpu/present"))
+        .map(|list| parse_cpu_list(&list).len());
+    if let Some(n) = present {
+        if n > 0 {
+            return n;
+        }
+    }
+
+    let cpus = unsafe { sys::sysconf(libc::_SC_NPROCESSORS_CONF) };
+    debug!("sysconf(_SC_NPROCESSORS_CONF): {}", cpus);
+    if cpus >= 1 {
+        cpus as usize
+    } else {
+        online_cpus()
+    }
+}
+
+pub fn get_cpu_states() -> CpuStates {
+    cpu_states_from_sysfs(SYSFS)
+}
+
+fn cpu_states_from_sysfs<P: AsRef<Path>>(sys: P) -> CpuStates {
+    let dir = sys.as_ref().join("devices/system/cpu");
+    let count = |name: &str| {
+        match read_file(dir.join(name)).map(|list| parse_cpu_list(&list).len()) {
+            Some(n) if n > 0 => n,
+            // Not narrowed by the sched affinity, like the lists of sysfs.
+            _ => online_cpus(),
+        }
+    };
+
+    // A list that fell back can't be allowed to be smaller than the ones after it.
+    let online = count("online");
+    let present = ::std::cmp::max(count("present"), online);
+    CpuStates {
+        possible: ::std::cmp::max(count("possible"), present),
+        present: present,
+        online: online,
+    }
+}
+
+fn open_file<P: AsRef<Path>>(path: P) -> io::Result<File> {
+    #[cfg(test)]
+    {
+        tests::FILE_OPENS.with(|opens| opens.set(opens.get() + 1));
+        tests::OPENED.with(|opened| opened.borrow_mut().push(path.as_ref().to_owned()));
+        if let Some(open) = tests::fault::OPEN.with(|open| open.get()) {
+            return open(path.as_ref());
+        }
+    }
+
+    File::open(path)
+}
+
+fn read_file<P: AsRef<Path>>(path: P) -> Option<String> {
+    debug!("reading {:?}", path.as_ref());
+    let mut file = some!(open_file(path).ok());
+
+    let mut buf = String::new();
+    some!(file.read_to_string(&mut buf).ok());
+
+    Some(buf)
+}
+
+/// The period of the CFS bandwidth control the kernel starts a cgroup with, 100ms.
+///
+/// Some minimal cgroup v1 layouts only have `cpu.cfs_quota_us`, which is then
+/// assumed to be this often.
+const DEFAULT_CFS_PERIOD_US: usize = 100000;
+
+/// Cached CPU quota calculated from cgroups.
+///
+/// If 0, there is no quota and only logical cpus are checked.
+// Allow deprecation warnings, we want to work on older rustc. This is also
+// why it isn't a `OnceLock`, which needs Rust 1.70.
+#[allow(warnings)]
+static CGROUPS_CPUS: AtomicUsize = ::std::sync::atomic::ATOMIC_USIZE_INIT;
+
+#[allow(warnings)]
+static CGROUPS_ONCE: Once = ::std::sync::ONCE_INIT;
+
+fn cached_cgroups_quota() -> Option<usize> {
+    CGROUPS_ONCE.call_once(init_cgroups);
+
+    match CGROUPS_CPUS.load(Ordering::Acquire) {
+        0 => None,
+        quota => Some(quota),
+    }
+}
+
+fn init_cgroups() {
+    // Should only be called once
+    debug_assert!(CGROUPS_CPUS.load(Ordering::SeqCst) == 0);
+
+    CGROUPS_CPUS.store(cgroups_quota(), Ordering::SeqCst);
+}
+
+pub fn refresh_num_cpus() -> usize {
+    // Make sure a first call racing with this one can't overwrite the new quota.
+    CGROUPS_ONCE.call_once(init_cgroups);
+
+    CGROUPS_CPUS.store(cgroups_quota(), Ordering::SeqCst);
+
+    get_num_cpus()
+}
+
+fn cgroups_quota() -> usize {
+    #[cfg(feature = "tracing")]
+    let span = ::tracing::debug_span!("cgroups");
+    #[cfg(feature = "tracing")]
+    let _guard = span.enter();
+
+    // Fails in Miri by default (cannot open files), and Miri does not have parallelism anyway.
+    if cfg!(miri) {
+        return 0;
+    }
+
+    let quota = self_cpu_cgroup().and_then(|cgroup| cgroup.cpu_quota());
+    let cpuset = match quota {
+        Some(_) => load_cpuset("/proc/self/cgroup", "/proc/self/mountinfo"),
+        None => None,
+    };
+
+    let cpuset = cpuset.map(|cpus| cpus.len());
+    debug!("cgroups quota: {:?}, cpuset: {:?}", quota, cpuset);
+
+    match quota_within_cpuset(quota, cpuset) {
+        Some(quota) => {
+            debug!("cgroups limit: {}", quota);
+            quota
+        }
+        None => {
+            debug!("no cgroups limit");
+            0
+        }
+    }
+}
+
+// Every limit applies, so the count is the lowest of them. A quota of 4 CPUs
+// in a cpuset of 2 can only run 2 threads at once, and the affinity doesn't
+// always reflect the cpuset, such as in gVisor. A cpuset without a quota is left
+// to the affinity.
+fn quota_within_cpuset(quota: Option<usize>, cpuset: Option<usize>) -> Option<usize> {
+    match (quota, cpuset) {
+        (Some(quota), Some(cpuset)) if cpuset > 0 => Some(::std::cmp::min(quota, cpuset)),
+        (quota, _) => quota,
+    }
+}
+
+pub fn get_cgroup_version() -> Option<::CgroupVersion> {
+    if cfg!(miri) {
+        return None;
     }
 
-    fn parse_line(line: String) -> Option<Subsys> {
-        // Example format:
-        // 11:cpu,cpuacct:/
-        let mut fields = line.split(':');
+    cgroup_version_from_mountinfo("/proc/self/mountinfo")
+}
+
+fn cgroup_version_from_mountinfo<P: AsRef<Path>>(mountinfo_proc: P) -> Option<::CgroupVersion> {
+    let file = some!(open_file(mountinfo_proc).ok());
+    let file = BufReader::new(file);
+
+    let mut v1 = false;
+    let mut v2 = false;
+    for line in file.lines().filter_map(|result| result.ok()) {
+        match MountInfo::fs_type(&line) {
+            Some("cgroup") => v1 = true,
+            Some("cgroup2") => v2 = true,
+            _ => {}
+        }
+    }
+
+    match (v1, v2) {
+        (true, true) => Some(::CgroupVersion::Hybrid),
+        (true, false) => Some(::CgroupVersion::V1),
+        (false, true) => Some(::CgroupVersion::V2),
+        (false, false) => None,
+    }
+}
+
+pub fn get_cgroup_cpu_quota() -> Option<f64> {
+    if cfg!(miri) {
+        return None;
+    }
+
+    self_cpu_cgroup().and_then(|cgroup| cgroup.cpu_quota_fraction())
+}
+
+pub fn get_cgroup_cpus_at(path: &Path) -> Option<usize> {
+    cgroup_cpus_at(path, online_cpus())
+}
+
+fn cgroup_cpus_at<P: AsRef<Path>>(path: P, online: usize) -> Option<usize> {
+    let cgroup = some!(Cgroup::at(path));
+    let mut cpus = online;
+
+    if let Some(quota) = cgroup.cpu_quota() {
+        cpus = ::std::cmp::min(cpus, quota);
+    }
+    if let Some(cpuset) = cgroup.cpuset() {
+        cpus = ::std::cmp::min(cpus, cpuset.len());
+    }
+
+    Some(::std::cmp::max(cpus, 1))
+}
+
+pub fn get_effective_cpus_estimate() -> usize {
+    effective_cpus_estimate(self_cpu_cgroup(), get_num_cpus())
+}
+
+fn effective_cpus_estimate(cgroup: Option<Cgroup>, cpus: usize) -> usize {
+    let cgroup = match cgroup {
+        Some(cgroup) => cgroup,
+        None => return cpus,
+    };
+
+    // A quota is a hard limit, already part of `cpus`.
+    if cgroup.cpu_quota().is_some() {
+        return cpus;
+    }
+
+    match cgroup.shares_estimate(cpus) {
+        Some(estimate) => {
+            debug!("cgroups shares estimate: {}", estimate);
+            estimate
+        }
+        None => cpus,
+    }
+}
+
+pub fn get_cgroup_path() -> Option<PathBuf> {
+    self_cpu_cgroup().map(|cgroup| cgroup.base)
+}
+
+/// The cpu cgroup of the current process.
+fn self_cpu_cgroup() -> Option<Cgroup> {
+    // Fails in Miri by default (cannot open files).
+    if cfg!(miri) {
+        return None;
+    }
+
+    if let Some(root) = cgroup_root_override() {
+        debug!("CGROUP_ROOT: {:?}", root);
+        return find_cpu_cgroup_under("/proc/self/cgroup", root);
+    }
+
+    find_cpu_cgroup("/proc/self/cgroup", "/proc/self/mountinfo", "/sys/fs/cgroup")
+}
+
+#[cfg(feature = "env_override")]
+fn cgroup_root_override() -> Option<PathBuf> {
+    match ::std::env::var_os("CGROUP_ROOT") {
+        Some(ref root) if root.is_empty() => None,
+        root => root.map(PathBuf::from),
+    }
+}
+
+#[cfg(not(feature = "env_override"))]
+#[inline]
+fn cgroup_root_override() -> Option<PathBuf> {
+    None
+}
+
+/// Looks up the cgroup of `/proc/self/cgroup` under `root` instead of where the
+/// mountinfo says, or uses `root` itself when it isn't found there.
+fn find_cpu_cgroup_under<P1, P2>(cgroup_proc: P1, root: P2) -> Option<Cgroup>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let root = root.as_ref();
+    let path = match Subsys::load_cpu(cgroup_proc) {
+        Some(subsys) => subsys.base,
+        None => String::new(),
+    };
+    let path = if path.starts_with('/') { &path[1..] } else { &path[..] };
+
+    cpu_cgroup_in(root, path).or_else(|| cpu_cgroup_in(root, ""))
+}
+
+/// The cgroup at `path` of a cgroups v2 hierarchy mounted at `cgroupfs`, or of
+/// its v1 cpu controller.
+fn cpu_cgroup_in(cgroupfs: &Path, path: &str) -> Option<Cgroup> {
+    ["", "cpu,cpuacct", "cpu"]
+        .iter()
+        .filter_map(|dir| {
+            let mount = cgroupfs.join(dir);
+            Cgroup::at(mount.join(path)).map(|cgroup| cgroup.within(mount))
+        })
+        .next()
+}
+
+fn find_cpu_cgroup<P1, P2, P3>(cgroup_proc: P1, mountinfo_proc: P2, cgroupfs: P3) -> Option<Cgroup>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+    P3: AsRef<Path>,
+{
+    if open_file(cgroup_proc.as_ref()).is_ok() {
+        return load_cpu_cgroup(cgroup_proc, mountinfo_proc);
+    }
+
+    // Hardened containers may mask `/proc/self/cgroup`, but still mount their
+    // own cgroup at the well-known path, as a cgroup namespace does.
+    debug!("{:?} unreadable, trying {:?}", cgroup_proc.as_ref(), cgroupfs.as_ref());
+    cpu_cgroup_in(cgroupfs.as_ref(), "")
+}
+
+fn load_cpu_cgroup<P1, P2>(cgroup_proc: P1, mountinfo_proc: P2) -> Option<Cgroup>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let subsys = some!(Subsys::load_cpu(cgroup_proc));
+    let mntinfo = some!(MountInfo::load_cpu(mountinfo_proc, subsys.version));
+
+    // A rootless container with its own cgroup namespace is at `0::/`, while the
+    // cgroup2 mount it was given keeps the host path of its cgroup as the root.
+    // The mount point is then the container's own cgroup.
+    let namespace_root = mntinfo.version == CgroupVersion::V2 && subsys.base == "/";
+    let mount_point = mntinfo.mount_point.clone();
+
+    match Cgroup::translate(mntinfo, subsys) {
+        None if namespace_root => {
+            debug!("cgroup namespace root, using the mount point {:?}", mount_point);
+            Cgroup::at(mount_point)
+        }
+        cgroup => cgroup,
+    }
+}
+
+fn load_cpuset<P1, P2>(cgroup_proc: P1, mountinfo_proc: P2) -> Option<Vec<usize>>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let subsys = some!(Subsys::load(cgroup_proc, "cpuset"));
+    let mntinfo = some!(MountInfo::load(mountinfo_proc, subsys.version, "cpuset"));
+    let cgroup = some!(Cgroup::translate(mntinfo, subsys));
+    cgroup.cpuset()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CgroupVersion {
+    V1,
+    V2,
+}
+
+struct Cgroup {
+    version: CgroupVersion,
+    base: PathBuf,
+    // Where the hierarchy is mounted, up to which the quotas of the parents apply.
+    mount: PathBuf,
+}
+
+struct MountInfo {
+    version: CgroupVersion,
+    root: String,
+    mount_point: String,
+}
+
+struct Subsys {
+    version: CgroupVersion,
+    base: String,
+}
+
+impl Cgroup {
+    fn new(version: CgroupVersion, dir: PathBuf) -> Cgroup {
+        Cgroup {
+            version: version,
+            mount: dir.clone(),
+            base: dir,
+        }
+    }
+
+    /// The same cgroup, with the parents up to `mount` limiting its quota too.
+    fn within(self, mount: PathBuf) -> Cgroup {
+        Cgroup {
+            version: self.version,
+            base: self.base,
+            mount: mount,
+        }
+    }
+
+    /// Opens the cgroup directory at `dir`, telling the version apart by the
+    /// files in it, since it may not belong to the current process.
+    fn at<P: AsRef<Path>>(dir: P) -> Option<Cgroup> {
+        let dir = dir.as_ref();
+
+        // Every cgroups v2 directory has this, including the root.
+        if dir.join("cgroup.controllers").is_file() {
+            return Some(Cgroup::new(CgroupVersion::V2, dir.into()));
+        }
+
+        let v1_params = ["cpu.cfs_quota_us", "cpuset.cpus", "cpuset.effective_cpus"];
+        if v1_params.iter().any(|param| dir.join(param).is_file()) {
+            return Some(Cgroup::new(CgroupVersion::V1, dir.into()));
+        }
+
+        debug!("not a cgroup: {:?}", dir);
+        None
+    }
+
+    fn translate(mntinfo: MountInfo, subsys: Subsys) -> Option<Cgroup> {
+        // Translate the subsystem directory via the host paths.
+        debug!(
+            "subsys = {:?}; root = {:?}; mount_point = {:?}",
+            subsys.base, mntinfo.root, mntinfo.mount_point
+        );
+
+        let rel_from_root = some!(Path::new(&subsys.base).strip_prefix(&mntinfo.root).ok());
+
+        debug!("rel_from_root: {:?}", rel_from_root);
+
+        // join(mp.MountPoint, relPath)
+        let mount = PathBuf::from(mntinfo.mount_point);
+        let path = mount.join(rel_from_root);
+        Some(Cgroup::new(mntinfo.version, path).within(mount))
+    }
+
+    fn cpu_quota(&self) -> Option<usize> {
+        // Ceil the division, since we want to be able to saturate
+        // the available CPUs, and flooring would leave a CPU un-utilized.
+        self.cpu_quota_fraction().map(|cpus| cpus.ceil() as usize)
+    }
+
+    /// The tightest quota of this cgroup and its parents, up to the mount.
+    ///
+    /// A child can't use more than the quota of its parent, so a parent limited
+    /// to 2 CPUs also limits a child with a quota of 4, or without any.
+    fn cpu_quota_fraction(&self) -> Option<f64> {
+        let mut tightest: Option<f64> = None;
+        let mut dir = Some(self.base.as_path());
+
+        while let Some(path) = dir {
+            let quota = Cgroup::new(self.version, path.into()).own_cpu_quota_fraction();
+            if let Some(cpus) = quota {
+                debug!("cgroup {:?} quota: {}", path, cpus);
+                tightest = Some(tightest.map_or(cpus, |tightest| tightest.min(cpus)));
+            }
+            if path == self.mount {
+                break;
+            }
+            dir = match path.parent() {
+                Some(parent) if parent.starts_with(&self.mount) => Some(parent),
+                _ => None,
+            };
+        }
+
+        tightest
+    }
+
+    fn own_cpu_quota_fraction(&self) -> Option<f64> {
+        let (quota_us, period_us) = match self.version {
+            CgroupVersion::V1 => (
+                some!(self.quota_us()),
+                self.period_us().unwrap_or(DEFAULT_CFS_PERIOD_US),
+            ),
+            CgroupVersion::V2 => some!(self.max()),
+        };
+
+        // protect against dividing by zero
+        if period_us == 0 {
+            return None;
+        }
+
+        // The kernel refuses to write a quota of 0, unlike -1 for no quota,
+        // which doesn't parse. A 0 would round to no CPUs at all, which can't
+        // be enforced, so it's not a limit either.
+        if quota_us == 0 {
+            return None;
+        }
+
+        let cpus = quota_us as f64 / period_us as f64;
+
+        // A quota for more CPUs than any machine has doesn't limit anything,
+        // and wouldn't fit in a usize either.
+        if cpus.ceil() > MAX_CPUS as f64 {
+            return None;
+        }
+
+        Some(cpus)
+    }
+
+    fn cpuset(&self) -> Option<Vec<usize>> {
+        let cpus = match self.version {
+            // Without the `cpuset.` prefix on the legacy cpuset filesystem.
+            CgroupVersion::V1 => some!(self
+                .raw_param("cpuset.effective_cpus")
+                .or_else(|| self.raw_param("cpuset.cpus"))
+                .or_else(|| self.raw_param("effective_cpus"))
+                .or_else(|| self.raw_param("cpus"))),
+            CgroupVersion::V2 => some!(self.raw_param("cpuset.cpus.effective")),
+        };
+
+        let cpus = parse_cpu_list(&cpus);
+        if cpus.is_empty() {
+            None
+        } else {
+            Some(cpus)
+        }
+    }
+
+    /// The share of `cpus` this cgroup gets when all of its siblings are busy,
+    /// from its weight relative to the sum of theirs.
+    ///
+    /// This ignores the tasks of the parent itself, and siblings which are idle
+    /// leave their share to the others, so it's only an estimate.
+    ///
+    /// There are no siblings at the mount point, whose parent isn't a cgroup: in
+    /// a container, it's often the cgroup itself, next to the `cpu` and
+    /// `cpuacct` symlinks to it of v1. Those symlinks are also counted once
+    /// anywhere else.
+    fn shares_estimate(&self, cpus: usize) -> Option<usize> {
+        if self.base == self.mount {
+            return None;
+        }
+        let weight = some!(self.weight());
+        let parent = some!(self.base.parent());
+
+        let mut siblings = HashSet::new();
+        let total: usize = some!(fs::read_dir(parent).ok())
+            .filter_map(|entry| entry.ok())
+            .map(|entry| fs::canonicalize(entry.path()).unwrap_or_else(|_| entry.path()))
+            .filter(|path| siblings.insert(path.clone()))
+            .filter_map(|path| Cgroup::new(self.version, path).weight())
+            .sum();
+
+        if total == 0 {
+            return None;
+        }
+
+        let estimate = (cpus as f64 * weight as f64 / total as f64).ceil() as usize;
+        Some(::std::cmp::max(::std::cmp::min(estimate, cpus), 1))
+    }
+
+    fn weight(&self) -> Option<usize> {
+        match self.version {
+            CgroupVersion::V1 => self.param("cpu.shares"),
+            CgroupVersion::V2 => self.param("cpu.weight"),
+        }
+    }
+
+    fn quota_us(&self) -> Option<usize> {
+        self.param("cpu.cfs_quota_us")
+    }
+
+    fn period_us(&self) -> Option<usize> {
+        self.param("cpu.cfs_period_us")
+    }
+
+    fn max(&self) -> Option<(usize, usize)> {
+        let max = some!(self.raw_param("cpu.max"));
+        let mut max = some!(max.lines().next()).split(' ');
+
+        // `max` is no quota, rather than one that failed to parse: the count is
+        // then left to the cpuset and the sched affinity.
+        let quota = match max.next() {
+            Some("max") => return None,
+            quota => some!(quota.and_then(|quota| quota.parse().ok())),
+        };
+        let period = some!(max.next().and_then(|period| period.parse().ok()));
+
+        Some((quota, period))
+    }
+
+    fn param(&self, param: &str) -> Option<usize> {
+        let buf = some!(self.raw_param(param));
+
+        match buf.trim().parse() {
+            Ok(value) => Some(value),
+            Err(_) => {
+                debug!("{} of {:?} isn't a count: {:?}", param, self.base, buf.trim());
+                None
+            }
+        }
+    }
+
+    fn raw_param(&self, param: &str) -> Option<String> {
+        read_file(self.base.join(param))
+    }
+}
+
+impl MountInfo {
+    fn load_cpu<P: AsRef<Path>>(proc_path: P, version: CgroupVersion) -> Option<MountInfo> {
+        MountInfo::load(proc_path, version, "cpu")
+    }
+
+    fn load<P: AsRef<Path>>(
+        proc_path: P,
+        version: CgroupVersion,
+        controller: &str,
+    ) -> Option<MountInfo> {
+        let file = some!(open_file(proc_path).ok());
+        let file = BufReader::new(file);
+
+        file.lines()
+            .filter_map(|result| result.ok())
+            .filter_map(|line| MountInfo::parse_line(line, controller))
+            .find(|mount_info| mount_info.version == version)
+    }
+
+    fn parse_line(line: String, controller: &str) -> Option<MountInfo> {
+        let mut fields = line.split(' ');
+
+        // 7 5 0:6 </> /sys/fs/cgroup/cpu,cpuacct rw,nosuid,nodev,noexec,relatime shared:7 - cgroup cgroup rw,cpu,cpuacct
+        let mnt_root = some!(fields.nth(3));
+        // 7 5 0:6 / </sys/fs/cgroup/cpu,cpuacct> rw,nosuid,nodev,noexec,relatime shared:7 - cgroup cgroup rw,cpu,cpuacct
+        let mnt_point = some!(fields.next());
+
+        // Ignore all fields until the separator(-).
+        // Note: there could be zero or more optional fields before hyphen.
+        // See: https://man7.org/linux/man-pages/man5/proc.5.html
+        // 7 5 0:6 / /sys/fs/cgroup/cpu,cpuacct rw,nosuid,nodev,noexec,relatime shared:7 <-> cgroup cgroup rw,cpu,cpuacct
+        // Note: we cannot use `?` here because we need to support Rust 1.13.
+        match fields.find(|&s| s == "-") {
+            Some(_) => {}
+            None => return None,
+        };
+
+        // 7 5 0:6 / /sys/fs/cgroup/cpu,cpuacct rw,nosuid,nodev,noexec,relatime shared:7 - <cgroup> cgroup rw,cpu,cpuacct
+        let fs_type = fields.next();
+        let version = match fs_type {
+            Some("cgroup") => CgroupVersion::V1,
+            Some("cgroup2") => CgroupVersion::V2,
+            // The legacy cpuset filesystem, which `cset shield` mounts at
+            // `/cpusets` when the cgroup one isn't, works like a v1 cpuset.
+            Some("cpuset") if controller == "cpuset" => CgroupVersion::V1,
+            _ => return None,
+        };
+
+        // cgroups2 only has a single mount point
+        if fs_type == Some("cgroup") {
+            // 7 5 0:6 / /sys/fs/cgroup/cpu,cpuacct rw,nosuid,nodev,noexec,relatime shared:7 - cgroup cgroup <rw,cpu,cpuacct>
+            let super_opts = some!(fields.nth(1));
+
+            // We only care about the requested controller, such as 'cpu'
+            if !super_opts.split(',').any(|opt| opt == controller) {
+                return None;
+            }
+        }
+
+        Some(MountInfo {
+            version: version,
+            root: mnt_root.to_owned(),
+            mount_point: mnt_point.to_owned(),
+        })
+    }
+
+    fn fs_type(line: &str) -> Option<&str> {
+        // 7 5 0:6 / /sys/fs/cgroup/cpu,cpuacct rw,nosuid,nodev,noexec,relatime shared:7 - <cgroup> cgroup rw,cpu,cpuacct
+        line.split(' ').skip_while(|&s| s != "-").nth(1)
+    }
+}
+
+impl Subsys {
+    fn load_cpu<P: AsRef<Path>>(proc_path: P) -> Option<Subsys> {
+        Subsys::load(proc_path, "cpu")
+    }
+
+    fn load<P: AsRef<Path>>(proc_path: P, controller: &str) -> Option<Subsys> {
+        let file = some!(open_file(proc_path).ok());
+        let file = BufReader::new(file);
+
+        file.lines()
+            .filter_map(|result| result.ok())
+            .filter_map(|line| Subsys::parse_line(line, controller))
+            .fold(None, |previous, line| {
+                // already-found v1 trumps v2 since it explicitly specifies its controllers
+                if previous.is_some() && line.version == CgroupVersion::V2 {
+                    return previous;
+                }
+
+                Some(line)
+            })
+    }
+
+    fn parse_line(line: String, controller: &str) -> Option<Subsys> {
+        // Example format:
+        // 11:cpu,cpuacct:/
+        // The unified cgroups v2 line has no controllers:
+        // 0::/user.slice/user-1000.slice
+        // The path is the rest of the line, it may contain colons as well.
+        let mut fields = line.splitn(3, ':');
+
+        let sub_systems = some!(fields.nth(1));
+
+        let version = if sub_systems.is_empty() {
+            CgroupVersion::V2
+        } else {
+            CgroupVersion::V1
+        };
+
+        // Named v1 hierarchies without a controller, such as systemd's
+        // `1:name=systemd:/user.slice`, never match one here.
+        if version == CgroupVersion::V1 && !sub_systems.split(',').any(|sub| sub == controller) {
+            return None;
+        }
+
+        fields.next().map(|path| Subsys {
+            version: version,
+            base: path.to_owned(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::path::PathBuf;
+
+    thread_local! {
+        /// Counts the calls to `open_file()`, to check what's cached isn't read again.
+        pub static FILE_OPENS: Cell<usize> = Cell::new(0);
+        /// The paths passed to `open_file()`, to check what's read only once.
+        pub static OPENED: RefCell<Vec<PathBuf>> = RefCell::new(Vec::new());
+    }
+
+    /// Stands in for the `sys` calls, so a test can make them fail on its own
+    /// thread and check which fallback is reached.
+    pub mod fault {
+        use libc::{self, c_int, c_long, cpu_set_t, pid_t, size_t};
+        use std::cell::Cell;
+        use std::fs::File;
+        use std::io;
+        use std::path::Path;
+
+        type SchedGetaffinity = fn(pid_t, size_t, *mut cpu_set_t) -> c_int;
+        type Sysconf = fn(c_int) -> c_long;
+        type Open = fn(&Path) -> io::Result<File>;
+
+        thread_local! {
+            pub static SCHED_GETAFFINITY: Cell<Option<SchedGetaffinity>> = Cell::new(None);
+            pub static SYSCONF: Cell<Option<Sysconf>> = Cell::new(None);
+            /// Replaces `File::open()` in `open_file()`.
+            pub static OPEN: Cell<Option<Open>> = Cell::new(None);
+        }
+
+        #[allow(dead_code)]
+        pub unsafe fn sched_getaffinity(pid: pid_t, size: size_t, set: *mut cpu_set_t) -> c_int {
+            match SCHED_GETAFFINITY.with(|stub| stub.get()) {
+                Some(stub) => stub(pid, size, set),
+                None => libc::sched_getaffinity(pid, size, set),
+            }
+        }
+
+        pub unsafe fn sysconf(name: c_int) -> c_long {
+            match SYSCONF.with(|stub| stub.get()) {
+                Some(stub) => stub(name),
+                None => libc::sysconf(name),
+            }
+        }
+    }
+
+    mod cache {
+        use super::{FILE_OPENS, OPENED};
+        use super::super::{cpu_info_from, read_file};
+        use std::cell::Cell;
+        use std::collections::HashMap;
+
+        fn opens_of<F: FnOnce()>(f: F) -> usize {
+            FILE_OPENS.with(|opens| opens.set(0));
+            f();
+            FILE_OPENS.with(Cell::get)
+        }
+
+        #[test]
+        fn test_file_opens() {
+            assert_eq!(opens_of(|| { read_file("fixtures/proc/stat"); }), 1);
+        }
+
+        #[test]
+        fn test_second_call_opens_nothing() {
+            let info = ::detect();
+            assert_eq!(opens_of(|| { ::detect(); }), 0);
+            assert_eq!(opens_of(|| { ::detect_blocking(); }), 0);
+            assert_eq!(::detect_blocking(), info);
+
+            ::get();
+            assert_eq!(opens_of(|| { ::get(); }), 0);
+        }
+
+        #[test]
+        fn test_cpu_info_opens_each_file_once() {
+            for &(cpuinfo, sys) in &[
+                ("fixtures/cpuinfo/epyc_7302p", "fixtures/sysfs/full"),
+                ("fixtures/cpuinfo/smt_hidden", "fixtures/sysfs/smt_hidden"),
+                ("fixtures/cpuinfo/missing", "fixtures/sysfs/hybrid_arm"),
+            ] {
+                OPENED.with(|opened| opened.borrow_mut().clear());
+                let info = cpu_info_from(cpuinfo, sys, 8);
+                assert!(info.physical >= 1);
+
+                // get() reads the real files of the host, only the fixtures count.
+                let mut opens = HashMap::new();
+                OPENED.with(|opened| {
+                    for path in opened.borrow().iter().filter(|path| path.starts_with("fixtures")) {
+                        *opens.entry(path.clone()).or_insert(0) += 1;
+                    }
+                });
+                assert!(!opens.is_empty());
+                for (path, &count) in &opens {
+                    assert_eq!((path, count), (path, 1));
+                }
+            }
+        }
+    }
+
+    mod v1 {
+        use super::super::{Cgroup, CgroupVersion, MountInfo, Subsys};
+        use std::path::{Path, PathBuf};
+
+        // `static_in_const` feature is not stable in Rust 1.13.
+        static FIXTURES_PROC: &'static str = "fixtures/cgroups/proc/cgroups";
+
+        static FIXTURES_CGROUPS: &'static str = "fixtures/cgroups/cgroups";
+
+        macro_rules! join {
+            ($base:expr, $($path:expr),+) => ({
+                Path::new($base)
+                    $(.join($path))+
+            })
+        }
+
+        #[test]
+        fn test_load_mountinfo() {
+            // test only one optional fields
+            let path = join!(FIXTURES_PROC, "mountinfo");
+
+            let mnt_info = MountInfo::load_cpu(path, CgroupVersion::V1).unwrap();
+
+            assert_eq!(mnt_info.root, "/");
+            assert_eq!(mnt_info.mount_point, "/sys/fs/cgroup/cpu,cpuacct");
+
+            // test zero optional field
+            let path = join!(FIXTURES_PROC, "mountinfo_zero_opt");
+
+            let mnt_info = MountInfo::load_cpu(path, CgroupVersion::V1).unwrap();
+
+            assert_eq!(mnt_info.root, "/");
+            assert_eq!(mnt_info.mount_point, "/sys/fs/cgroup/cpu,cpuacct");
+
+            // test multi optional fields
+            let path = join!(FIXTURES_PROC, "mountinfo_multi_opt");
+
+            let mnt_info = MountInfo::load_cpu(path, CgroupVersion::V1).unwrap();
+
+            assert_eq!(mnt_info.root, "/");
+            assert_eq!(mnt_info.mount_point, "/sys/fs/cgroup/cpu,cpuacct");
+        }
+
+        #[test]
+        fn test_load_subsys() {
+            let path = join!(FIXTURES_PROC, "cgroup");
+
+            let subsys = Subsys::load_cpu(path).unwrap();
+
+            assert_eq!(subsys.base, "/");
+            assert_eq!(subsys.version, CgroupVersion::V1);
+        }
+
+        #[test]
+        fn test_load_split_controllers() {
+            // `cpu` and `cpuacct` mounted as separate hierarchies, the
+            // `cpuacct` ones must not be mistaken for `cpu`.
+            let subsys = Subsys::load_cpu(join!(FIXTURES_PROC, "cgroup_split")).unwrap();
+            assert_eq!(subsys.base, "/docker/01abcd");
+
+            let path = join!(FIXTURES_PROC, "mountinfo_split");
+            let mnt_info = MountInfo::load_cpu(path, CgroupVersion::V1).unwrap();
+            assert_eq!(mnt_info.root, "/docker/01abcd");
+            assert_eq!(mnt_info.mount_point, "/sys/fs/cgroup/cpu");
+
+            let cgroup = Cgroup::translate(mnt_info, subsys).unwrap();
+            assert_eq!(cgroup.base, PathBuf::from("/sys/fs/cgroup/cpu"));
+        }
+
+        #[test]
+        fn test_parse_subsys_exact() {
+            let parse = |line: &str| Subsys::parse_line(line.into(), "cpu").map(|s| s.base);
+
+            assert_eq!(parse("11:cpu,cpuacct:/a"), Some("/a".into()));
+            assert_eq!(parse("11:cpuacct,cpu:/a"), Some("/a".into()));
+            assert_eq!(parse("3:cpu:/a"), Some("/a".into()));
+            assert_eq!(parse("4:cpuacct:/a"), None);
+            assert_eq!(parse("5:cpuset:/a"), None);
+            assert_eq!(parse("1:name=systemd:/a"), None);
+            assert_eq!(parse("1:name=cpu:/a"), None);
+        }
+
+        #[test]
+        fn test_load_subsys_systemd() {
+            // A hybrid systemd host, the `cpu` line wins over the `name=systemd`
+            // and the unified `0::` lines.
+            let subsys = Subsys::load_cpu(join!(FIXTURES_PROC, "cgroup_systemd")).unwrap();
+            assert_eq!(subsys.base, "/user.slice");
+            assert_eq!(subsys.version, CgroupVersion::V1);
+
+            let subsys = Subsys::load(join!(FIXTURES_PROC, "cgroup_systemd"), "cpuset").unwrap();
+            assert_eq!(subsys.base, "/");
+            assert_eq!(subsys.version, CgroupVersion::V1);
+        }
+
+        #[test]
+        fn test_cgroup_mount() {
+            let cases = &[
+                ("/", "/sys/fs/cgroup/cpu", "/", Some("/sys/fs/cgroup/cpu")),
+                (
+                    "/docker/01abcd",
+                    "/sys/fs/cgroup/cpu",
+                    "/docker/01abcd",
+                    Some("/sys/fs/cgroup/cpu"),
+                ),
+                (
+                    "/docker/01abcd",
+                    "/sys/fs/cgroup/cpu",
+                    "/docker/01abcd/",
+                    Some("/sys/fs/cgroup/cpu"),
+                ),
+                (
+                    "/docker/01abcd",
+                    "/sys/fs/cgroup/cpu",
+                    "/docker/01abcd/large",
+                    Some("/sys/fs/cgroup/cpu/large"),
+                ),
+                // fails
+                ("/docker/01abcd", "/sys/fs/cgroup/cpu", "/", None),
+                ("/docker/01abcd", "/sys/fs/cgroup/cpu", "/docker", None),
+                ("/docker/01abcd", "/sys/fs/cgroup/cpu", "/elsewhere", None),
+                (
+                    "/docker/01abcd",
+                    "/sys/fs/cgroup/cpu",
+                    "/docker/01abcd-other-dir",
+                    None,
+                ),
+            ];
+
+            for &(root, mount_point, subsys, expected) in cases.iter() {
+                let mnt_info = MountInfo {
+                    version: CgroupVersion::V1,
+                    root: root.into(),
+                    mount_point: mount_point.into(),
+                };
+                let subsys = Subsys {
+                    version: CgroupVersion::V1,
+                    base: subsys.into(),
+                };
+
+                let actual = Cgroup::translate(mnt_info, subsys).map(|c| c.base);
+                let expected = expected.map(PathBuf::from);
+                assert_eq!(actual, expected);
+            }
+        }
+
+        #[test]
+        fn test_cgroup_cpu_quota() {
+            let cgroup = Cgroup::new(CgroupVersion::V1, join!(FIXTURES_CGROUPS, "good"));
+            assert_eq!(cgroup.cpu_quota(), Some(6));
+        }
+
+        #[test]
+        fn test_cgroup_cpu_quota_nested() {
+            // a child with a quota of 4 in a parent with 2, the root has none
+            let mount = join!(FIXTURES_CGROUPS, "nested");
+            let child = join!(FIXTURES_CGROUPS, "nested", "parent_2", "child");
+            let child = Cgroup::new(CgroupVersion::V1, child);
+            assert_eq!(child.cpu_quota(), Some(4));
+            assert_eq!(child.within(mount).cpu_quota(), Some(2));
+        }
+
+        #[test]
+        fn test_cgroup_cpu_quota_divide_by_zero() {
+            let cgroup = Cgroup::new(CgroupVersion::V1, join!(FIXTURES_CGROUPS, "zero-period"));
+            assert!(cgroup.quota_us().is_some());
+            assert_eq!(cgroup.period_us(), Some(0));
+            assert_eq!(cgroup.cpu_quota(), None);
+        }
+
+        #[test]
+        fn test_cgroup_cpu_quota_default_period() {
+            let cgroup = Cgroup::new(CgroupVersion::V1, join!(FIXTURES_CGROUPS, "no-period"));
+            assert_eq!(cgroup.period_us(), None);
+            // 250ms every 100ms
+            assert_eq!(cgroup.cpu_quota_fraction(), Some(2.5));
+            assert_eq!(cgroup.cpu_quota(), Some(3));
+        }
+
+        #[test]
+        fn test_cgroup_cpu_quota_zero() {
+            let cgroup = Cgroup::new(CgroupVersion::V1, join!(FIXTURES_CGROUPS, "zero-quota"));
+            assert_eq!(cgroup.quota_us(), Some(0));
+            assert_eq!(cgroup.cpu_quota(), None);
+        }
+
+        #[test]
+        fn test_cgroup_cpu_quota_ceil() {
+            let cgroup = Cgroup::new(CgroupVersion::V1, join!(FIXTURES_CGROUPS, "ceil"));
+            assert_eq!(cgroup.cpu_quota(), Some(2));
+            assert_eq!(cgroup.cpu_quota_fraction(), Some(1.5));
+        }
+
+        #[test]
+        fn test_cgroup_cpu_quota_huge() {
+            let cgroup = Cgroup::new(CgroupVersion::V1, join!(FIXTURES_CGROUPS, "huge"));
+            assert_eq!(cgroup.cpu_quota(), None);
+        }
+    }
+
+    mod v2 {
+        use super::super::{cgroup_cpus_at, Cgroup, CgroupVersion, MountInfo, Subsys};
+        use std::path::{Path, PathBuf};
+
+        // `static_in_const` feature is not stable in Rust 1.13.
+        static FIXTURES_PROC: &'static str = "fixtures/cgroups2/proc/cgroups";
+
+        static FIXTURES_CGROUPS: &'static str = "fixtures/cgroups2/cgroups";
+
+        macro_rules! join {
+            ($base:expr, $($path:expr),+) => ({
+                Path::new($base)
+                    $(.join($path))+
+            })
+        }
+
+        #[test]
+        fn test_load_mountinfo() {
+            // test only one optional fields
+            let path = join!(FIXTURES_PROC, "mountinfo");
+
+            let mnt_info = MountInfo::load_cpu(path, CgroupVersion::V2).unwrap();
+
+            assert_eq!(mnt_info.root, "/");
+            assert_eq!(mnt_info.mount_point, "/sys/fs/cgroup");
+        }
+
+        #[test]
+        fn test_load_subsys() {
+            let path = join!(FIXTURES_PROC, "cgroup");
+
+            let subsys = Subsys::load_cpu(path).unwrap();
+
+            assert_eq!(subsys.base, "/");
+            assert_eq!(subsys.version, CgroupVersion::V2);
+        }
+
+        #[test]
+        fn test_parse_subsys_unified() {
+            let parse = |line: &str| Subsys::parse_line(line.into(), "cpu").map(|s| (s.version, s.base));
+
+            assert_eq!(
+                parse("0::/user.slice/user-1000.slice"),
+                Some((CgroupVersion::V2, "/user.slice/user-1000.slice".into()))
+            );
+            assert_eq!(parse("0::/a:b"), Some((CgroupVersion::V2, "/a:b".into())));
+            assert_eq!(parse("0::/"), Some((CgroupVersion::V2, "/".into())));
+        }
+
+        #[test]
+        fn test_load_subsys_multi() {
+            let path = join!(FIXTURES_PROC, "cgroup_multi");
+
+            let subsys = Subsys::load_cpu(path).unwrap();
+
+            assert_eq!(subsys.base, "/");
+            assert_eq!(subsys.version, CgroupVersion::V1);
+        }
+
+        #[test]
+        fn test_load_subsys_systemd() {
+            // Only the unified line has the `cpu` controller, besides `name=systemd`.
+            let subsys = Subsys::load_cpu(join!(FIXTURES_PROC, "cgroup_systemd")).unwrap();
+            assert_eq!(subsys.base, "/user.slice/user-1000.slice/session-2.scope");
+            assert_eq!(subsys.version, CgroupVersion::V2);
+        }
+
+        #[test]
+        fn test_cgroup_mount() {
+            let cases = &[
+                ("/", "/sys/fs/cgroup/cpu", "/", Some("/sys/fs/cgroup/cpu")),
+                (
+                    "/docker/01abcd",
+                    "/sys/fs/cgroup/cpu",
+                    "/docker/01abcd",
+                    Some("/sys/fs/cgroup/cpu"),
+                ),
+                (
+                    "/docker/01abcd",
+                    "/sys/fs/cgroup/cpu",
+                    "/docker/01abcd/",
+                    Some("/sys/fs/cgroup/cpu"),
+                ),
+                (
+                    "/docker/01abcd",
+                    "/sys/fs/cgroup/cpu",
+                    "/docker/01abcd/large",
+                    Some("/sys/fs/cgroup/cpu/large"),
+                ),
+                // fails
+                ("/docker/01abcd", "/sys/fs/cgroup/cpu", "/", None),
+                ("/docker/01abcd", "/sys/fs/cgroup/cpu", "/docker", None),
+                ("/docker/01abcd", "/sys/fs/cgroup/cpu", "/elsewhere", None),
+                (
+                    "/docker/01abcd",
+                    "/sys/fs/cgroup/cpu",
+                    "/docker/01abcd-other-dir",
+                    None,
+                ),
+            ];
+
+            for &(root, mount_point, subsys, expected) in cases.iter() {
+                let mnt_info = MountInfo {
+                    version: CgroupVersion::V1,
+                    root: root.into(),
+                    mount_point: mount_point.into(),
+                };
+                let subsys = Subsys {
+                    version: CgroupVersion::V1,
+                    base: subsys.into(),
+                };
+
+                let actual = Cgroup::translate(mnt_info, subsys).map(|c| c.base);
+                let expected = expected.map(PathBuf::from);
+                assert_eq!(actual, expected);
+            }
+        }
+
+        #[test]
+        fn test_cgroup_cpu_quota() {
+            let cgroup = Cgroup::new(CgroupVersion::V2, join!(FIXTURES_CGROUPS, "good"));
+            assert_eq!(cgroup.cpu_quota(), Some(6));
+        }
+
+        #[test]
+        fn test_cgroup_cpu_quota_nested() {
+            let nested = |path: &[&str]| {
+                let dir = path.iter().fold(join!(FIXTURES_CGROUPS, "nested"), |dir, p| dir.join(p));
+                Cgroup::new(CgroupVersion::V2, dir).within(join!(FIXTURES_CGROUPS, "nested"))
+            };
+
+            // the child's own quota is the tightest
+            assert_eq!(nested(&["parent_4", "child"]).cpu_quota(), Some(2));
+            // the parent's is
+            assert_eq!(nested(&["parent_2", "child"]).cpu_quota(), Some(2));
+            assert_eq!(nested(&["parent_2", "unlimited"]).cpu_quota(), Some(2));
+            assert_eq!(nested(&["parent_4"]).cpu_quota(), Some(4));
+            assert_eq!(nested(&[]).cpu_quota(), None);
+
+            // not above the mount
+            let child = join!(FIXTURES_CGROUPS, "nested", "parent_2", "child");
+            let cgroup = Cgroup::new(CgroupVersion::V2, child.clone()).within(child);
+            assert_eq!(cgroup.cpu_quota(), Some(4));
+        }
+
+        #[test]
+        fn test_cgroup_cpu_quota_divide_by_zero() {
+            let cgroup = Cgroup::new(CgroupVersion::V2, join!(FIXTURES_CGROUPS, "zero-period"));
+            let period = cgroup.max().map(|max| max.1);
+
+            assert_eq!(period, Some(0));
+            assert_eq!(cgroup.cpu_quota(), None);
+        }
+
+        #[test]
+        fn test_cgroup_cpu_quota_ceil() {
+            let cgroup = Cgroup::new(CgroupVersion::V2, join!(FIXTURES_CGROUPS, "ceil"));
+            assert_eq!(cgroup.cpu_quota(), Some(2));
+            assert_eq!(cgroup.cpu_quota_fraction(), Some(1.5));
+        }
+
+        #[test]
+        fn test_cgroup_cpu_quota_fraction() {
+            let cgroup = Cgroup::new(CgroupVersion::V2, join!(FIXTURES_CGROUPS, "fractional"));
+            assert_eq!(cgroup.cpu_quota(), Some(3));
+            assert_eq!(cgroup.cpu_quota_fraction(), Some(2.5));
+        }
+
+        #[test]
+        fn test_cgroup_cpu_quota_huge() {
+            let cgroup = Cgroup::new(CgroupVersion::V2, join!(FIXTURES_CGROUPS, "huge"));
+            assert_eq!(cgroup.cpu_quota(), None);
+        }
+
+        #[test]
+        fn test_cgroup_cpu_quota_max() {
+            // `max` is no quota, with the period still set
+            let cgroup = Cgroup::new(CgroupVersion::V2, join!(FIXTURES_CGROUPS, "unlimited"));
+            assert_eq!(cgroup.max(), None);
+            assert_eq!(cgroup.cpu_quota(), None);
+            assert_eq!(cgroup_cpus_at(join!(FIXTURES_CGROUPS, "unlimited"), 8), Some(8));
+        }
+    }
+
+    mod physical {
+        use super::super::{
+            cores_from_apicids, cores_per_package_from_sysfs, physical_cpus_from_cpuinfo,
+            physical_cpus_from_sysfs, physical_cpus_with_source, physical_disagreement,
+            topology_from, topology_from_cpuinfo,
+        };
+        use std::path::Path;
+        use Source;
+
+        static FIXTURES_CPUINFO: &'static str = "fixtures/cpuinfo";
+
+        static FIXTURES_SYSFS: &'static str = "fixtures/sysfs";
+
+        #[test]
+        fn test_epyc_two_sockets() {
+            let path = Path::new(FIXTURES_CPUINFO).join("epyc_7251_2s");
+            assert_eq!(physical_cpus_from_cpuinfo(path), Some(16));
+        }
+
+        #[test]
+        fn test_epyc_sparse_core_ids() {
+            let path = Path::new(FIXTURES_CPUINFO).join("epyc_7302p");
+            assert_eq!(physical_cpus_from_cpuinfo(path), Some(16));
+        }
+
+        #[test]
+        fn test_epyc_guest_without_topology() {
+            // The guest kernel doesn't report `physical id`/`cpu cores`,
+            // so only the sysfs siblings know about SMT.
+            let path = Path::new(FIXTURES_CPUINFO).join("epyc_7763_guest");
+            assert_eq!(physical_cpus_from_cpuinfo(path), None);
+
+            let path = Path::new(FIXTURES_SYSFS).join("epyc_7763_guest");
+            assert_eq!(physical_cpus_from_sysfs(path), Some(4));
+        }
+
+        #[test]
+        fn test_source() {
+            let cpuinfo = Path::new(FIXTURES_CPUINFO);
+            let sys = Path::new(FIXTURES_SYSFS);
+
+            assert_eq!(
+                physical_cpus_with_source(cpuinfo.join("epyc_7251_2s"), sys.join("missing")),
+                Some((16, Source::Cpuinfo))
+            );
+            // /proc/cpuinfo has no topology, the sysfs siblings are used instead
+            // of an accidental fallback
+            assert_eq!(
+                physical_cpus_with_source(cpuinfo.join("epyc_7763_guest"), sys.join("epyc_7763_guest")),
+                Some((4, Source::Sysfs))
+            );
+            assert_eq!(
+                physical_cpus_with_source(cpuinfo.join("missing"), sys.join("two_sockets")),
+                Some((14, Source::Sysfs))
+            );
+            assert_eq!(
+                physical_cpus_with_source(cpuinfo.join("missing"), sys.join("missing")),
+                None
+            );
+        }
+
+        #[test]
+        fn test_disagreement() {
+            // `cpu cores` claims 8 cores without SMT, the sysfs siblings 4 with 2 threads
+            let cpuinfo = Path::new(FIXTURES_CPUINFO).join("smt_hidden");
+            let sys = Path::new(FIXTURES_SYSFS).join("smt_hidden");
+
+            assert_eq!(physical_cpus_with_source(&cpuinfo, &sys), Some((4, Source::Sysfs)));
+            assert_eq!(physical_disagreement(&cpuinfo, &sys), Some((8, 4)));
+
+            let sys = Path::new(FIXTURES_SYSFS).join("missing");
+            assert_eq!(physical_cpus_with_source(&cpuinfo, &sys), Some((8, Source::Cpuinfo)));
+            assert_eq!(physical_disagreement(&cpuinfo, &sys), None);
+        }
+
+        #[test]
+        fn test_cores_per_package() {
+            let path = Path::new(FIXTURES_SYSFS).join("two_sockets");
+            assert_eq!(cores_per_package_from_sysfs(&path), Some(vec![6, 8]));
+            assert_eq!(physical_cpus_from_sysfs(&path), Some(14));
+
+            // without `physical_package_id`
+            let path = Path::new(FIXTURES_SYSFS).join("epyc_7763_guest");
+            assert_eq!(cores_per_package_from_sysfs(&path), Some(vec![4]));
+        }
+
+        #[test]
+        fn test_partial_blocks() {
+            // The first block has no `cpu cores`, and the last one has CRLF line
+            // endings and no trailing newline.
+            let path = Path::new(FIXTURES_CPUINFO).join("partial_blocks");
+            assert_eq!(physical_cpus_from_cpuinfo(path), Some(5));
+        }
+
+        #[test]
+        fn test_absurd_cpu_cores() {
+            // would overflow when summed, or fail to parse on 32-bit
+            let path = Path::new(FIXTURES_CPUINFO).join("absurd_cores");
+            assert_eq!(physical_cpus_from_cpuinfo(path), None);
+
+            // more than any machine has
+            let path = Path::new(FIXTURES_CPUINFO).join("absurd_cores_small");
+            assert_eq!(physical_cpus_from_cpuinfo(path), None);
+        }
+
+        #[test]
+        fn test_cores_from_apicids() {
+            assert_eq!(cores_from_apicids(&[0, 1, 2, 3], 1), 4);
+            assert_eq!(cores_from_apicids(&[0, 1, 2, 3], 2), 2);
+            // sparse IDs across two packages
+            assert_eq!(cores_from_apicids(&[0, 6, 1, 7], 2), 2);
+            // not a power of 2
+            assert_eq!(cores_from_apicids(&[0, 1, 2, 4, 5, 6], 3), 2);
+        }
+
+        #[test]
+        fn test_apicid_without_cpu_cores() {
+            // Hyper-threaded Xeons from before `cpu cores` was reported, whose
+            // threads sysfs tells apart
+            let path = Path::new(FIXTURES_CPUINFO).join("xeon_ht_2s");
+            assert_eq!(topology_from_cpuinfo(&path, Some(2)), Some((2, 2)));
+            // without sysfs, SMT isn't guessed
+            assert_eq!(physical_cpus_from_cpuinfo(&path), None);
+
+            // Virtual CPUs without SMT or any topology besides the APIC IDs
+            let path = Path::new(FIXTURES_CPUINFO).join("qemu_apicid");
+            assert_eq!(topology_from_cpuinfo(&path, Some(1)), Some((1, 4)));
+            assert_eq!(physical_cpus_from_cpuinfo(path), None);
+        }
+
+        #[test]
+        fn test_ht_flag_without_smt() {
+            // A 4 core Phenom with the `ht` flag, as every multi-core x86 CPU
+            // has, but no SMT, and no `cpu cores`
+            let cpuinfo = Path::new(FIXTURES_CPUINFO).join("ht_no_smt");
+            assert_eq!(topology_from_cpuinfo(&cpuinfo, Some(1)), Some((1, 4)));
+            // nor guessed from the `ht` flag
+            assert_eq!(physical_cpus_from_cpuinfo(&cpuinfo), None);
+            let sys = Path::new(FIXTURES_SYSFS).join("missing");
+            assert_eq!(physical_cpus_with_source(&cpuinfo, sys), None);
+        }
+
+        #[test]
+        fn test_loongarch() {
+            // 4 cores with 2 threads each
+            let path = Path::new(FIXTURES_CPUINFO).join("loongson_3a6000");
+            assert_eq!(physical_cpus_from_cpuinfo(path), Some(4));
+        }
+
+        #[test]
+        fn test_topology() {
+            let cpuinfo = Path::new(FIXTURES_CPUINFO);
+            let sys = Path::new(FIXTURES_SYSFS);
+
+            let topology = topology_from(cpuinfo.join("epyc_7251_2s"), sys.join("missing"), 32);
+            assert_eq!(topology.sockets, 2);
+            assert_eq!(topology.physical_cores, 16);
+            assert_eq!(topology.logical_cores, 32);
+
+            assert_eq!(topology_from_cpuinfo(cpuinfo.join("xeon_ht_2s"), Some(2)), Some((2, 2)));
+            assert_eq!(topology_from_cpuinfo(cpuinfo.join("qemu_apicid"), Some(1)), Some((1, 4)));
+            assert_eq!(topology_from_cpuinfo(cpuinfo.join("loongson_3a6000"), None), Some((1, 4)));
+
+            // the packages of sysfs, when /proc/cpuinfo has no topology
+            let topology = topology_from(cpuinfo.join("missing"), sys.join("two_sockets"), 28);
+            assert_eq!((topology.sockets, topology.physical_cores), (2, 14));
+
+            // nothing is known but the logical CPUs
+            let topology = topology_from(cpuinfo.join("missing"), sys.join("missing"), 3);
+            assert_eq!((topology.sockets, topology.physical_cores, topology.logical_cores), (1, 3, 3));
+        }
+    }
+
+    mod hybrid {
+        use super::super::hybrid_counts_from_sysfs;
+
+        #[test]
+        fn test_hybrid_intel() {
+            assert_eq!(hybrid_counts_from_sysfs("fixtures/sysfs/hybrid_intel"), Some((16, 8)));
+        }
+
+        #[test]
+        fn test_hybrid_arm_capacity() {
+            // 1 prime, 3 performance and 4 efficiency cores
+            assert_eq!(hybrid_counts_from_sysfs("fixtures/sysfs/hybrid_arm"), Some((4, 4)));
+        }
+
+        #[test]
+        fn test_not_hybrid() {
+            // no capacities
+            assert_eq!(hybrid_counts_from_sysfs("fixtures/sysfs/two_sockets"), None);
+            // all the same capacity
+            assert_eq!(hybrid_counts_from_sysfs("fixtures/sysfs/uniform_arm"), None);
+        }
+    }
+
+    mod states {
+        use super::super::{cpu_states_from_sysfs, hardware_from_sysfs, online_cpus, online_from_sysfs};
+
+        #[test]
+        fn test_online_from_sysfs() {
+            assert_eq!(online_from_sysfs("fixtures/sysfs/hotplug"), 5);
+            assert_eq!(online_from_sysfs("fixtures/sysfs/full"), 8);
+        }
+
+        #[test]
+        fn test_cpu_states() {
+            let states = cpu_states_from_sysfs("fixtures/sysfs/hotplug");
+            assert_eq!(states.possible, 16);
+            assert_eq!(states.present, 8);
+            assert_eq!(states.online, 5);
+
+            // the missing lists are the online CPUs of this system, but never
+            // fewer than the lists which are there
+            let states = cpu_states_from_sysfs("fixtures/sysfs/epyc_7763_guest");
+            assert_eq!(states.online, online_cpus());
+            assert!(states.possible >= states.present && states.present >= states.online);
+            let states = cpu_states_from_sysfs("fixtures/sysfs/online_only");
+            assert_eq!((states.possible, states.present, states.online), (256, 256, 256));
+        }
+
+        #[test]
+        fn test_hardware_from_sysfs() {
+            // more present than online
+            assert_eq!(hardware_from_sysfs("fixtures/sysfs/hotplug"), 8);
+            assert_eq!(hardware_from_sysfs("fixtures/sysfs/sparse"), 7);
+            assert_eq!(hardware_from_sysfs("fixtures/sysfs/full"), 8);
+            // without sysfs
+            assert!(hardware_from_sysfs("fixtures/sysfs/missing") >= 1);
+        }
+    }
+
+    mod version {
+        use super::super::cgroup_version_from_mountinfo;
+        use CgroupVersion;
+
+        #[test]
+        fn test_cgroup_version() {
+            let cases = &[
+                ("fixtures/cgroups/proc/cgroups/mountinfo", Some(CgroupVersion::V1)),
+                ("fixtures/cgroups2/proc/cgroups/mountinfo", Some(CgroupVersion::V2)),
+                ("fixtures/cgroups/proc/cgroups/mountinfo_hybrid", Some(CgroupVersion::Hybrid)),
+                ("fixtures/cgroups/proc/cgroups/mountinfo_no_cgroups", None),
+            ];
+
+            for &(path, expected) in cases.iter() {
+                assert_eq!(cgroup_version_from_mountinfo(path), expected);
+            }
+        }
+    }
+
+    mod blocked {
+        use super::super::{logical_cpus_with_source, mask_of, unaffine_cpus};
+        use libc;
+        use LogicalSource;
+
+        #[test]
+        fn test_affinity_blocked() {
+            // seccomp filters refuse sched_getaffinity with EPERM or ENOSYS
+            for &errno in &[libc::EPERM, libc::ENOSYS] {
+                assert_eq!(
+                    logical_cpus_with_source(Err(errno)),
+                    (unaffine_cpus(), LogicalSource::Sysconf { affinity_blocked: true })
+                );
+            }
+        }
+
+        #[test]
+        fn test_affinity_failed() {
+            assert_eq!(
+                logical_cpus_with_source(Err(libc::EINVAL)),
+                (unaffine_cpus(), LogicalSource::Sysconf { affinity_blocked: false })
+            );
+            assert_eq!(logical_cpus_with_source(Ok(3)), (3, LogicalSource::Affinity));
+        }
+
+        #[test]
+        fn test_mask_of() {
+            assert_eq!(mask_of(&[0, 2, 64], 128), vec![0b101, 1]);
+            assert_eq!(mask_of(&[], 1024), vec![0; 16]);
+            assert_eq!(mask_of(&[128], 128), vec![0, 0]);
+        }
+    }
+
+    mod ladder {
+        use super::fault::{OPEN, SCHED_GETAFFINITY, SYSCONF};
+        use super::super::{
+            affinity_query, logical_cpus, logical_cpus_with_source, try_logical_cpus, unaffine_cpus,
+        };
+        use libc::{self, c_int, c_long, cpu_set_t, pid_t, size_t};
+        use std::fs::File;
+        use std::io;
+        use std::path::Path;
+        use {Error, LogicalSource};
+
+        fn fail_with(errno: c_int) -> c_int {
+            unsafe { *libc::__errno_location() = errno };
+            -1
+        }
+
+        fn affinity_einval(_: pid_t, _: size_t, _: *mut cpu_set_t) -> c_int {
+            fail_with(libc::EINVAL)
+        }
+
+        fn affinity_eperm(_: pid_t, _: size_t, _: *mut cpu_set_t) -> c_int {
+            fail_with(libc::EPERM)
+        }
+
+        fn sysconf_fails(_: c_int) -> c_long {
+            fail_with(libc::EINVAL) as c_long
+        }
+
+        fn cpuinfo_fixture(path: &Path) -> io::Result<File> {
+            if path == Path::new("/proc/cpuinfo") {
+                File::open("fixtures/cpuinfo/epyc_7763_guest")
+            } else {
+                File::open(path)
+            }
+        }
+
+        fn proc_stat_fixture(path: &Path) -> io::Result<File> {
+            if path == Path::new("/proc/stat") {
+                File::open("fixtures/proc/stat")
+            } else {
+                open_fails(path)
+            }
+        }
+
+        fn open_fails(_: &Path) -> io::Result<File> {
+            Err(io::Error::from_raw_os_error(libc::EACCES))
+        }
+
+        #[test]
+        fn test_fallback_ladder() {
+            // affinity, unless it's disabled
+            match affinity_query() {
+                Ok(count) => assert_eq!(logical_cpus(), count),
+                Err(_) => assert_eq!(logical_cpus(), unaffine_cpus()),
+            }
+
+            // sysconf
+            SCHED_GETAFFINITY.with(|stub| stub.set(Some(affinity_einval)));
+            if cfg!(not(feature = "no_affinity")) {
+                assert_eq!(affinity_query(), Err(libc::EINVAL));
+            }
+            assert_eq!(logical_cpus(), unaffine_cpus());
+
+            // /proc/cpuinfo
+            SYSCONF.with(|stub| stub.set(Some(sysconf_fails)));
+            OPEN.with(|stub| stub.set(Some(cpuinfo_fixture)));
+            assert_eq!(logical_cpus(), 8);
+
+            // /proc/stat
+            OPEN.with(|stub| stub.set(Some(proc_stat_fixture)));
+            assert_eq!(logical_cpus(), 4);
+
+            // nothing left
+            OPEN.with(|stub| stub.set(Some(open_fails)));
+            assert_eq!(logical_cpus(), 1);
+            assert_eq!(try_logical_cpus(), Err(Error::Os(libc::EINVAL)));
+        }
+
+        #[test]
+        fn test_affinity_blocked() {
+            SCHED_GETAFFINITY.with(|stub| stub.set(Some(affinity_eperm)));
+            let blocked = cfg!(not(feature = "no_affinity"));
+            assert_eq!(
+                logical_cpus_with_source(affinity_query()),
+                (unaffine_cpus(), LogicalSource::Sysconf { affinity_blocked: blocked })
+            );
+        }
+    }
+
+    mod gvisor {
+        use super::super::{get_num_cpus, physical_cpus_or_logical, physical_cpus_with_source};
+
+        #[test]
+        fn test_gvisor() {
+            // Its `cpuN` directories are empty, which git can't keep, but they'd
+            // be skipped without a `thread_siblings_list` anyway.
+            let cpuinfo = "fixtures/cpuinfo/gvisor";
+            let sys = "fixtures/sysfs/gvisor";
+
+            assert_eq!(physical_cpus_with_source(cpuinfo, sys), None);
+            assert_eq!(physical_cpus_or_logical(cpuinfo, sys), get_num_cpus());
+        }
+    }
+
+    mod firecracker {
+        use super::super::{
+            get_num_cpus, physical_cpus_or_logical, physical_cpus_with_source,
+            physical_disagreement,
+        };
+        use Source;
+
+        #[test]
+        fn test_firecracker_smt() {
+            // 4 vCPUs, as 2 cores of 2 threads
+            let cpuinfo = "fixtures/cpuinfo/firecracker";
+            let sys = "fixtures/sysfs/firecracker";
+
+            assert_eq!(physical_cpus_with_source(cpuinfo, sys), Some((2, Source::Cpuinfo)));
+            assert_eq!(physical_disagreement(cpuinfo, sys), None);
+        }
+
+        #[test]
+        fn test_firecracker_uniprocessor() {
+            // No topology in /proc/cpuinfo, and only the CPU lists in sysfs.
+            let cpuinfo = "fixtures/cpuinfo/firecracker_up";
+            let sys = "fixtures/sysfs/firecracker_up";
+
+            assert_eq!(physical_cpus_with_source(cpuinfo, sys), None);
+            assert_eq!(physical_cpus_or_logical(cpuinfo, sys), get_num_cpus());
+        }
+    }
+
+    mod vm {
+        use super::super::{
+            cores_per_package_from_sysfs, cpus_from_cpuinfo, physical_cpus_with_source,
+            threads_per_core_from_sysfs,
+        };
+        use Source;
+
+        #[test]
+        fn test_socket_per_vcpu() {
+            // 4 vCPUs, each its own socket with 1 core
+            let cpuinfo = "fixtures/cpuinfo/vm_socket_per_vcpu";
+            let sys = "fixtures/sysfs/vm_socket_per_vcpu";
+
+            assert_eq!(physical_cpus_with_source(cpuinfo, sys), Some((4, Source::Cpuinfo)));
+            assert_eq!(cpus_from_cpuinfo(cpuinfo), Some(4));
+            assert_eq!(cores_per_package_from_sysfs(sys), Some(vec![1, 1, 1, 1]));
+
+            let threads = threads_per_core_from_sysfs(sys).unwrap();
+            assert_eq!(threads, vec![1, 1, 1, 1]);
+            assert!(!::has_smt(&threads));
+        }
+    }
+
+    mod smt_off {
+        use super::super::{
+            cores_per_package_from_sysfs, cpus_from_cpuinfo, online_from_sysfs,
+            physical_cpus_with_source, threads_per_core_from_sysfs,
+        };
+        use Source;
+
+        #[test]
+        fn test_smt_turned_off() {
+            // 6 cores and 12 threads with `smt/control` set to `off`: CPUs 6-11
+            // are offline, but still listed as siblings of CPUs 0-5
+            let cpuinfo = "fixtures/cpuinfo/smt_off";
+            let sys = "fixtures/sysfs/smt_off";
+
+            assert_eq!(physical_cpus_with_source(cpuinfo, sys), Some((6, Source::Cpuinfo)));
+            assert_eq!(cores_per_package_from_sysfs(sys), Some(vec![6]));
+            assert_eq!(cpus_from_cpuinfo(cpuinfo), Some(6));
+            assert_eq!(online_from_sysfs(sys), 6);
+
+            let threads = threads_per_core_from_sysfs(sys).unwrap();
+            assert_eq!(threads, vec![1; 6]);
+            assert!(!::has_smt(&threads));
+        }
+    }
+
+    mod arm_core_id {
+        use super::super::{
+            cores_per_package_from_sysfs, cpus_from_cpuinfo, physical_cpus_from_cpuinfo,
+            physical_cpus_with_source,
+        };
+        use Source;
+
+        #[test]
+        fn test_core_id_without_siblings() {
+            // a Snapdragon 845 with a kernel which only has `core_id` and
+            // `physical_package_id`, with each cluster of 4 cores numbered from 0
+            let cpuinfo = "fixtures/cpuinfo/snapdragon_845";
+            let sys = "fixtures/sysfs/arm_core_id";
+
+            assert_eq!(physical_cpus_from_cpuinfo(cpuinfo), None);
+            assert_eq!(cpus_from_cpuinfo(cpuinfo), Some(8));
+            assert_eq!(cores_per_package_from_sysfs(sys), Some(vec![4, 4]));
+            assert_eq!(physical_cpus_with_source(cpuinfo, sys), Some((8, Source::Sysfs)));
+        }
+    }
+
+    mod topology {
+        use super::super::full_topology_from_sysfs;
+        use {CoreType, LogicalCpu, PhysicalCore};
+
+        fn core(package: usize, ids: &[usize], core_type: CoreType, freq: u64) -> PhysicalCore {
+            PhysicalCore {
+                package: package,
+                cpus: ids
+                    .iter()
+                    .map(|&id| LogicalCpu {
+                        id: id,
+                        core_type: core_type,
+                        max_freq_khz: Some(freq),
+                    })
+                    .collect(),
+            }
+        }
+
+        #[test]
+        fn test_full_topology() {
+            // 2 nodes of 2 performance cores with 2 threads and 2 efficiency
+            // cores, a memory-only node 2 and an offline CPU 12
+            let topology = full_topology_from_sysfs("fixtures/sysfs/hybrid_numa").unwrap();
+            let (p, e) = (CoreType::Performance, CoreType::Efficiency);
+
+            assert_eq!(topology.nodes.len(), 2);
+            assert_eq!(topology.nodes[0].id, 0);
+            assert_eq!(
+                topology.nodes[0].cores,
+                vec![
+                    core(0, &[0, 1], p, 5600000),
+                    core(0, &[2, 3], p, 5600000),
+                    core(0, &[4], e, 4400000),
+                    core(0, &[5], e, 4400000),
+                ]
+            );
+            assert_eq!(topology.nodes[1].id, 1);
+            assert_eq!(
+                topology.nodes[1].cores,
+                vec![
+                    core(1, &[6, 7], p, 5600000),
+                    core(1, &[8, 9], p, 5600000),
+                    core(1, &[10], e, 4400000),
+                    core(1, &[11], e, 4400000),
+                ]
+            );
+        }
+
+        #[test]
+        fn test_full_topology_partial() {
+            // Arm capacities, without nodes or frequencies
+            let topology = full_topology_from_sysfs("fixtures/sysfs/hybrid_arm").unwrap();
+            assert_eq!(topology.nodes.len(), 1);
+            assert_eq!(topology.nodes[0].id, 0);
+
+            let cpus: Vec<LogicalCpu> = topology.nodes[0]
+                .cores
+                .iter()
+                .flat_map(|core| core.cpus.iter().cloned())
+                .collect();
+            let efficiency = cpus.iter().filter(|cpu| cpu.core_type == CoreType::Efficiency);
+            assert_eq!(efficiency.count(), 4);
+            assert!(cpus.iter().all(|cpu| cpu.core_type != CoreType::Unknown));
+
+            let topology = full_topology_from_sysfs("fixtures/sysfs/two_sockets").unwrap();
+            let cores: usize = topology.nodes.iter().map(|node| node.cores.len()).sum();
+            assert_eq!(cores, 14);
+            assert!(topology.nodes[0].cores[0].cpus.iter().all(|cpu| cpu.core_type == CoreType::Unknown));
+
+            assert!(full_topology_from_sysfs("fixtures/sysfs/missing").is_none());
+        }
+    }
+
+    mod chroot {
+        use super::super::{affinity_count, get_num_cpus, physical_cpus_or_logical};
+
+        #[test]
+        fn test_without_proc_and_sys() {
+            let physical =
+                physical_cpus_or_logical("fixtures/proc/missing/cpuinfo", "fixtures/sysfs/missing");
+            assert_eq!(physical, get_num_cpus());
+
+            // sched_getaffinity is a syscall, it doesn't need /proc
+            if let Some(affinity) = affinity_count() {
+                assert!(get_num_cpus() <= affinity);
+            }
+        }
+    }
+
+    mod masked {
+        use super::super::find_cpu_cgroup;
+        use std::path::PathBuf;
+
+        fn quota(cgroup_proc: &str, mountinfo_proc: &str, cgroupfs: &str) -> Option<usize> {
+            find_cpu_cgroup(cgroup_proc, mountinfo_proc, cgroupfs).and_then(|cgroup| cgroup.cpu_quota())
+        }
+
+        #[test]
+        fn test_proc_masked() {
+            let masked = "fixtures/cgroups/proc/cgroups/missing";
+            let mountinfo = "fixtures/cgroups/proc/cgroups/mountinfo";
+
+            assert_eq!(quota(masked, mountinfo, "fixtures/cgroups2/masked"), Some(2));
+            assert_eq!(quota(masked, mountinfo, "fixtures/cgroups/masked"), Some(3));
+            assert_eq!(quota(masked, mountinfo, "fixtures/cgroups/missing"), None);
+        }
+
+        #[test]
+        fn test_proc_readable() {
+            // The root isn't looked at when `/proc/self/cgroup` can be read.
+            let cgroup_proc = "fixtures/cgroups2/proc/cgroups/cgroup";
+            let mountinfo = "fixtures/cgroups2/proc/cgroups/mountinfo";
+            let cgroup = find_cpu_cgroup(cgroup_proc, mountinfo, "fixtures/cgroups2/masked");
+            assert_eq!(cgroup.map(|cgroup| cgroup.base), Some(PathBuf::from("/sys/fs/cgroup")));
+        }
+    }
+
+    mod rootless {
+        use super::super::{find_cpu_cgroup_under, load_cpu_cgroup};
+
+        static FIXTURES_PROC: &'static str = "fixtures/cgroups2/proc/cgroups";
+
+        #[test]
+        fn test_namespace_root() {
+            let cgroup_proc = format!("{}/cgroup_rootless", FIXTURES_PROC);
+            let mountinfo = format!("{}/mountinfo_rootless", FIXTURES_PROC);
+
+            let cgroup = load_cpu_cgroup(&cgroup_proc, &mountinfo);
+            assert_eq!(cgroup.and_then(|cgroup| cgroup.cpu_quota()), Some(2));
+        }
+
+        #[test]
+        fn test_cgroup_root() {
+            let root = "fixtures/cgroups2/cgroups/rootless";
+            let quota = |cgroup_proc: &str| {
+                find_cpu_cgroup_under(cgroup_proc, root).and_then(|cgroup| cgroup.cpu_quota())
+            };
+
+            assert_eq!(quota(&format!("{}/cgroup_rootless", FIXTURES_PROC)), Some(2));
+            // not under the root, so the root itself
+            assert_eq!(quota(&format!("{}/cgroup_docker", FIXTURES_PROC)), Some(2));
+            assert_eq!(quota("fixtures/cgroups2/proc/cgroups/missing"), Some(2));
+
+            let cgroup = find_cpu_cgroup_under(
+                format!("{}/cgroup_docker", FIXTURES_PROC),
+                "fixtures/cgroups2/missing",
+            );
+            assert!(cgroup.is_none());
+        }
+    }
+
+    mod path {
+        use super::super::load_cpu_cgroup;
+        use std::path::PathBuf;
+
+        fn cgroup_path(cgroup_proc: &str, mountinfo_proc: &str) -> Option<PathBuf> {
+            load_cpu_cgroup(cgroup_proc, mountinfo_proc).map(|cgroup| cgroup.base)
+        }
+
+        #[test]
+        fn test_cgroup_path() {
+            assert_eq!(
+                cgroup_path("fixtures/cgroups/proc/cgroups/cgroup", "fixtures/cgroups/proc/cgroups/mountinfo"),
+                Some(PathBuf::from("/sys/fs/cgroup/cpu,cpuacct"))
+            );
+            assert_eq!(
+                cgroup_path(
+                    "fixtures/cgroups2/proc/cgroups/cgroup_docker",
+                    "fixtures/cgroups2/proc/cgroups/mountinfo"
+                ),
+                Some(PathBuf::from("/sys/fs/cgroup/system.slice/docker-01abcd.scope"))
+            );
+            assert_eq!(
+                cgroup_path(
+                    "fixtures/cgroups2/proc/cgroups/cgroup_user_slice",
+                    "fixtures/cgroups2/proc/cgroups/mountinfo"
+                ),
+                Some(PathBuf::from(
+                    "/sys/fs/cgroup/user.slice/user-1000.slice/user@1000.service/app.slice/run-u42:1.service"
+                ))
+            );
+            // not in a cgroup
+            assert_eq!(
+                cgroup_path(
+                    "fixtures/cgroups/proc/cgroups/cgroup",
+                    "fixtures/cgroups/proc/cgroups/mountinfo_no_cgroups"
+                ),
+                None
+            );
+        }
+    }
+
+    mod restriction {
+        use super::super::restriction;
+        use Restriction;
+
+        #[test]
+        fn test_no_restriction() {
+            assert_eq!(restriction(8, None, None, None), None);
+            assert_eq!(restriction(8, Some(8), Some(8), Some(8)), None);
+            // a quota above the online CPUs doesn't restrict anything
+            assert_eq!(restriction(8, Some(8), None, Some(16)), None);
+        }
+
+        #[test]
+        fn test_taskset() {
+            assert_eq!(restriction(8, Some(2), None, None), Some(Restriction::Affinity));
+            // taskset inside a container with a larger cpuset
+            assert_eq!(restriction(8, Some(2), Some(4), None), Some(Restriction::Affinity));
+        }
 
-        let sub_systems = some!(fields.nth(1));
+        #[test]
+        fn test_cpuset() {
+            // docker --cpuset-cpus=0-3, which the affinity mask follows
+            assert_eq!(restriction(8, Some(4), Some(4), None), Some(Restriction::Cpuset));
+        }
 
-        let version = if sub_systems.is_empty() {
-            CgroupVersion::V2
-        } else {
-            CgroupVersion::V1
+        #[test]
+        fn test_cgroup_quota() {
+            // docker --cpus=2, with the full affinity
+            assert_eq!(restriction(8, Some(8), None, Some(2)), Some(Restriction::CgroupQuota));
+            assert_eq!(restriction(8, Some(4), Some(4), Some(2)), Some(Restriction::CgroupQuota));
+            // the affinity is lower than the quota
+            assert_eq!(restriction(8, Some(1), None, Some(2)), Some(Restriction::Affinity));
+        }
+    }
+
+    mod schedulable {
+        use super::super::{
+            isolated_cpus, load_cpuset, schedulable_cpus, Cgroup, CgroupVersion, MountInfo, Subsys,
         };
+        use std::path::Path;
 
-        if version == CgroupVersion::V1 && !sub_systems.split(',').any(|sub| sub == "cpu") {
-            return None;
+        #[test]
+        fn test_schedulable_cpus() {
+            let all: Vec<usize> = (0..8).collect();
+
+            assert_eq!(schedulable_cpus(&all, None, &[]), all);
+            // affinity
+            assert_eq!(schedulable_cpus(&[0, 1, 2, 3], None, &[]), vec![0, 1, 2, 3]);
+            // cpuset
+            assert_eq!(schedulable_cpus(&all, Some(&[0, 1, 2, 3, 4, 5]), &[]), vec![0, 1, 2, 3, 4, 5]);
+            // isolated
+            assert_eq!(schedulable_cpus(&all, None, &[4, 5]), vec![0, 1, 2, 3, 6, 7]);
+            // all of them
+            assert_eq!(
+                schedulable_cpus(&[1, 2, 3, 4, 5, 6, 7], Some(&[0, 1, 2, 3, 4, 5]), &[4, 5]),
+                vec![1, 2, 3]
+            );
         }
 
-        fields.next().map(|path| Subsys {
-            version: version,
-            base: path.to_owned(),
-        })
-    }
-}
+        #[test]
+        fn test_isolated_cpus() {
+            assert_eq!(isolated_cpus("fixtures/sysfs/isolated"), vec![4, 5]);
+            // nothing isolated
+            assert_eq!(isolated_cpus("fixtures/sysfs/hotplug"), Vec::<usize>::new());
+        }
 
-#[cfg(test)]
-mod tests {
-    mod v1 {
-        use super::super::{Cgroup, CgroupVersion, MountInfo, Subsys};
-        use std::path::{Path, PathBuf};
+        #[test]
+        fn test_load_cpuset_mount() {
+            let path = Path::new("fixtures/cgroups/proc/cgroups/mountinfo");
+            let mnt_info = MountInfo::load(path, CgroupVersion::V1, "cpuset").unwrap();
+            assert_eq!(mnt_info.mount_point, "/sys/fs/cgroup/cpuset");
 
-        // `static_in_const` feature is not stable in Rust 1.13.
-        static FIXTURES_PROC: &'static str = "fixtures/cgroups/proc/cgroups";
+            let path = Path::new("fixtures/cgroups2/proc/cgroups/cgroup");
+            let subsys = Subsys::load(path, "cpuset").unwrap();
+            assert_eq!(subsys.version, CgroupVersion::V2);
+        }
 
-        static FIXTURES_CGROUPS: &'static str = "fixtures/cgroups/cgroups";
+        #[test]
+        fn test_cgroup_cpuset() {
+            let cgroup = Cgroup::new(CgroupVersion::V1, Path::new("fixtures/cgroups/cgroups/cpuset").into());
+            assert_eq!(cgroup.cpuset(), Some(vec![0, 1, 2, 3, 4, 5]));
 
-        macro_rules! join {
-            ($base:expr, $($path:expr),+) => ({
-                Path::new($base)
-                    $(.join($path))+
-            })
+            let cgroup = Cgroup::new(CgroupVersion::V2, Path::new("fixtures/cgroups2/cgroups/cpuset").into());
+            assert_eq!(cgroup.cpuset(), Some(vec![2, 3, 4, 5, 6, 7]));
+
+            // no cpuset files
+            let cgroup = Cgroup::new(CgroupVersion::V2, Path::new("fixtures/cgroups2/cgroups/good").into());
+            assert_eq!(cgroup.cpuset(), None);
         }
 
         #[test]
-        fn test_load_mountinfo() {
-            // test only one optional fields
-            let path = join!(FIXTURES_PROC, "mountinfo");
+        fn test_cset_shield() {
+            // `cset shield` moved the process to the `user` cpuset, leaving 0-1
+            // to the `system` one.
+            let cgroup_proc = "fixtures/cgroups/proc/cgroups/cgroup_cset";
+            let shielded = Some(vec![2, 3, 4, 5, 6, 7]);
+
+            // on the legacy cpuset filesystem it mounted itself
+            let mountinfo = "fixtures/cgroups/proc/cgroups/mountinfo_cset";
+            assert_eq!(load_cpuset(cgroup_proc, mountinfo), shielded);
+
+            // on the cgroups v1 cpuset controller
+            let mountinfo = "fixtures/cgroups/proc/cgroups/mountinfo_cset_v1";
+            assert_eq!(load_cpuset(cgroup_proc, mountinfo), shielded);
+
+            // without the cpuset controller mounted
+            let mountinfo = "fixtures/cgroups/proc/cgroups/mountinfo_no_cgroups";
+            assert_eq!(load_cpuset(cgroup_proc, mountinfo), None);
+        }
+    }
 
-            let mnt_info = MountInfo::load_cpu(path, CgroupVersion::V1).unwrap();
+    mod refresh {
+        use super::super::{cached_cgroups_quota, cgroups_quota, get_num_cpus};
+        use libc;
+        use std::mem;
+        use std::sync::{Arc, Barrier};
+        use std::thread;
+
+        fn pin_to_first_cpu<F: FnOnce()>(f: F) {
+            let mut original: libc::cpu_set_t = unsafe { mem::zeroed() };
+            let mut pinned: libc::cpu_set_t = unsafe { mem::zeroed() };
+            let size = mem::size_of::<libc::cpu_set_t>();
+            unsafe {
+                assert_eq!(libc::sched_getaffinity(0, size, &mut original), 0);
+                let first = (0..libc::CPU_SETSIZE as usize)
+                    .find(|&i| libc::CPU_ISSET(i, &original))
+                    .unwrap();
+                libc::CPU_SET(first, &mut pinned);
+                assert_eq!(libc::sched_setaffinity(0, size, &pinned), 0);
+            }
 
-            assert_eq!(mnt_info.root, "/");
-            assert_eq!(mnt_info.mount_point, "/sys/fs/cgroup/cpu,cpuacct");
+            f();
 
-            // test zero optional field
-            let path = join!(FIXTURES_PROC, "mountinfo_zero_opt");
+            unsafe {
+                assert_eq!(libc::sched_setaffinity(0, size, &original), 0);
+            }
+        }
 
-            let mnt_info = MountInfo::load_cpu(path, CgroupVersion::V1).unwrap();
+        #[test]
+        fn test_concurrent_first_call() {
+            let barrier = Arc::new(Barrier::new(8));
+            let threads: Vec<_> = (0..8)
+                .map(|_| {
+                    let barrier = barrier.clone();
+                    thread::spawn(move || {
+                        barrier.wait();
+                        cached_cgroups_quota()
+                    })
+                })
+                .collect();
+
+            let quota = cgroups_quota();
+            for thread in threads {
+                assert_eq!(thread.join().unwrap().unwrap_or(0), quota);
+            }
+        }
 
-            assert_eq!(mnt_info.root, "/");
-            assert_eq!(mnt_info.mount_point, "/sys/fs/cgroup/cpu,cpuacct");
+        #[cfg(not(feature = "no_affinity"))]
+        #[test]
+        fn test_refresh_affinity() {
+            use std::sync::atomic::{AtomicUsize, Ordering};
 
-            // test multi optional fields
-            let path = join!(FIXTURES_PROC, "mountinfo_multi_opt");
+            let before = get_num_cpus();
+            if before < 2 {
+                return;
+            }
+            // `refresh()` without the shared cache of `get()`
+            let cache = AtomicUsize::new(0);
+            let detector = AtomicUsize::new(0);
+            let before = ::refreshed_cpus(&cache, &detector);
 
-            let mnt_info = MountInfo::load_cpu(path, CgroupVersion::V1).unwrap();
+            pin_to_first_cpu(|| {
+                assert_eq!(::refreshed_cpus(&cache, &detector), ::env_bounds(1));
+                assert_eq!(cache.load(Ordering::SeqCst), ::env_bounds(1));
+            });
 
-            assert_eq!(mnt_info.root, "/");
-            assert_eq!(mnt_info.mount_point, "/sys/fs/cgroup/cpu,cpuacct");
+            assert_eq!(::refreshed_cpus(&cache, &detector), before);
         }
 
+        #[cfg(feature = "no_affinity")]
         #[test]
-        fn test_load_subsys() {
-            let path = join!(FIXTURES_PROC, "cgroup");
+        fn test_no_affinity() {
+            use super::super::refresh_num_cpus;
 
-            let subsys = Subsys::load_cpu(path).unwrap();
+            let online = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) } as usize;
+            let before = get_num_cpus();
 
-            assert_eq!(subsys.base, "/");
-            assert_eq!(subsys.version, CgroupVersion::V1);
+            // The cgroups quota may still be lower than the online CPUs.
+            pin_to_first_cpu(|| assert_eq!(refresh_num_cpus(), before));
+            assert!(before <= online);
         }
+    }
+
+    mod conflict {
+        use super::super::{cgroup_cpus_at, quota_within_cpuset, Cgroup, CgroupVersion};
+        use std::path::PathBuf;
 
         #[test]
-        fn test_cgroup_mount() {
-            let cases = &[
-                ("/", "/sys/fs/cgroup/cpu", "/", Some("/sys/fs/cgroup/cpu")),
-                (
-                    "/docker/01abcd",
-                    "/sys/fs/cgroup/cpu",
-                    "/docker/01abcd",
-                    Some("/sys/fs/cgroup/cpu"),
-                ),
-                (
-                    "/docker/01abcd",
-                    "/sys/fs/cgroup/cpu",
-                    "/docker/01abcd/",
-                    Some("/sys/fs/cgroup/cpu"),
-                ),
-                (
-                    "/docker/01abcd",
-                    "/sys/fs/cgroup/cpu",
-                    "/docker/01abcd/large",
-                    Some("/sys/fs/cgroup/cpu/large"),
-                ),
-                // fails
-                ("/docker/01abcd", "/sys/fs/cgroup/cpu", "/", None),
-                ("/docker/01abcd", "/sys/fs/cgroup/cpu", "/docker", None),
-                ("/docker/01abcd", "/sys/fs/cgroup/cpu", "/elsewhere", None),
-                (
-                    "/docker/01abcd",
-                    "/sys/fs/cgroup/cpu",
-                    "/docker/01abcd-other-dir",
-                    None,
-                ),
-            ];
+        fn test_quota_larger_than_cpuset() {
+            // a quota of 4 CPUs, in a cpuset of 2
+            let path = "fixtures/cgroups2/cgroups/conflict";
+            let cgroup = Cgroup::new(CgroupVersion::V2, PathBuf::from(path));
+            let cpuset = cgroup.cpuset().map(|cpus| cpus.len());
+
+            assert_eq!(cgroup.cpu_quota(), Some(4));
+            assert_eq!(quota_within_cpuset(cgroup.cpu_quota(), cpuset), Some(2));
+            assert_eq!(cgroup_cpus_at(path, 16), Some(2));
+        }
 
-            for &(root, mount_point, subsys, expected) in cases.iter() {
-                let mnt_info = MountInfo {
-                    version: CgroupVersion::V1,
-                    root: root.into(),
-                    mount_point: mount_point.into(),
-                };
-                let subsys = Subsys {
-                    version: CgroupVersion::V1,
-                    base: subsys.into(),
-                };
+        #[test]
+        fn test_quota_within_cpuset() {
+            assert_eq!(quota_within_cpuset(Some(2), Some(8)), Some(2));
+            assert_eq!(quota_within_cpuset(Some(2), None), Some(2));
+            assert_eq!(quota_within_cpuset(None, Some(8)), None);
+        }
+    }
 
-                let actual = Cgroup::translate(mnt_info, subsys).map(|c| c.base);
-                let expected = expected.map(PathBuf::from);
-                assert_eq!(actual, expected);
-            }
+    mod hint {
+        use super::super::{capped_by_quota, Cgroup, CgroupVersion};
+        use std::path::PathBuf;
+
+        #[test]
+        fn test_quota_as_cap_or_hint() {
+            let v1 = Cgroup::new(CgroupVersion::V1, PathBuf::from("fixtures/cgroups/cgroups/good"));
+            let v2 = Cgroup::new(
+                CgroupVersion::V2,
+                PathBuf::from("fixtures/cgroups2/cgroups/fractional"),
+            );
+
+            // as a cap, the quota is the count, as a hint the 16 CPUs of the
+            // affinity are
+            assert_eq!(capped_by_quota(16, v1.cpu_quota()), 6);
+            assert_eq!(capped_by_quota(16, v2.cpu_quota()), 3);
+            assert_eq!(capped_by_quota(16, None), 16);
+            // and either way never more than the affinity
+            assert_eq!(capped_by_quota(2, v1.cpu_quota()), 2);
         }
+    }
+
+    mod cgroup_at {
+        use super::super::{cgroup_cpus_at, hardware_from_sysfs};
 
         #[test]
-        fn test_cgroup_cpu_quota() {
-            let cgroup = Cgroup::new(CgroupVersion::V1, join!(FIXTURES_CGROUPS, "good"));
-            assert_eq!(cgroup.cpu_quota(), Some(6));
+        fn test_cgroup_cpus_at_v2() {
+            // no limits, only the online cpus
+            assert_eq!(cgroup_cpus_at("fixtures/cgroups2/cgroups/siblings", 16), Some(16));
+            // quota of 2.5 cpus, cpuset of 8
+            assert_eq!(cgroup_cpus_at("fixtures/cgroups2/cgroups/siblings/web", 16), Some(3));
+            // no quota, cpuset of 2
+            assert_eq!(cgroup_cpus_at("fixtures/cgroups2/cgroups/siblings/batch", 16), Some(2));
+            // fewer cpus online than allowed
+            assert_eq!(cgroup_cpus_at("fixtures/cgroups2/cgroups/siblings/web", 2), Some(2));
         }
 
         #[test]
-        fn test_cgroup_cpu_quota_divide_by_zero() {
-            let cgroup = Cgroup::new(CgroupVersion::V1, join!(FIXTURES_CGROUPS, "zero-period"));
-            assert!(cgroup.quota_us().is_some());
-            assert_eq!(cgroup.period_us(), Some(0));
-            assert_eq!(cgroup.cpu_quota(), None);
+        fn test_cgroup_cpus_at_v1() {
+            assert_eq!(cgroup_cpus_at("fixtures/cgroups/cgroups/good", 16), Some(6));
+            assert_eq!(cgroup_cpus_at("fixtures/cgroups/cgroups/cpuset", 16), Some(6));
+            // a period of 0 is no quota
+            assert_eq!(cgroup_cpus_at("fixtures/cgroups/cgroups/zero-period", 16), Some(16));
         }
 
         #[test]
-        fn test_cgroup_cpu_quota_ceil() {
-            let cgroup = Cgroup::new(CgroupVersion::V1, join!(FIXTURES_CGROUPS, "ceil"));
-            assert_eq!(cgroup.cpu_quota(), Some(2));
+        fn test_available_and_installed() {
+            let installed = hardware_from_sysfs("fixtures/sysfs/two_sockets");
+            let available = cgroup_cpus_at("fixtures/cgroups2/cgroups/siblings/web", installed);
+            // the quota of 2.5 CPUs out of the 28 installed
+            assert_eq!((available, installed), (Some(3), 28));
+
+            // without one
+            let available = cgroup_cpus_at("fixtures/cgroups2/cgroups/siblings", installed);
+            assert_eq!((available, installed), (Some(28), 28));
+        }
+
+        #[test]
+        fn test_cgroup_cpus_at_invalid() {
+            assert_eq!(cgroup_cpus_at("fixtures/sysfs/hotplug", 16), None);
+            assert_eq!(cgroup_cpus_at("fixtures/cgroups2/cgroups/missing", 16), None);
         }
     }
 
-    mod v2 {
-        use super::super::{Cgroup, CgroupVersion, MountInfo, Subsys};
-        use std::path::{Path, PathBuf};
+    mod shares {
+        use super::super::{effective_cpus_estimate, Cgroup};
+        use std::path::Path;
 
-        // `static_in_const` feature is not stable in Rust 1.13.
-        static FIXTURES_PROC: &'static str = "fixtures/cgroups2/proc/cgroups";
+        // The cgroup at `dir`, with its parent as the mount point.
+        fn estimate(dir: &str, cpus: usize) -> usize {
+            let mount = Path::new(dir).parent().unwrap().to_path_buf();
+            effective_cpus_estimate(Cgroup::at(dir).map(|cgroup| cgroup.within(mount)), cpus)
+        }
 
-        static FIXTURES_CGROUPS: &'static str = "fixtures/cgroups2/cgroups";
+        #[test]
+        fn test_shares_v2() {
+            // weights of 100, 300 and 400
+            assert_eq!(estimate("fixtures/cgroups2/cgroups/shares/a", 8), 1);
+            assert_eq!(estimate("fixtures/cgroups2/cgroups/shares/b", 8), 3);
+            assert_eq!(estimate("fixtures/cgroups2/cgroups/shares/c", 8), 4);
+            // rounded up, and never more than the cpus
+            assert_eq!(estimate("fixtures/cgroups2/cgroups/shares/a", 12), 2);
+            assert_eq!(estimate("fixtures/cgroups2/cgroups/shares/c", 1), 1);
+        }
 
-        macro_rules! join {
-            ($base:expr, $($path:expr),+) => ({
-                Path::new($base)
-                    $(.join($path))+
-            })
+        #[test]
+        fn test_shares_v1() {
+            // shares of 1024 and 3072, no quota
+            assert_eq!(estimate("fixtures/cgroups/cgroups/shares/a", 8), 2);
+            assert_eq!(estimate("fixtures/cgroups/cgroups/shares/b", 8), 6);
         }
 
         #[test]
-        fn test_load_mountinfo() {
-            // test only one optional fields
-            let path = join!(FIXTURES_PROC, "mountinfo");
+        fn test_quota_wins() {
+            assert_eq!(estimate("fixtures/cgroups2/cgroups/siblings/web", 3), 3);
+            assert_eq!(estimate("fixtures/cgroups/cgroups/good", 6), 6);
+        }
 
-            let mnt_info = MountInfo::load_cpu(path, CgroupVersion::V2).unwrap();
+        #[test]
+        fn test_no_shares() {
+            // no weight file
+            assert_eq!(estimate("fixtures/cgroups2/cgroups/siblings/batch", 2), 2);
+            // not in a cgroup
+            assert_eq!(effective_cpus_estimate(None, 4), 4);
+        }
 
-            assert_eq!(mnt_info.root, "/");
-            assert_eq!(mnt_info.mount_point, "/sys/fs/cgroup");
+        #[test]
+        fn test_shares_v1_symlinks() {
+            // `cpu` and `cpuacct` link to `cpu,cpuacct`, which shares the CPUs
+            // with `other` only
+            let dir = "fixtures/cgroups/cgroups/links/cpu,cpuacct";
+            assert_eq!(estimate(dir, 8), 4);
+
+            // at the mount point, as in a container with its own v1 mounts
+            assert_eq!(effective_cpus_estimate(Cgroup::at(dir), 8), 8);
         }
+    }
+
+    mod proc_stat {
+        use super::super::{cpus_from_cpuinfo, cpus_from_proc_stat, max_threads_from_limits};
 
         #[test]
-        fn test_load_subsys() {
-            let path = join!(FIXTURES_PROC, "cgroup");
+        fn test_cpus_from_proc_stat() {
+            assert_eq!(cpus_from_proc_stat("fixtures/proc/stat"), Some(4));
+            assert_eq!(cpus_from_proc_stat("fixtures/proc/missing"), None);
+            // no cpuN lines
+            assert_eq!(cpus_from_proc_stat("fixtures/cgroups/proc/cgroups/cgroup"), None);
+        }
 
-            let subsys = Subsys::load_cpu(path).unwrap();
+        #[test]
+        fn test_cpus_from_cpuinfo() {
+            assert_eq!(cpus_from_cpuinfo("fixtures/cpuinfo/armv7_imx6"), Some(4));
+            assert_eq!(cpus_from_cpuinfo("fixtures/cpuinfo/epyc_7302p"), Some(32));
+            assert_eq!(cpus_from_cpuinfo("fixtures/cpuinfo/missing"), None);
+            assert_eq!(cpus_from_cpuinfo("fixtures/proc/stat"), None);
+        }
 
-            assert_eq!(subsys.base, "/");
-            assert_eq!(subsys.version, CgroupVersion::V2);
+        #[test]
+        fn test_max_threads_from_limits() {
+            assert_eq!(max_threads_from_limits("fixtures/proc/limits"), Some(4096));
+            assert_eq!(max_threads_from_limits("fixtures/proc/limits_unlimited"), None);
+            assert_eq!(max_threads_from_limits("fixtures/proc/missing"), None);
+            // no `Max processes` line
+            assert_eq!(max_threads_from_limits("fixtures/proc/stat"), None);
         }
+    }
+
+    mod sysfs {
+        use super::super::{
+            allowed_cpus_per_node, cores_per_package_from_sysfs, cpu_states_from_sysfs,
+            cpus_excluding, hybrid_counts_from_sysfs, isolated_cpus, node_cpus_from_sysfs,
+            nohz_full_cpus, numa_nodes_from_sysfs, online_cpu_dirs, parse_cpu_list, physical_cpus,
+            read_file, threads_per_core_from_sysfs,
+        };
+        use std::path::Path;
+
+        // A single socket with 4 cores and 2 threads each.
+        static FULL: &'static str = "fixtures/sysfs/full";
 
         #[test]
-        fn test_load_subsys_multi() {
-            let path = join!(FIXTURES_PROC, "cgroup_multi");
+        fn test_physical_from_sysfs_root() {
+            // without /proc/cpuinfo, the core count comes from the sysfs topology
+            assert_eq!(physical_cpus("fixtures/cpuinfo/missing", FULL), Some(4));
+            assert_eq!(physical_cpus("fixtures/cpuinfo/missing", "fixtures/sysfs/missing"), None);
+            // the sysfs topology wins when /proc/cpuinfo disagrees with it
+            assert_eq!(physical_cpus("fixtures/cpuinfo/epyc_7302p", FULL), Some(4));
+        }
 
-            let subsys = Subsys::load_cpu(path).unwrap();
+        #[test]
+        fn test_full_tree() {
+            assert_eq!(cores_per_package_from_sysfs(FULL), Some(vec![4]));
+            assert_eq!(hybrid_counts_from_sysfs(FULL), None);
+            assert_eq!(isolated_cpus(FULL), Vec::<usize>::new());
 
-            assert_eq!(subsys.base, "/");
-            assert_eq!(subsys.version, CgroupVersion::V1);
+            let states = cpu_states_from_sysfs(FULL);
+            assert_eq!((states.possible, states.present, states.online), (8, 8, 8));
         }
 
         #[test]
-        fn test_cgroup_mount() {
-            let cases = &[
-                ("/", "/sys/fs/cgroup/cpu", "/", Some("/sys/fs/cgroup/cpu")),
-                (
-                    "/docker/01abcd",
-                    "/sys/fs/cgroup/cpu",
-                    "/docker/01abcd",
-                    Some("/sys/fs/cgroup/cpu"),
-                ),
-                (
-                    "/docker/01abcd",
-                    "/sys/fs/cgroup/cpu",
-                    "/docker/01abcd/",
-                    Some("/sys/fs/cgroup/cpu"),
-                ),
-                (
-                    "/docker/01abcd",
-                    "/sys/fs/cgroup/cpu",
-                    "/docker/01abcd/large",
-                    Some("/sys/fs/cgroup/cpu/large"),
-                ),
-                // fails
-                ("/docker/01abcd", "/sys/fs/cgroup/cpu", "/", None),
-                ("/docker/01abcd", "/sys/fs/cgroup/cpu", "/docker", None),
-                ("/docker/01abcd", "/sys/fs/cgroup/cpu", "/elsewhere", None),
-                (
-                    "/docker/01abcd",
-                    "/sys/fs/cgroup/cpu",
-                    "/docker/01abcd-other-dir",
-                    None,
-                ),
-            ];
+        fn test_numa_nodes() {
+            assert_eq!(numa_nodes_from_sysfs(FULL), 1);
+            assert_eq!(numa_nodes_from_sysfs("fixtures/sysfs/two_sockets"), 2);
+            // without NUMA support
+            assert_eq!(numa_nodes_from_sysfs("fixtures/sysfs/hotplug"), 1);
+        }
 
-            for &(root, mount_point, subsys, expected) in cases.iter() {
-                let mnt_info = MountInfo {
-                    version: CgroupVersion::V1,
-                    root: root.into(),
-                    mount_point: mount_point.into(),
-                };
-                let subsys = Subsys {
-                    version: CgroupVersion::V1,
-                    base: subsys.into(),
-                };
+        #[test]
+        fn test_sparse_online() {
+            // cpu2 is missing, cpu3 is offline but still has a directory
+            let sys = "fixtures/sysfs/sparse";
+            let cpus = Path::new(sys).join("devices/system/cpu");
+            let expected: Vec<_> = [0, 1, 4, 5, 6, 7]
+                .iter()
+                .map(|cpu| cpus.join(format!("cpu{}", cpu)))
+                .collect();
+
+            let online = parse_cpu_list(&read_file(cpus.join("online")).unwrap());
+            assert_eq!(online_cpu_dirs(sys, Some(&online)), Some(expected));
+            assert_eq!(cores_per_package_from_sysfs(sys), Some(vec![6]));
+            // two big online cores, the offline one isn't counted
+            assert_eq!(hybrid_counts_from_sysfs(sys), Some((2, 4)));
+            assert_eq!(cpu_states_from_sysfs(sys).online, 6);
+        }
 
-                let actual = Cgroup::translate(mnt_info, subsys).map(|c| c.base);
-                let expected = expected.map(PathBuf::from);
-                assert_eq!(actual, expected);
-            }
+        #[test]
+        fn test_allowed_cpus_per_node() {
+            let nodes = node_cpus_from_sysfs("fixtures/sysfs/two_sockets");
+            let allowed: Vec<usize> = (8..16).collect();
+
+            assert_eq!(
+                allowed_cpus_per_node(&allowed, nodes),
+                vec![vec![8, 9, 10, 11], vec![12, 13, 14, 15]]
+            );
+            // a node without allowed CPUs is left out
+            let nodes = node_cpus_from_sysfs("fixtures/sysfs/two_sockets");
+            assert_eq!(allowed_cpus_per_node(&[0, 1], nodes), vec![vec![0, 1]]);
+            // without NUMA support
+            assert_eq!(allowed_cpus_per_node(&[0, 1], None), vec![vec![0, 1]]);
         }
 
         #[test]
-        fn test_cgroup_cpu_quota() {
-            let cgroup = Cgroup::new(CgroupVersion::V2, join!(FIXTURES_CGROUPS, "good"));
-            assert_eq!(cgroup.cpu_quota(), Some(6));
+        fn test_cpus_per_node() {
+            let sys = "fixtures/sysfs/two_sockets";
+            let per_node: Vec<usize> = node_cpus_from_sysfs(sys)
+                .unwrap()
+                .iter()
+                .map(|cpus| cpus.len())
+                .collect();
+
+            assert_eq!(per_node, vec![12, 16]);
+            assert_eq!(per_node.iter().sum::<usize>(), cpu_states_from_sysfs(sys).possible);
+
+            assert_eq!(node_cpus_from_sysfs(FULL).map(|nodes| nodes.len()), Some(1));
+            // without NUMA support
+            assert_eq!(node_cpus_from_sysfs("fixtures/sysfs/hotplug"), None);
         }
 
         #[test]
-        fn test_cgroup_cpu_quota_divide_by_zero() {
-            let cgroup = Cgroup::new(CgroupVersion::V2, join!(FIXTURES_CGROUPS, "zero-period"));
-            let period = cgroup.max().map(|max| max.1);
+        fn test_threads_per_core() {
+            // 2 P-cores with SMT, then 4 E-cores without
+            assert_eq!(
+                threads_per_core_from_sysfs("fixtures/sysfs/hybrid_smt"),
+                Some(vec![2, 2, 1, 1, 1, 1])
+            );
+            assert_eq!(threads_per_core_from_sysfs(FULL), Some(vec![2, 2, 2, 2]));
+            assert_eq!(threads_per_core_from_sysfs("fixtures/sysfs/missing"), None);
+        }
 
-            assert_eq!(period, Some(0));
-            assert_eq!(cgroup.cpu_quota(), None);
+        #[test]
+        fn test_nohz_full() {
+            let allowed: Vec<usize> = (0..8).collect();
+
+            let nohz_full = nohz_full_cpus("fixtures/sysfs/nohz_full");
+            assert_eq!(nohz_full, vec![2, 3]);
+            assert_eq!(cpus_excluding(8, &allowed, &nohz_full), 6);
+            // the quota is lower anyway
+            assert_eq!(cpus_excluding(4, &allowed, &nohz_full), 4);
+            // only the allowed ones count
+            assert_eq!(cpus_excluding(2, &[3, 4], &nohz_full), 1);
+
+            assert_eq!(nohz_full_cpus(FULL), Vec::<usize>::new());
+            assert_eq!(nohz_full_cpus("fixtures/sysfs/missing"), Vec::<usize>::new());
         }
 
         #[test]
-        fn test_cgroup_cpu_quota_ceil() {
-            let cgroup = Cgroup::new(CgroupVersion::V2, join!(FIXTURES_CGROUPS, "ceil"));
-            assert_eq!(cgroup.cpu_quota(), Some(2));
+        fn test_min_node_cpus() {
+            use smallest_node;
+
+            let per_node: Vec<usize> = node_cpus_from_sysfs("fixtures/sysfs/asymmetric_numa")
+                .unwrap()
+                .iter()
+                .map(|cpus| cpus.len())
+                .collect();
+
+            // node2 only has memory
+            assert_eq!(per_node, vec![6, 2, 0]);
+            assert_eq!(smallest_node(&per_node, 8), 2);
+            assert_eq!(smallest_node(&per_node, 1), 1);
+            assert_eq!(smallest_node(&[0], 4), 4);
         }
     }
 }
@@ -0,0 +1,58 @@
+// Included by src/lib.rs only with a rustc new enough for `NonZeroUsize` and
+// the `clippy::` lints below, see build.rs. Clippy checks against the 1.13 of
+// clippy.toml.
+use std::num::NonZeroUsize;
+
+/// Returns [`get()`] as a `NonZeroUsize`, since it's always at least `1`.
+///
+/// This saves the `NonZeroUsize::new(num_cpus::get()).unwrap()` of APIs which
+/// take a non zero count. It needs Rust 1.31, and isn't there with older ones.
+///
+/// # Examples
+///
+/// ```
+/// let cpus = num_cpus::get_nonzero();
+/// assert_eq!(cpus.get(), num_cpus::get());
+/// ```
+///
+/// [`get()`]: fn.get.html
+#[allow(clippy::incompatible_msrv)]
+#[inline]
+pub fn get_nonzero() -> NonZeroUsize {
+    nonzero(::get())
+}
+
+/// Returns [`get_physical()`] as a `NonZeroUsize`, since it's always at least `1`.
+///
+/// Like [`get_nonzero()`], this needs Rust 1.31.
+///
+/// # Examples
+///
+/// ```
+/// let cores = num_cpus::get_physical_nonzero();
+/// assert_eq!(cores.get(), num_cpus::get_physical());
+/// ```
+///
+/// [`get_physical()`]: fn.get_physical.html
+/// [`get_nonzero()`]: fn.get_nonzero.html
+#[allow(clippy::incompatible_msrv)]
+#[inline]
+pub fn get_physical_nonzero() -> NonZeroUsize {
+    nonzero(::get_physical())
+}
+
+#[allow(clippy::incompatible_msrv)]
+pub fn nonzero(cpus: usize) -> NonZeroUsize {
+    // `get()` and `get_physical()` already clamp, this only keeps it obvious.
+    unsafe { NonZeroUsize::new_unchecked(::clamp_min_one(cpus)) }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_get_nonzero() {
+        assert_eq!(super::get_nonzero().get(), ::get());
+        assert_eq!(super::get_physical_nonzero().get(), ::get_physical());
+        assert_eq!(super::nonzero(0).get(), 1);
+    }
+}
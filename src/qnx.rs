@@ -0,0 +1,88 @@
+use std::collections::HashSet;
+use std::mem;
+
+// `struct syspage_array_info` of <sys/syspage.h>, whose fields the libc crate
+// keeps private.
+#[repr(C)]
+struct SyspageArrayInfo {
+    entry_off: u16,
+    entry_size: u16,
+    element_size: u16,
+}
+
+// The beginning of `struct cpuinfo_entry`, one per logical CPU.
+#[repr(C)]
+struct CpuinfoEntry {
+    cpu: u32,
+    speed: u32,
+    flags: u32,
+    smp_hwcoreid: u32,
+}
+
+pub fn get_num_physical_cpus_probe() -> Option<usize> {
+    hw_core_ids().and_then(|ids| physical_from_core_ids(&ids))
+}
+
+/// Reads the hardware core id of every CPU from the `cpuinfo` section of the
+/// system page, as `SYSPAGE_ENTRY(cpuinfo)` does.
+fn hw_core_ids() -> Option<Vec<u32>> {
+    unsafe {
+        let syspage = libc::_syspage_ptr;
+        if syspage.is_null() {
+            return None;
+        }
+
+        let info = &(*syspage).new_cpuinfo as *const libc::syspage_array_info as *const SyspageArrayInfo;
+        let element_size = (*info).element_size as usize;
+        if element_size < mem::size_of::<CpuinfoEntry>() {
+            return None;
+        }
+
+        let entries = (syspage as *const u8).offset((*info).entry_off as isize);
+        let ids = (0..(*syspage).num_cpu as usize)
+            .map(|cpu| {
+                let entry = entries.offset((cpu * element_size) as isize) as *const CpuinfoEntry;
+                (*entry).smp_hwcoreid
+            })
+            .collect();
+
+        Some(ids)
+    }
+}
+
+// The startup program of an SMT capable board gives the threads of a core the
+// same hardware id. When every CPU has its own, there's nothing telling them
+// apart from the physical cores, so this leaves it to the logical count.
+fn physical_from_core_ids(ids: &[u32]) -> Option<usize> {
+    let cores = ids.iter().collect::<HashSet<_>>().len();
+
+    if cores == 0 || cores == ids.len() {
+        None
+    } else {
+        Some(cores)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::physical_from_core_ids;
+
+    #[test]
+    fn test_physical_from_core_ids() {
+        assert_eq!(physical_from_core_ids(&[0, 0, 1, 1, 2, 2, 3, 3]), Some(4));
+        // no SMT info
+        assert_eq!(physical_from_core_ids(&[0, 1, 2, 3]), None);
+        assert_eq!(physical_from_core_ids(&[]), None);
+    }
+
+    #[test]
+    fn test_physical_le_logical() {
+        let logical = super::super::get_num_cpus();
+        assert!(logical >= 1);
+
+        // `None` without a system page, or without SMT info in it
+        if let Some(physical) = super::get_num_physical_cpus_probe() {
+            assert!(physical >= 1 && physical <= logical);
+        }
+    }
+}
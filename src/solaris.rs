@@ -0,0 +1,160 @@
+use std::collections::HashSet;
+use std::ptr;
+
+use libc::{self, c_char, c_int, c_void};
+
+// The handles of <kstat.h>, only ever used through pointers.
+enum KstatCtl {}
+enum Kstat {}
+
+// `kstat_named_t`: a name of `KSTAT_STRLEN` bytes, the type of the value, and
+// the value itself, a union of at most 16 bytes aligned as a 64-bit integer.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct KstatNamed {
+    name: [c_char; 31],
+    data_type: u8,
+    value: [u64; 2],
+}
+
+const KSTAT_DATA_CHAR: u8 = 0;
+const KSTAT_DATA_INT32: u8 = 1;
+const KSTAT_DATA_UINT32: u8 = 2;
+const KSTAT_DATA_INT64: u8 = 3;
+const KSTAT_DATA_UINT64: u8 = 4;
+
+#[link(name = "kstat")]
+extern "C" {
+    fn kstat_open() -> *mut KstatCtl;
+    fn kstat_close(kc: *mut KstatCtl) -> c_int;
+    fn kstat_lookup(
+        kc: *mut KstatCtl,
+        module: *const c_char,
+        instance: c_int,
+        name: *const c_char,
+    ) -> *mut Kstat;
+    fn kstat_read(kc: *mut KstatCtl, ksp: *mut Kstat, buf: *mut c_void) -> c_int;
+    fn kstat_data_lookup(ksp: *mut Kstat, name: *const c_char) -> *mut c_void;
+}
+
+pub fn get_num_physical_cpus_probe() -> Option<usize> {
+    cpu_cores().and_then(|cores| physical_from_cores(&cores))
+}
+
+/// Reads the `(chip_id, core_id)` of every online CPU from its `cpu_info`
+/// kstat, as `kstat -p cpu_info:::core_id` lists them.
+///
+/// Returns `None` if kstat can't be opened, such as in a zone without
+/// `/dev/kstat`, or if a CPU doesn't report its core.
+fn cpu_cores() -> Option<Vec<(i64, i64)>> {
+    // The CPU ids are below the maximum number of CPUs, but may have gaps.
+    let max = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_MAX) };
+    if max < 1 {
+        return None;
+    }
+
+    unsafe {
+        let kc = kstat_open();
+        if kc.is_null() {
+            return None;
+        }
+
+        let mut cores = Some(Vec::new());
+        for id in 0..max as c_int {
+            let ksp = kstat_lookup(kc, b"cpu_info\0".as_ptr() as *const c_char, id, ptr::null());
+            if ksp.is_null() || kstat_read(kc, ksp, ptr::null_mut()) == -1 {
+                continue;
+            }
+
+            // An offline CPU keeps its kstat, in the `off-line` state.
+            match named_str(ksp, b"state\0") {
+                Some(ref state) if state == "on-line" || state == "no-intr" => {}
+                _ => continue,
+            }
+
+            match (named_int(ksp, b"chip_id\0"), named_int(ksp, b"core_id\0")) {
+                (Some(chip), Some(core)) => {
+                    if let Some(ref mut cores) = cores {
+                        cores.push((chip, core));
+                    }
+                }
+                _ => {
+                    cores = None;
+                    break;
+                }
+            }
+        }
+
+        kstat_close(kc);
+        cores
+    }
+}
+
+unsafe fn named(ksp: *mut Kstat, name: &[u8]) -> Option<KstatNamed> {
+    let named = kstat_data_lookup(ksp, name.as_ptr() as *const c_char) as *const KstatNamed;
+    if named.is_null() {
+        None
+    } else {
+        Some(*named)
+    }
+}
+
+// `core_id` and `chip_id` are longs, 64-bit in a 64-bit process.
+unsafe fn named_int(ksp: *mut Kstat, name: &[u8]) -> Option<i64> {
+    let named = match named(ksp, name) {
+        Some(named) => named,
+        None => return None,
+    };
+    let low = &named.value as *const [u64; 2] as *const u32;
+    match named.data_type {
+        KSTAT_DATA_INT32 => Some(*(low as *const i32) as i64),
+        KSTAT_DATA_UINT32 => Some(*low as i64),
+        KSTAT_DATA_INT64 | KSTAT_DATA_UINT64 => Some(named.value[0] as i64),
+        _ => None,
+    }
+}
+
+unsafe fn named_str(ksp: *mut Kstat, name: &[u8]) -> Option<String> {
+    let named = match named(ksp, name) {
+        Some(named) if named.data_type == KSTAT_DATA_CHAR => named,
+        _ => return None,
+    };
+    let bytes = &*(&named.value as *const [u64; 2] as *const [u8; 16]);
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8(bytes[..len].to_vec()).ok()
+}
+
+// The threads of a core share its `core_id`, which is only unique on its chip.
+fn physical_from_cores(cores: &[(i64, i64)]) -> Option<usize> {
+    match cores.iter().collect::<HashSet<_>>().len() {
+        0 => None,
+        count => Some(count),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::physical_from_cores;
+
+    #[test]
+    fn test_physical_from_cores() {
+        // 2 chips of 2 cores with 2 threads each
+        let cores = [(0, 0), (0, 0), (0, 1), (0, 1), (1, 0), (1, 0), (1, 1), (1, 1)];
+        assert_eq!(physical_from_cores(&cores), Some(4));
+        // no SMT
+        assert_eq!(physical_from_cores(&[(0, 0), (0, 1)]), Some(2));
+        assert_eq!(physical_from_cores(&[]), None);
+    }
+
+    #[test]
+    fn test_physical_le_logical() {
+        let online = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+        assert!(online >= 1);
+
+        match super::get_num_physical_cpus_probe() {
+            Some(physical) => assert!(physical >= 1 && physical <= online as usize),
+            // a zone without `/dev/kstat`, where `get_physical()` is `get()`
+            None => assert!(super::cpu_cores().map_or(true, |cores| cores.is_empty())),
+        }
+    }
+}
@@ -0,0 +1,512 @@
+use std::mem;
+use std::ptr;
+
+#[allow(non_upper_case_globals)]
+const RelationProcessorCore: u32 = 0;
+#[allow(non_upper_case_globals)]
+const RelationProcessorPackage: u32 = 3;
+#[allow(non_upper_case_globals)]
+const RelationAll: u32 = 0xffff;
+
+const ALL_PROCESSOR_GROUPS: u16 = 0xffff;
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+struct SYSTEM_LOGICAL_PROCESSOR_INFORMATION {
+    mask: usize,
+    relationship: u32,
+    _unused: [u64; 2]
+}
+
+#[repr(C)]
+struct SYSTEM_INFO {
+    wProcessorArchitecture: u16,
+    wReserved: u16,
+    dwPageSize: u32,
+    lpMinimumApplicationAddress: *mut u8,
+    lpMaximumApplicationAddress: *mut u8,
+    dwActiveProcessorMask: *mut u8,
+    dwNumberOfProcessors: u32,
+    dwProcessorType: u32,
+    dwAllocationGranularity: u32,
+    wProcessorLevel: u16,
+    wProcessorRevision: u16,
+}
+
+extern "system" {
+    fn GetSystemInfo(lpSystemInfo: *mut SYSTEM_INFO);
+
+    fn GetActiveProcessorCount(group: u16) -> u32;
+
+    #[cfg(test)]
+    fn GetActiveProcessorGroupCount() -> u16;
+
+    fn GetLogicalProcessorInformation(
+        info: *mut SYSTEM_LOGICAL_PROCESSOR_INFORMATION,
+        length: &mut u32
+    ) -> u32;
+
+    fn GetLogicalProcessorInformationEx(
+        relationship: u32,
+        info: *mut u8,
+        length: &mut u32
+    ) -> u32;
+}
+
+/// A `RelationProcessorCore` record of `GetLogicalProcessorInformationEx`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ProcessorCore {
+    /// Higher classes are faster cores. On a Snapdragon laptop the Prime,
+    /// Gold and Silver cores report 2, 1 and 0; non-hybrid parts report 0.
+    efficiency_class: u8,
+    /// Logical processors of this core, summed over all of its groups.
+    logical: usize,
+}
+
+// Offsets into SYSTEM_LOGICAL_PROCESSOR_INFORMATION_EX and its
+// PROCESSOR_RELATIONSHIP payload. They don't depend on the architecture,
+// only the size of the GROUP_AFFINITY mask does.
+const EX_HEADER_SIZE: usize = 8;
+const EX_EFFICIENCY_CLASS: usize = 9;
+const EX_GROUP_COUNT: usize = 30;
+const EX_GROUP_MASK: usize = 32;
+
+pub fn try_num_physical_cpus() -> Option<usize> {
+    get_num_physical_cpus_ex().or_else(get_num_physical_cpus_windows)
+}
+
+pub fn get_num_physical_cpus_with_source() -> (usize, ::Source) {
+    match try_num_physical_cpus() {
+        Some(num) => (num, ::Source::ProcessorInformation),
+        None => (get_num_cpus(), ::Source::Logical),
+    }
+}
+
+pub fn get_num_physical_cpus() -> usize {
+    match try_num_physical_cpus() {
+        Some(num) => num,
+        None => get_num_cpus()
+    }
+}
+
+// GetLogicalProcessorInformationEx is available since Windows 7 and is the
+// only one reporting EfficiencyClass, which is needed to tell apart the
+// tiers of hybrid parts such as the Snapdragon X (Prime/Gold/Silver) or
+// Intel's P/E cores.
+//
+// Each `RelationProcessorCore` record is one physical core, so they're counted
+// rather than the bits of their masks, which are its hyperthreads.
+fn get_num_physical_cpus_ex() -> Option<usize> {
+    match processor_cores() {
+        Some(ref cores) if !cores.is_empty() => Some(cores.len()),
+        _ => None,
+    }
+}
+
+pub fn get_hybrid_counts() -> Option<(usize, usize)> {
+    match processor_cores() {
+        Some(cores) => hybrid_counts(&cores),
+        None => None,
+    }
+}
+
+// The cores of the lowest efficiency class are the efficiency cores, any
+// higher class (Gold and Prime alike) counts as performance ones.
+fn hybrid_counts(cores: &[ProcessorCore]) -> Option<(usize, usize)> {
+    let lowest = cores.iter().map(|core| core.efficiency_class).min();
+    let (efficiency, performance): (Vec<&ProcessorCore>, Vec<&ProcessorCore>) = cores
+        .iter()
+        .partition(|core| Some(core.efficiency_class) == lowest);
+
+    if performance.is_empty() {
+        return None;
+    }
+
+    let logical = |cores: Vec<&ProcessorCore>| cores.iter().map(|core| core.logical).sum();
+    Some((logical(performance), logical(efficiency)))
+}
+
+// Both kinds of records come from a single call, rather than one for each.
+pub fn get_topology() -> ::CpuTopology {
+    let buf = processor_information(RelationAll);
+    let cores = buf.as_ref().and_then(|buf| parse_processor_cores(buf));
+    let packages = buf.as_ref().and_then(|buf| count_records(buf, RelationProcessorPackage));
+
+    ::CpuTopology {
+        sockets: match packages {
+            Some(packages) if packages > 0 => packages,
+            _ => 1,
+        },
+        physical_cores: match cores {
+            Some(ref cores) if !cores.is_empty() => cores.len(),
+            _ => get_num_physical_cpus(),
+        },
+        logical_cores: get_num_cpus(),
+    }
+}
+
+fn processor_cores() -> Option<Vec<ProcessorCore>> {
+    match processor_information(RelationProcessorCore) {
+        Some(buf) => parse_processor_cores(&buf),
+        None => None,
+    }
+}
+
+/// The records of `GetLogicalProcessorInformationEx` for `relationship`.
+fn processor_information(relationship: u32) -> Option<Vec<u8>> {
+    // The required size of the buffer, in bytes.
+    let mut needed_size = 0;
+
+    unsafe {
+        GetLogicalProcessorInformationEx(relationship, ptr::null_mut(), &mut needed_size);
+    }
+
+    if needed_size == 0 {
+        return None;
+    }
+
+    let mut buf = vec![0u8; needed_size as usize];
+
+    let result = unsafe {
+        GetLogicalProcessorInformationEx(relationship, buf[..].as_mut_ptr(), &mut needed_size)
+    };
+
+    // Failed for any reason.
+    if result == 0 {
+        return None;
+    }
+
+    buf.truncate(needed_size as usize);
+    Some(buf)
+}
+
+/// Walks the variable sized records returned by
+/// `GetLogicalProcessorInformationEx(RelationProcessorCore, ..)`.
+///
+/// The buffer is read byte by byte so that it doesn't need to be aligned.
+/// Returns `None` if a record doesn't fit in the buffer.
+fn parse_processor_cores(buf: &[u8]) -> Option<Vec<ProcessorCore>> {
+    // GROUP_AFFINITY is a KAFFINITY (pointer sized) mask, the group number
+    // and three reserved u16s.
+    let mask_size = mem::size_of::<usize>();
+    let group_affinity_size = mask_size + 8;
+
+    let records = match split_records(buf) {
+        Some(records) => records,
+        None => return None,
+    };
+    let mut cores = Vec::new();
+
+    for (relationship, record) in records {
+        // Only interested in processor cores, the other records may have been
+        // asked for along with them.
+        if relationship != RelationProcessorCore {
+            continue;
+        }
+
+        if record.len() < EX_GROUP_MASK {
+            return None;
+        }
+
+        let group_count = read_le(&record[EX_GROUP_COUNT..EX_GROUP_MASK]);
+        let masks_end = EX_GROUP_MASK + group_count * group_affinity_size;
+
+        if masks_end > record.len() {
+            return None;
+        }
+
+        let logical = record[EX_GROUP_MASK..masks_end]
+            .chunks(group_affinity_size)
+            .map(|group| read_le(&group[..mask_size]).count_ones() as usize)
+            .sum();
+
+        cores.push(ProcessorCore {
+            efficiency_class: record[EX_EFFICIENCY_CLASS],
+            logical: logical,
+        });
+    }
+
+    Some(cores)
+}
+
+/// Counts the records of `relationship`, such as the packages, whose payload
+/// isn't needed.
+fn count_records(buf: &[u8], relationship: u32) -> Option<usize> {
+    split_records(buf).map(|records| {
+        records.iter().filter(|&&(record, _)| record == relationship).count()
+    })
+}
+
+/// Splits the variable sized records of `GetLogicalProcessorInformationEx` into
+/// their relationship and bytes, header included.
+///
+/// Returns `None` if a record doesn't fit in the buffer.
+fn split_records(buf: &[u8]) -> Option<Vec<(u32, &[u8])>> {
+    let mut records = Vec::new();
+    let mut rest = buf;
+
+    while !rest.is_empty() {
+        if rest.len() < EX_HEADER_SIZE {
+            return None;
+        }
+
+        let relationship = read_le(&rest[0..4]) as u32;
+        let size = read_le(&rest[4..8]);
+
+        if size < EX_HEADER_SIZE || size > rest.len() {
+            return None;
+        }
+
+        records.push((relationship, &rest[..size]));
+        rest = &rest[size..];
+    }
+
+    Some(records)
+}
+
+fn read_le(bytes: &[u8]) -> usize {
+    bytes.iter().rev().fold(0, |acc, &b| (acc << 8) | b as usize)
+}
+
+fn get_num_physical_cpus_windows() -> Option<usize> {
+    // Inspired by https://msdn.microsoft.com/en-us/library/ms683194
+
+    // First we need to determine how much space to reserve.
+
+    // The required size of the buffer, in bytes.
+    let mut needed_size = 0;
+
+    unsafe {
+        GetLogicalProcessorInformation(ptr::null_mut(), &mut needed_size);
+    }
+
+    let struct_size = mem::size_of::<SYSTEM_LOGICAL_PROCESSOR_INFORMATION>() as u32;
+
+    // Could be 0, or some other bogus size.
+    if needed_size == 0 || needed_size < struct_size || needed_size % struct_size != 0 {
+        return None;
+    }
+
+    let count = needed_size / struct_size;
+
+    // Allocate some memory where we will store the processor info.
+    let mut buf = Vec::with_capacity(count as usize);
+
+    let result;
+
+    unsafe {
+        result = GetLogicalProcessorInformation(buf.as_mut_ptr(), &mut needed_size);
+    }
+
+    // Failed for any reason.
+    if result == 0 {
+        return None;
+    }
+
+    let count = needed_size / struct_size;
+
+    unsafe {
+        buf.set_len(count as usize);
+    }
+
+    let phys_proc_count = buf.iter()
+        // Only interested in processor packages (physical processors.)
+        .filter(|proc_info| proc_info.relationship == RelationProcessorCore)
+        .count();
+
+    if phys_proc_count == 0 {
+        None
+    } else {
+        Some(phys_proc_count)
+    }
+}
+
+// Unlike GetSystemInfo, this counts the processors of every group, rather
+// than at most the 64 of the current one.
+pub fn get_num_cpus() -> usize {
+    match unsafe { GetActiveProcessorCount(ALL_PROCESSOR_GROUPS) } {
+        0 => get_num_cpus_system_info(),
+        cpus => cpus as usize,
+    }
+}
+
+// Only counts the processors of the calling thread's processor group, which
+// holds at most 64 of them, for when GetActiveProcessorCount fails.
+fn get_num_cpus_system_info() -> usize {
+    unsafe {
+        let mut sysinfo: SYSTEM_INFO = mem::zeroed();
+        GetSystemInfo(&mut sysinfo);
+        sysinfo.dwNumberOfProcessors as usize
+    }
+}
+
+// The active processors of every group are already what's installed.
+pub fn get_num_hardware_cpus() -> usize {
+    get_num_cpus()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem;
+    use super::{
+        count_records, get_num_cpus, get_num_cpus_system_info, hybrid_counts,
+        parse_processor_cores, GetActiveProcessorCount, GetActiveProcessorGroupCount,
+        ProcessorCore, RelationProcessorCore, RelationProcessorPackage,
+    };
+
+    #[allow(non_upper_case_globals)]
+    const RelationCache: u32 = 2;
+
+    fn push_le(buf: &mut Vec<u8>, value: usize, len: usize) {
+        for i in 0..len {
+            buf.push((value >> (8 * i)) as u8);
+        }
+    }
+
+    // Builds a record the way Windows lays it out, with one GROUP_AFFINITY
+    // per (group, mask).
+    fn record(relationship: u32, efficiency_class: u8, groups: &[(u16, usize)]) -> Vec<u8> {
+        let mask_size = mem::size_of::<usize>();
+        let size = 32 + groups.len() * (mask_size + 8);
+
+        let mut buf = Vec::new();
+        push_le(&mut buf, relationship as usize, 4);
+        push_le(&mut buf, size, 4);
+        // Flags, LTP_PC_SMT if there's more than one thread.
+        buf.push(0);
+        buf.push(efficiency_class);
+        buf.extend_from_slice(&[0; 20]);
+        push_le(&mut buf, groups.len(), 2);
+        for &(group, mask) in groups {
+            push_le(&mut buf, mask, mask_size);
+            push_le(&mut buf, group as usize, 2);
+            buf.extend_from_slice(&[0; 6]);
+        }
+        assert_eq!(buf.len(), size);
+        buf
+    }
+
+    #[test]
+    fn test_parse_three_tier() {
+        // A Snapdragon-like layout: 1 Prime, 3 Gold, 4 Silver cores. The cache
+        // record in the middle must be skipped.
+        let mut buf = record(RelationProcessorCore, 2, &[(0, 1 << 7)]);
+        for cpu in 4..7 {
+            buf.extend(record(RelationProcessorCore, 1, &[(0, 1 << cpu)]));
+        }
+        buf.extend(record(RelationCache, 0, &[(0, 0xff)]));
+        for cpu in 0..4 {
+            buf.extend(record(RelationProcessorCore, 0, &[(0, 1 << cpu)]));
+        }
+
+        let cores = parse_processor_cores(&buf).unwrap();
+        assert_eq!(cores.len(), 8);
+
+        let class = |c: u8| cores.iter().filter(|core| core.efficiency_class == c).count();
+        assert_eq!((class(2), class(1), class(0)), (1, 3, 4));
+        assert!(cores.iter().all(|core| core.logical == 1));
+
+        assert_eq!(hybrid_counts(&cores), Some((4, 4)));
+    }
+
+    #[test]
+    fn test_parse_smt_and_groups() {
+        let mut buf = record(RelationProcessorCore, 1, &[(0, 0b11)]);
+        buf.extend(record(RelationProcessorCore, 0, &[(0, 1 << 2), (1, 1)]));
+
+        assert_eq!(parse_processor_cores(&buf), Some(vec![
+            ProcessorCore { efficiency_class: 1, logical: 2 },
+            ProcessorCore { efficiency_class: 0, logical: 2 },
+        ]));
+    }
+
+    #[test]
+    fn test_parse_hyperthreaded() {
+        // 2 groups of 4 cores with 2 threads each
+        let mut buf = Vec::new();
+        for group in 0..2 {
+            for core in 0..4 {
+                buf.extend(record(RelationProcessorCore, 0, &[(group, 0b11 << (2 * core))]));
+            }
+        }
+
+        let cores = parse_processor_cores(&buf).unwrap();
+        let logical: usize = cores.iter().map(|core| core.logical).sum();
+        assert_eq!(cores.len(), 8);
+        assert_eq!(logical, 2 * cores.len());
+    }
+
+    #[test]
+    fn test_hybrid_counts() {
+        let core = |efficiency_class, logical| ProcessorCore {
+            efficiency_class: efficiency_class,
+            logical: logical,
+        };
+
+        // 6 P-cores with Hyper-Threading, 8 E-cores without
+        let mut cores = vec![core(1, 2); 6];
+        cores.extend(vec![core(0, 1); 8]);
+        assert_eq!(hybrid_counts(&cores), Some((12, 8)));
+
+        // not hybrid
+        assert_eq!(hybrid_counts(&[core(0, 2), core(0, 2)]), None);
+        assert_eq!(hybrid_counts(&[]), None);
+    }
+
+    #[test]
+    fn test_parse_truncated() {
+        let buf = record(RelationProcessorCore, 0, &[(0, 1)]);
+
+        assert_eq!(parse_processor_cores(&[]), Some(vec![]));
+        assert_eq!(parse_processor_cores(&buf[..buf.len() - 1]), None);
+        assert_eq!(parse_processor_cores(&buf[..4]), None);
+    }
+
+    #[test]
+    fn test_parse_packages() {
+        // 2 packages of 2 cores with 2 threads each, as asked with RelationAll
+        let mut buf = Vec::new();
+        for package in 0..2 {
+            buf.extend(record(RelationProcessorPackage, 0, &[(0, 0b1111 << (4 * package))]));
+            for core in 0..2 {
+                let mask = 0b11 << (4 * package + 2 * core);
+                buf.extend(record(RelationProcessorCore, 0, &[(0, mask)]));
+            }
+        }
+        buf.extend(record(RelationCache, 0, &[(0, 0xff)]));
+
+        assert_eq!(count_records(&buf, RelationProcessorPackage), Some(2));
+        assert_eq!(parse_processor_cores(&buf).map(|cores| cores.len()), Some(4));
+        assert_eq!(count_records(&buf[..buf.len() - 1], RelationProcessorPackage), None);
+    }
+
+    #[test]
+    fn test_topology() {
+        let topology = super::get_topology();
+
+        assert!(topology.sockets >= 1);
+        assert!(topology.physical_cores <= topology.logical_cores);
+        assert_eq!(topology.logical_cores, get_num_cpus());
+    }
+
+    #[test]
+    fn test_physical_le_logical() {
+        let cores = super::processor_cores().unwrap();
+        let logical: usize = cores.iter().map(|core| core.logical).sum();
+
+        assert!(!cores.is_empty());
+        assert!(super::get_num_physical_cpus() <= logical);
+    }
+
+    #[test]
+    fn test_num_cpus_all_groups() {
+        let groups = unsafe { GetActiveProcessorGroupCount() };
+        let cpus: usize = (0..groups)
+            .map(|group| unsafe { GetActiveProcessorCount(group) } as usize)
+            .sum();
+
+        assert_eq!(get_num_cpus(), cpus);
+        // only the current group, which is all of them below 64 processors
+        assert!(get_num_cpus_system_info() <= cpus);
+    }
+}